@@ -0,0 +1,563 @@
+//! Error types for every fallible operation in tomochan.
+//!
+//! Each area of the crate has its own error enum, and [`Error`] wraps all of
+//! them for functions that touch several areas (such as opening a container,
+//! which reads the container header, the index and the store).
+
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+use crate::format::container::Role;
+
+#[derive(Debug)]
+pub enum Error {
+    Container(ContainerError),
+    Index(IndexError),
+    Store(StoreError),
+    Schema(SchemaError),
+    Convert(ConvertError),
+    Export(ExportError),
+    Update(UpdateError),
+    Patch(PatchError),
+    Signature(SignatureError),
+    Config(ConfigError),
+}
+
+/// Errors reading, validating or writing the container wrapper of a file.
+#[derive(Debug)]
+pub enum ContainerError {
+    Io(io::Error),
+    /// File does not begin with the `TOMOCHAN:XXXX:` magic.
+    NotTomochan,
+    InvalidHeaderVersion,
+    UnsupportedHeaderVersion(u16),
+    Header(serde_json::Error),
+    RoleMismatch { expected: Role, found: Role },
+    RoleVersionTooNew { supported: u64, required: u64 },
+    /// File was written in an older role-specific format that can no longer
+    /// be read.
+    RoleVersionTooOld { minimum: u64, found: u64 },
+    /// File is a development version, and dev versions are not allowed.
+    DevVersion,
+    PayloadLengthMismatch { expected: u64, found: u64 },
+    PayloadHashMismatch,
+    /// Role-specific payload could not be decoded.
+    Decode(bincode::error::DecodeError),
+}
+
+/// Errors building or reading the FST index.
+#[derive(Debug)]
+pub enum IndexError {
+    Io(io::Error),
+    Fst(fst::Error),
+    MalformedKey,
+    InvalidKind(u8),
+    InvalidUtf8(std::str::Utf8Error),
+    /// Index does not fit in the address space of this platform.
+    TooLarge,
+}
+
+/// Errors writing to or reading from the compressed store.
+#[derive(Debug)]
+pub enum StoreError {
+    Io(io::Error),
+    Zstd(zeekstd::Error),
+    Encode(bincode::error::EncodeError),
+    Decode(bincode::error::DecodeError),
+    /// An offset points outside of the raw region, or at an item that
+    /// continues past its end.
+    InvalidRawOffset(u64),
+    /// An offset points past the end of the store.
+    InvalidOffset(u64),
+    /// The compression level is outside of what zstd supports.
+    InvalidCompressionLevel(i32),
+    /// The index could not be read while looking up the entries of a key.
+    Index(IndexError),
+}
+
+/// Errors parsing yomitan JSON data into schema types.
+#[derive(Debug)]
+pub enum SchemaError {
+    InvalidField(&'static str),
+    MissingField(&'static str),
+    ExpectedArray(&'static str),
+    ExpectedObject(&'static str),
+    WrongLength { kind: &'static str, expected: usize, found: usize },
+    UnknownDefinitionType(String),
+    UnknownMode(String),
+    UnsupportedFormat(u8),
+}
+
+/// Errors loading or saving small user configuration files, such as the
+/// dictionary collection configuration.
+#[derive(Debug)]
+pub enum ConfigError {
+    Io { path: PathBuf, source: io::Error },
+    Json { path: PathBuf, source: serde_json::Error },
+    /// The same dictionary file was added to a collection twice.
+    DuplicateDictionary(PathBuf),
+}
+
+/// Errors converting source data into a tomochan container.
+#[derive(Debug)]
+pub enum ConvertError {
+    Io { path: PathBuf, source: io::Error },
+    Json { path: PathBuf, source: serde_json::Error },
+    Zip { path: PathBuf, source: zip::result::ZipError },
+    /// A single entry of a yomitan bank file failed to parse. `row` is the
+    /// index of the entry within the bank array.
+    Bank { file: PathBuf, bank: &'static str, row: usize, source: SchemaError },
+    /// `index.json` could not be parsed.
+    Metadata { path: PathBuf, source: SchemaError },
+    /// Container metadata was not given, and the source has no `index.json`
+    /// to take it from.
+    MissingMetadata(&'static str),
+    InvalidPath(PathBuf),
+    /// An archive entry is larger than the importer is willing to read.
+    EntryTooLarge(PathBuf),
+    TooManyTags,
+    Encode(bincode::error::EncodeError),
+    Store(StoreError),
+    Index(IndexError),
+    Container(ContainerError),
+}
+
+/// Errors writing a dictionary back out as a yomitan archive.
+#[derive(Debug)]
+pub enum ExportError {
+    Io(io::Error),
+    Zip(zip::result::ZipError),
+    Json(serde_json::Error),
+    Store(StoreError),
+}
+
+/// Errors checking for or applying dictionary updates.
+#[derive(Debug)]
+pub enum UpdateError {
+    Io { path: PathBuf, source: io::Error },
+    /// The transport failed to fetch `url`.
+    Transport { url: String, message: String },
+    Json { url: String, source: serde_json::Error },
+    /// The remote `index.json` is not a valid dictionary index.
+    Index { url: String, source: SchemaError },
+    /// Signatures are required, which converted updates never have.
+    SignatureRequired,
+    /// The converted update is not a newer revision of the installed
+    /// dictionary.
+    UnexpectedUpdate { name: String, revision: u64 },
+    Convert(ConvertError),
+    Container(ContainerError),
+}
+
+/// Errors creating or applying patches between container revisions.
+#[derive(Debug)]
+pub enum PatchError {
+    Io { path: PathBuf, source: io::Error },
+    /// Patches can only be made between revisions of the same container.
+    NameMismatch { base: String, target: String },
+    /// The base container is not the one the patch was made from.
+    WrongBase { name: String, revision: u64 },
+    /// The patch refers to data outside of the base container.
+    InvalidCopy,
+    /// The reconstructed container is not the one the patch was made for.
+    TargetMismatch,
+    Encode(bincode::error::EncodeError),
+}
+
+/// Errors signing containers or checking their signatures.
+#[derive(Debug)]
+pub enum SignatureError {
+    Io { path: PathBuf, source: io::Error },
+    /// No randomness was available to generate a key.
+    Random(getrandom::Error),
+    Unsigned,
+    /// Signed by a key that is not trusted. Holds the public key.
+    UntrustedKey(String),
+    /// A key is not a base64 encoded Ed25519 key.
+    InvalidKey,
+    /// The signature does not match the container.
+    Invalid,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Container(e) => e.fmt(f),
+            Error::Index(e) => e.fmt(f),
+            Error::Store(e) => e.fmt(f),
+            Error::Schema(e) => e.fmt(f),
+            Error::Convert(e) => e.fmt(f),
+            Error::Export(e) => e.fmt(f),
+            Error::Update(e) => e.fmt(f),
+            Error::Patch(e) => e.fmt(f),
+            Error::Signature(e) => e.fmt(f),
+            Error::Config(e) => e.fmt(f),
+        }
+    }
+}
+
+impl fmt::Display for ContainerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ContainerError::Io(e) => write!(f, "failed to read container: {}", e),
+            ContainerError::NotTomochan => write!(f, "not recognized as a tomochan file"),
+            ContainerError::InvalidHeaderVersion => write!(f, "invalid version in header"),
+            ContainerError::UnsupportedHeaderVersion(v) => write!(f, "incompatible header version {}", v),
+            ContainerError::Header(e) => write!(f, "invalid container header: {}", e),
+            ContainerError::RoleMismatch { expected, found } => {
+                write!(f, "container role is different than expected: expected {}, found {}", expected, found)
+            }
+            ContainerError::RoleVersionTooNew { supported, required } => {
+                write!(f, "container role format too new: requires version {}, supported {}", required, supported)
+            }
+            ContainerError::RoleVersionTooOld { minimum, found } => {
+                write!(f, "container role format too old: version {}, requires at least {}", found, minimum)
+            }
+            ContainerError::DevVersion => write!(f, "package is a development version"),
+            ContainerError::PayloadLengthMismatch { expected, found } => {
+                write!(f, "payload length mismatch: expected {}, found {}", expected, found)
+            }
+            ContainerError::PayloadHashMismatch => write!(f, "SHA256 hash of payload does not match header"),
+            ContainerError::Decode(e) => write!(f, "failed to decode payload: {}", e),
+        }
+    }
+}
+
+impl fmt::Display for IndexError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IndexError::Io(e) => write!(f, "failed to read index: {}", e),
+            IndexError::Fst(e) => write!(f, "invalid index: {}", e),
+            IndexError::MalformedKey => write!(f, "malformed index key"),
+            IndexError::InvalidKind(b) => write!(f, "invalid index key kind {:#04x}", b),
+            IndexError::InvalidUtf8(e) => write!(f, "invalid utf8 index key: {}", e),
+            IndexError::TooLarge => write!(f, "dictionary file too large for 32 bit platform"),
+        }
+    }
+}
+
+impl fmt::Display for StoreError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StoreError::Io(e) => write!(f, "store io error: {}", e),
+            StoreError::Zstd(e) => write!(f, "store compression error: {}", e),
+            StoreError::Encode(e) => write!(f, "bincode serialization failed: {}", e),
+            StoreError::Decode(e) => write!(f, "bincode deserialization failed: {}", e),
+            StoreError::InvalidRawOffset(offset) => write!(f, "invalid raw region offset {}", offset),
+            StoreError::InvalidOffset(offset) => write!(f, "invalid store offset {}", offset),
+            StoreError::InvalidCompressionLevel(level) => {
+                write!(f, "invalid compression level {}, must be from 1 to {}", level, zstd_safe::max_c_level())
+            }
+            StoreError::Index(e) => write!(f, "index lookup failed: {}", e),
+        }
+    }
+}
+
+impl fmt::Display for SchemaError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SchemaError::InvalidField(field) => write!(f, "invalid {}", field),
+            SchemaError::MissingField(field) => write!(f, "missing {}", field),
+            SchemaError::ExpectedArray(field) => write!(f, "{} must be an array", field),
+            SchemaError::ExpectedObject(field) => write!(f, "{} must be an object", field),
+            SchemaError::WrongLength { kind, expected, found } => {
+                write!(f, "{} array must have exactly {} elements, found {}", kind, expected, found)
+            }
+            SchemaError::UnknownDefinitionType(t) => write!(f, "unknown definition type: {}", t),
+            SchemaError::UnknownMode(mode) => write!(f, "unknown mode: {}", mode),
+            SchemaError::UnsupportedFormat(v) => write!(f, "unsupported format value: {}", v),
+        }
+    }
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Io { path, source } => write!(f, "failed to access {}: {}", path.display(), source),
+            ConfigError::Json { path, source } => write!(f, "invalid config {}: {}", path.display(), source),
+            ConfigError::DuplicateDictionary(path) => write!(f, "dictionary {} is already in the collection", path.display()),
+        }
+    }
+}
+
+impl fmt::Display for ConvertError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConvertError::Io { path, source } => write!(f, "failed to access {}: {}", path.display(), source),
+            ConvertError::Json { path, source } => write!(f, "failed to parse {}: {}", path.display(), source),
+            ConvertError::Zip { path, source } => write!(f, "failed to read archive {}: {}", path.display(), source),
+            ConvertError::Bank { file, bank, row, source } => {
+                write!(f, "{} bank {} row {}: {}", bank, file.display(), row, source)
+            }
+            ConvertError::Metadata { path, source } => write!(f, "invalid dictionary index {}: {}", path.display(), source),
+            ConvertError::MissingMetadata(field) => write!(f, "no {} given and no index.json to take it from", field),
+            ConvertError::InvalidPath(path) => write!(f, "invalid path: {}", path.display()),
+            ConvertError::EntryTooLarge(path) => write!(f, "archive entry too large: {}", path.display()),
+            ConvertError::TooManyTags => write!(f, "too many tags"),
+            ConvertError::Encode(e) => write!(f, "failed to encode package: {}", e),
+            ConvertError::Store(e) => e.fmt(f),
+            ConvertError::Index(e) => e.fmt(f),
+            ConvertError::Container(e) => e.fmt(f),
+        }
+    }
+}
+
+impl fmt::Display for ExportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExportError::Io(e) => write!(f, "failed to write archive: {}", e),
+            ExportError::Zip(e) => write!(f, "failed to write archive: {}", e),
+            ExportError::Json(e) => write!(f, "failed to serialize bank: {}", e),
+            ExportError::Store(e) => e.fmt(f),
+        }
+    }
+}
+
+impl fmt::Display for UpdateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UpdateError::Io { path, source } => write!(f, "failed to access {}: {}", path.display(), source),
+            UpdateError::Transport { url, message } => write!(f, "failed to fetch {}: {}", url, message),
+            UpdateError::Json { url, source } => write!(f, "failed to parse {}: {}", url, source),
+            UpdateError::Index { url, source } => write!(f, "invalid dictionary index {}: {}", url, source),
+            UpdateError::SignatureRequired => write!(f, "updates are unsigned, but signatures are required"),
+            UpdateError::UnexpectedUpdate { name, revision } => {
+                write!(f, "update to {} revision {} is not a newer revision of the installed dictionary", name, revision)
+            }
+            UpdateError::Convert(e) => e.fmt(f),
+            UpdateError::Container(e) => e.fmt(f),
+        }
+    }
+}
+
+impl fmt::Display for PatchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PatchError::Io { path, source } => write!(f, "failed to access {}: {}", path.display(), source),
+            PatchError::NameMismatch { base, target } => {
+                write!(f, "cannot patch between different containers: {} and {}", base, target)
+            }
+            PatchError::WrongBase { name, revision } => {
+                write!(f, "patch is for {} revision {}", name, revision)
+            }
+            PatchError::InvalidCopy => write!(f, "patch reads outside of the base container"),
+            PatchError::TargetMismatch => write!(f, "patched container does not match the patch"),
+            PatchError::Encode(e) => write!(f, "failed to encode patch: {}", e),
+        }
+    }
+}
+
+impl fmt::Display for SignatureError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SignatureError::Io { path, source } => write!(f, "failed to access {}: {}", path.display(), source),
+            SignatureError::Random(e) => write!(f, "failed to generate key: {}", e),
+            SignatureError::Unsigned => write!(f, "container is not signed"),
+            SignatureError::UntrustedKey(key) => write!(f, "container is signed by untrusted key {}", key),
+            SignatureError::InvalidKey => write!(f, "invalid Ed25519 key"),
+            SignatureError::Invalid => write!(f, "container signature is not valid"),
+        }
+    }
+}
+
+// Wrapper variants are transparent: they display as the inner error, so
+// they skip it in the chain and return its source instead. Otherwise every
+// message would show up twice when the chain is printed.
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Container(e) => e.source(),
+            Error::Index(e) => e.source(),
+            Error::Store(e) => e.source(),
+            Error::Schema(e) => e.source(),
+            Error::Convert(e) => e.source(),
+            Error::Export(e) => e.source(),
+            Error::Update(e) => e.source(),
+            Error::Patch(e) => e.source(),
+            Error::Signature(e) => e.source(),
+            Error::Config(e) => e.source(),
+        }
+    }
+}
+
+impl std::error::Error for ContainerError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ContainerError::Io(e) => Some(e),
+            ContainerError::Header(e) => Some(e),
+            ContainerError::Decode(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl std::error::Error for IndexError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            IndexError::Io(e) => Some(e),
+            IndexError::Fst(e) => Some(e),
+            IndexError::InvalidUtf8(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl std::error::Error for StoreError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            StoreError::Io(e) => Some(e),
+            StoreError::Zstd(e) => Some(e),
+            StoreError::Encode(e) => Some(e),
+            StoreError::Decode(e) => Some(e),
+            StoreError::Index(e) => Some(e),
+            StoreError::InvalidRawOffset(_) | StoreError::InvalidOffset(_) | StoreError::InvalidCompressionLevel(_) => None,
+        }
+    }
+}
+
+impl std::error::Error for SchemaError {}
+
+impl std::error::Error for ConfigError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ConfigError::Io { source, .. } => Some(source),
+            ConfigError::Json { source, .. } => Some(source),
+            ConfigError::DuplicateDictionary(_) => None,
+        }
+    }
+}
+
+impl std::error::Error for ConvertError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ConvertError::Io { source, .. } => Some(source),
+            ConvertError::Json { source, .. } => Some(source),
+            ConvertError::Zip { source, .. } => Some(source),
+            ConvertError::Bank { source, .. } => Some(source),
+            ConvertError::Metadata { source, .. } => Some(source),
+            ConvertError::Encode(e) => Some(e),
+            ConvertError::Store(e) => e.source(),
+            ConvertError::Index(e) => e.source(),
+            ConvertError::Container(e) => e.source(),
+            _ => None,
+        }
+    }
+}
+
+impl std::error::Error for ExportError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ExportError::Io(e) => Some(e),
+            ExportError::Zip(e) => Some(e),
+            ExportError::Json(e) => Some(e),
+            ExportError::Store(e) => e.source(),
+        }
+    }
+}
+
+impl std::error::Error for UpdateError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            UpdateError::Io { source, .. } => Some(source),
+            UpdateError::Json { source, .. } => Some(source),
+            UpdateError::Index { source, .. } => Some(source),
+            UpdateError::Convert(e) => e.source(),
+            UpdateError::Container(e) => e.source(),
+            UpdateError::Transport { .. } | UpdateError::SignatureRequired | UpdateError::UnexpectedUpdate { .. } => None,
+        }
+    }
+}
+
+impl std::error::Error for PatchError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            PatchError::Io { source, .. } => Some(source),
+            PatchError::Encode(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl std::error::Error for SignatureError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SignatureError::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+macro_rules! impl_from {
+    ($from:ty => $to:ident :: $variant:ident) => {
+        impl From<$from> for $to {
+            fn from(e: $from) -> Self {
+                $to::$variant(e)
+            }
+        }
+    };
+}
+
+impl_from!(ContainerError => Error::Container);
+impl_from!(IndexError => Error::Index);
+impl_from!(StoreError => Error::Store);
+impl_from!(SchemaError => Error::Schema);
+impl_from!(ConvertError => Error::Convert);
+impl_from!(ExportError => Error::Export);
+impl_from!(UpdateError => Error::Update);
+impl_from!(PatchError => Error::Patch);
+impl_from!(SignatureError => Error::Signature);
+impl_from!(ConfigError => Error::Config);
+
+impl_from!(io::Error => ContainerError::Io);
+impl_from!(bincode::error::DecodeError => ContainerError::Decode);
+
+impl_from!(fst::Error => IndexError::Fst);
+impl_from!(std::str::Utf8Error => IndexError::InvalidUtf8);
+
+impl_from!(zeekstd::Error => StoreError::Zstd);
+impl_from!(bincode::error::EncodeError => StoreError::Encode);
+impl_from!(bincode::error::DecodeError => StoreError::Decode);
+impl_from!(IndexError => StoreError::Index);
+
+impl_from!(StoreError => ConvertError::Store);
+impl_from!(IndexError => ConvertError::Index);
+impl_from!(ContainerError => ConvertError::Container);
+impl_from!(bincode::error::EncodeError => ConvertError::Encode);
+
+impl_from!(io::Error => ExportError::Io);
+impl_from!(zip::result::ZipError => ExportError::Zip);
+impl_from!(serde_json::Error => ExportError::Json);
+impl_from!(StoreError => ExportError::Store);
+
+impl_from!(ConvertError => UpdateError::Convert);
+impl_from!(ContainerError => UpdateError::Container);
+
+impl_from!(bincode::error::EncodeError => PatchError::Encode);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::error::Error as _;
+
+    fn chain(error: &dyn std::error::Error) -> Vec<String> {
+        let mut messages = vec![error.to_string()];
+        let mut source = error.source();
+        while let Some(e) = source {
+            messages.push(e.to_string());
+            source = e.source();
+        }
+        messages
+    }
+
+    #[test]
+    fn wrappers_are_transparent() {
+        let error: Error = ConvertError::Container(ContainerError::PayloadHashMismatch).into();
+        assert_eq!(chain(&error), ["SHA256 hash of payload does not match header"]);
+
+        let error: Error = ContainerError::Io(io::Error::other("disk on fire")).into();
+        assert_eq!(chain(&error), ["failed to read container: disk on fire", "disk on fire"]);
+
+        let error = UpdateError::Convert(ConvertError::Store(StoreError::InvalidOffset(7)));
+        assert!(error.source().is_none());
+    }
+}
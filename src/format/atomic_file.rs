@@ -0,0 +1,68 @@
+//! Files written next to their destination and renamed over it once
+//! complete, so that readers and crashes see either the old or the new
+//! contents, never a partial file.
+
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+pub(crate) struct AtomicFile {
+    dst: PathBuf,
+    tmp: PathBuf,
+    file: File,
+    committed: bool,
+}
+
+impl AtomicFile {
+    /// Creates `<dst>.tmp`, replacing any left over by an interrupted write.
+    pub fn create(dst: impl AsRef<Path>) -> io::Result<Self> {
+        let dst = dst.as_ref().to_path_buf();
+        let mut tmp = dst.clone().into_os_string();
+        tmp.push(".tmp");
+        let tmp = PathBuf::from(tmp);
+
+        let file = File::create(&tmp)?;
+        Ok(Self { dst, tmp, file, committed: false })
+    }
+
+    pub fn tmp_path(&self) -> &Path {
+        &self.tmp
+    }
+
+    pub fn file(&mut self) -> &mut File {
+        &mut self.file
+    }
+
+    /// Flushes the contents to disk and renames the file over its
+    /// destination. The directory is synced as well, so the rename is not
+    /// lost in a crash either.
+    pub fn commit(mut self) -> io::Result<()> {
+        self.file.sync_all()?;
+        fs::rename(&self.tmp, &self.dst)?;
+        self.committed = true;
+
+        // directories can't be opened as files on every platform
+        #[cfg(unix)]
+        if let Some(parent) = self.dst.parent() {
+            let parent = if parent.as_os_str().is_empty() { Path::new(".") } else { parent };
+            File::open(parent)?.sync_all()?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Like [`fs::write`], but replaces `dst` atomically.
+pub(crate) fn write(dst: impl AsRef<Path>, contents: &[u8]) -> io::Result<()> {
+    let mut file = AtomicFile::create(dst)?;
+    file.file().write_all(contents)?;
+    file.commit()
+}
+
+impl Drop for AtomicFile {
+    fn drop(&mut self) {
+        if !self.committed {
+            let _ = fs::remove_file(&self.tmp);
+        }
+    }
+}
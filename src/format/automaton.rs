@@ -0,0 +1,358 @@
+//! FST automata for searching index keys.
+//!
+//! Index keys are stored as composite keys (kind byte, key, `\0`, 4 byte
+//! repetition index), so automata that describe the key alone have to be
+//! wrapped in [`CompositeKey`] before being used to search the index.
+
+use fst::Automaton;
+
+const REP_LEN: u8 = 4;
+
+/// Matches composite keys of a single kind whose key is matched by `inner`.
+pub struct CompositeKey<A> {
+    kind: u8,
+    inner: A,
+}
+
+impl<A: Automaton> CompositeKey<A> {
+    pub fn new(kind: u8, inner: A) -> Self {
+        Self { kind, inner }
+    }
+}
+
+#[derive(Clone)]
+pub enum CompositeState<S> {
+    Kind,
+    Key(S),
+    /// Number of repetition index bytes read so far.
+    Rep(u8),
+    Dead,
+}
+
+impl<A: Automaton> Automaton for CompositeKey<A> {
+    type State = CompositeState<A::State>;
+
+    fn start(&self) -> Self::State {
+        CompositeState::Kind
+    }
+
+    fn is_match(&self, state: &Self::State) -> bool {
+        matches!(state, CompositeState::Rep(REP_LEN))
+    }
+
+    fn can_match(&self, state: &Self::State) -> bool {
+        match state {
+            CompositeState::Key(inner) => self.inner.can_match(inner),
+            CompositeState::Dead => false,
+            _ => true,
+        }
+    }
+
+    fn accept(&self, state: &Self::State, byte: u8) -> Self::State {
+        match state {
+            CompositeState::Kind if byte == self.kind => CompositeState::Key(self.inner.start()),
+            CompositeState::Key(inner) if byte == 0 => {
+                if self.inner.is_match(inner) {
+                    CompositeState::Rep(0)
+                } else {
+                    CompositeState::Dead
+                }
+            }
+            CompositeState::Key(inner) => CompositeState::Key(self.inner.accept(inner, byte)),
+            CompositeState::Rep(n) if *n < REP_LEN => CompositeState::Rep(n + 1),
+            _ => CompositeState::Dead,
+        }
+    }
+}
+
+/// Matches keys starting with a prefix. Unlike `fst::automaton::StartsWith`
+/// its state is `Clone`, which is needed to search several indexes at once.
+pub struct Prefix<'a> {
+    prefix: &'a [u8],
+}
+
+impl<'a> Prefix<'a> {
+    pub fn new(prefix: &'a str) -> Self {
+        Self { prefix: prefix.as_bytes() }
+    }
+}
+
+impl Automaton for Prefix<'_> {
+    /// Number of prefix bytes matched so far, `None` after a mismatch.
+    type State = Option<usize>;
+
+    fn start(&self) -> Self::State {
+        Some(0)
+    }
+
+    fn is_match(&self, state: &Self::State) -> bool {
+        *state == Some(self.prefix.len())
+    }
+
+    fn can_match(&self, state: &Self::State) -> bool {
+        state.is_some()
+    }
+
+    fn accept(&self, state: &Self::State, byte: u8) -> Self::State {
+        match *state {
+            Some(matched) if matched == self.prefix.len() => Some(matched),
+            Some(matched) if self.prefix[matched] == byte => Some(matched + 1),
+            _ => None,
+        }
+    }
+}
+
+/// Matches keys within a maximum edit distance of a query, counting edits
+/// in characters rather than bytes.
+pub struct Levenshtein {
+    query: Vec<char>,
+    max_distance: u32,
+}
+
+impl Levenshtein {
+    pub fn new(query: &str, max_distance: u32) -> Self {
+        Self {
+            query: query.chars().collect(),
+            max_distance,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct LevenshteinState {
+    /// Edit distances between the key so far and every prefix of the query.
+    row: Vec<u32>,
+    /// Bytes of a character that has not been fully read yet.
+    pending: Vec<u8>,
+}
+
+impl Automaton for Levenshtein {
+    type State = LevenshteinState;
+
+    fn start(&self) -> Self::State {
+        LevenshteinState {
+            row: (0..=self.query.len() as u32).collect(),
+            pending: Vec::new(),
+        }
+    }
+
+    fn is_match(&self, state: &Self::State) -> bool {
+        state.pending.is_empty() && state.row.last().is_some_and(|&d| d <= self.max_distance)
+    }
+
+    fn can_match(&self, state: &Self::State) -> bool {
+        state.row.iter().min().is_some_and(|&d| d <= self.max_distance)
+    }
+
+    fn accept(&self, state: &Self::State, byte: u8) -> Self::State {
+        if state.row.is_empty() {
+            return state.clone();
+        }
+
+        let mut pending = state.pending.clone();
+        pending.push(byte);
+
+        if pending.len() <= continuation_len(pending[0]) as usize {
+            return LevenshteinState { row: state.row.clone(), pending };
+        }
+
+        let Some(c) = std::str::from_utf8(&pending).ok().and_then(|s| s.chars().next()) else {
+            return LevenshteinState { row: Vec::new(), pending: Vec::new() };
+        };
+
+        let mut row = Vec::with_capacity(state.row.len());
+        row.push(state.row[0] + 1);
+        for (i, &q) in self.query.iter().enumerate() {
+            let substitution = state.row[i] + u32::from(q != c);
+            row.push(substitution.min(state.row[i + 1] + 1).min(row[i] + 1));
+        }
+
+        LevenshteinState { row, pending: Vec::new() }
+    }
+}
+
+enum Token {
+    Byte(u8),
+    /// `?`, a single character.
+    AnyChar,
+    /// `*`, any number of characters.
+    AnyString,
+}
+
+/// Glob style pattern, where `*` matches any number of characters and `?`
+/// matches exactly one character.
+pub struct Wildcard {
+    tokens: Vec<Token>,
+}
+
+/// Position in the pattern, and the number of UTF-8 continuation bytes that
+/// still have to be consumed by a `?` before it is reached.
+type Position = (usize, u8);
+
+impl Wildcard {
+    pub fn new(pattern: &str) -> Self {
+        let mut tokens = Vec::new();
+        for c in pattern.chars() {
+            match c {
+                '*' => tokens.push(Token::AnyString),
+                '?' => tokens.push(Token::AnyChar),
+                _ => {
+                    let mut buf = [0u8; 4];
+                    tokens.extend(c.encode_utf8(&mut buf).bytes().map(Token::Byte));
+                }
+            }
+        }
+
+        Self { tokens }
+    }
+
+    fn closure(&self, mut positions: Vec<Position>) -> Vec<Position> {
+        // `*` can match nothing, so whenever one is reached the token after
+        // it is reachable too
+        let mut i = 0;
+        while i < positions.len() {
+            let (token, pending) = positions[i];
+            if pending == 0 && matches!(self.tokens.get(token), Some(Token::AnyString)) {
+                positions.push((token + 1, 0));
+            }
+            i += 1;
+        }
+
+        positions.sort_unstable();
+        positions.dedup();
+        positions
+    }
+}
+
+fn is_continuation(byte: u8) -> bool {
+    byte & 0xC0 == 0x80
+}
+
+fn continuation_len(lead: u8) -> u8 {
+    match lead.leading_ones() {
+        2 => 1,
+        3 => 2,
+        4 => 3,
+        _ => 0,
+    }
+}
+
+impl Automaton for Wildcard {
+    type State = Vec<Position>;
+
+    fn start(&self) -> Self::State {
+        self.closure(vec![(0, 0)])
+    }
+
+    fn is_match(&self, state: &Self::State) -> bool {
+        state.contains(&(self.tokens.len(), 0))
+    }
+
+    fn can_match(&self, state: &Self::State) -> bool {
+        !state.is_empty()
+    }
+
+    fn accept(&self, state: &Self::State, byte: u8) -> Self::State {
+        let mut next = Vec::new();
+        for &(token, pending) in state {
+            if pending > 0 {
+                if is_continuation(byte) {
+                    next.push((token, pending - 1));
+                }
+                continue;
+            }
+
+            match self.tokens.get(token) {
+                Some(Token::Byte(b)) if *b == byte => next.push((token + 1, 0)),
+                Some(Token::AnyChar) if !is_continuation(byte) => {
+                    next.push((token + 1, continuation_len(byte)));
+                }
+                Some(Token::AnyString) => next.push((token, 0)),
+                _ => {}
+            }
+        }
+
+        self.closure(next)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches<A: Automaton>(automaton: &A, key: &[u8]) -> bool {
+        let mut state = automaton.start();
+        for &byte in key {
+            if !automaton.can_match(&state) {
+                return false;
+            }
+            state = automaton.accept(&state, byte);
+        }
+        automaton.is_match(&state)
+    }
+
+    fn composite(kind: u8, key: &str, rep: u32) -> Vec<u8> {
+        let mut composite = vec![kind];
+        composite.extend_from_slice(key.as_bytes());
+        composite.push(0);
+        composite.extend_from_slice(&rep.to_be_bytes());
+        composite
+    }
+
+    #[test]
+    fn wildcard() {
+        let pattern = Wildcard::new("食?る");
+        assert!(matches(&pattern, "食べる".as_bytes()));
+        assert!(!matches(&pattern, "食る".as_bytes()));
+        assert!(!matches(&pattern, "食べべる".as_bytes()));
+
+        let pattern = Wildcard::new("*べ*");
+        assert!(matches(&pattern, "食べる".as_bytes()));
+        assert!(matches(&pattern, "べ".as_bytes()));
+        assert!(!matches(&pattern, "食る".as_bytes()));
+
+        let pattern = Wildcard::new("a*b?");
+        assert!(matches(&pattern, b"abc"));
+        assert!(matches(&pattern, "axxbあ".as_bytes()));
+        assert!(!matches(&pattern, b"ab"));
+    }
+
+    #[test]
+    fn levenshtein_counts_characters() {
+        let query = Levenshtein::new("たべる", 1);
+        assert!(matches(&query, "たべる".as_bytes()));
+        assert!(matches(&query, "たべた".as_bytes()));
+        assert!(matches(&query, "たべれる".as_bytes()));
+        assert!(matches(&query, "べる".as_bytes()));
+        assert!(!matches(&query, "のむ".as_bytes()));
+        assert!(!matches(&query, "たべさせる".as_bytes()));
+
+        let exact = Levenshtein::new("abc", 0);
+        assert!(matches(&exact, b"abc"));
+        assert!(!matches(&exact, b"abd"));
+    }
+
+    #[test]
+    fn prefix() {
+        let prefix = Prefix::new("食べ");
+        assert!(matches(&prefix, "食べる".as_bytes()));
+        assert!(matches(&prefix, "食べ".as_bytes()));
+        assert!(!matches(&prefix, "食".as_bytes()));
+        assert!(!matches(&prefix, "飲む".as_bytes()));
+    }
+
+    #[test]
+    fn composite_key() {
+        let automaton = CompositeKey::new(0, Prefix::new("食"));
+        assert!(matches(&automaton, &composite(0, "食べる", 0)));
+        assert!(matches(&automaton, &composite(0, "食", 7)));
+        assert!(!matches(&automaton, &composite(1, "食べる", 0)));
+        assert!(!matches(&automaton, &composite(0, "飲む", 0)));
+        // key without the full repetition index
+        assert!(!matches(&automaton, &composite(0, "食べる", 0)[..8]));
+
+        let automaton = CompositeKey::new(6, Wildcard::new("?る"));
+        assert!(matches(&automaton, &composite(6, "いる", 1)));
+        assert!(!matches(&automaton, &composite(6, "たべる", 1)));
+    }
+}
@@ -0,0 +1,479 @@
+//! A user configured set of dictionaries that are searched together.
+//!
+//! Every dictionary in a collection has a priority and an enabled flag.
+//! Lookups only consult enabled dictionaries, and results are ordered by
+//! priority, so reordering or disabling dictionaries never requires touching
+//! the dictionary files themselves.
+
+use super::atomic_file;
+use super::dictionary::Dictionary;
+use super::index::{DictionaryIndex, FuzzyMatch, KeyPage, KeyQuery};
+use super::types::{Queryable, QueryKindKey, LookupMode};
+use super::container::{ContainerMeta, open_container_logged};
+use super::frame_cache::FrameCache;
+use super::hashlog::HashLogFile;
+use crate::schema::{Term, Kanji, Tag, TermMeta, KanjiMeta};
+use crate::error::{ConfigError, Error, IndexError, StoreError};
+use serde::{Serialize, Deserialize};
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+
+/// Persisted configuration of a single dictionary in a collection.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CollectionEntryConfig {
+    pub path: String,
+    /// Lower values are searched first.
+    pub priority: i32,
+    pub enabled: bool,
+}
+
+/// Persisted configuration of a [`DictionaryCollection`].
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CollectionConfig {
+    pub dictionaries: Vec<CollectionEntryConfig>,
+    /// Memory budget in bytes of a frame cache shared by the dictionaries,
+    /// no cache if unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub frame_cache_budget: Option<usize>,
+}
+
+impl CollectionConfig {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, ConfigError> {
+        let path = path.as_ref();
+        let json = fs::read_to_string(path)
+            .map_err(|source| ConfigError::Io { path: path.into(), source })?;
+
+        serde_json::from_str(&json)
+            .map_err(|source| ConfigError::Json { path: path.into(), source })
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), ConfigError> {
+        let path = path.as_ref();
+        let json = serde_json::to_vec_pretty(self)
+            .map_err(|source| ConfigError::Json { path: path.into(), source })?;
+
+        // written atomically, a crash while saving must not lose the list of
+        // installed dictionaries
+        atomic_file::write(path, &json)
+            .map_err(|source| ConfigError::Io { path: path.into(), source })
+    }
+}
+
+pub struct CollectionEntry {
+    pub path: String,
+    pub meta: ContainerMeta,
+    pub priority: i32,
+    pub enabled: bool,
+    pub dictionary: Dictionary,
+}
+
+/// A lookup result, tagged with the dictionary it was found in.
+#[derive(Debug, Clone)]
+pub struct CollectionResult<T> {
+    /// Name of the source dictionary, for display. Several dictionaries can
+    /// have the same name (ex. two revisions of one dictionary).
+    pub dictionary: String,
+    /// Path of the source dictionary, which identifies it in the collection.
+    pub path: String,
+    pub priority: i32,
+    pub entry: T,
+}
+
+impl CollectionEntry {
+    fn result<T>(&self, entry: T) -> CollectionResult<T> {
+        CollectionResult {
+            dictionary: self.meta.name.clone(),
+            path: self.path.clone(),
+            priority: self.priority,
+            entry,
+        }
+    }
+}
+
+/// Set of opened dictionaries, kept sorted by priority. Dictionaries with
+/// equal priority keep the order they were added in.
+#[derive(Default)]
+pub struct DictionaryCollection {
+    entries: Vec<CollectionEntry>,
+    frame_cache: Option<Arc<FrameCache>>,
+    hash_log: Option<Arc<HashLogFile>>,
+}
+
+impl DictionaryCollection {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Opens every dictionary listed in `config`.
+    pub fn open(config: &CollectionConfig, verify: bool) -> Result<Self, Error> {
+        Self::open_with_hash_log(config, verify, None)
+    }
+
+    /// Same as [`DictionaryCollection::open`], with a hash log set before
+    /// any dictionary is opened.
+    pub fn open_with_hash_log(config: &CollectionConfig, verify: bool, hash_log: Option<Arc<HashLogFile>>) -> Result<Self, Error> {
+        let mut collection = Self::new();
+        collection.set_hash_log(hash_log);
+        collection.set_frame_cache(config.frame_cache_budget.map(|budget| Arc::new(FrameCache::new(budget))));
+        for entry in &config.dictionaries {
+            collection.add(&entry.path, entry.priority, entry.enabled, verify)?;
+        }
+
+        Ok(collection)
+    }
+
+    pub fn config(&self) -> CollectionConfig {
+        CollectionConfig {
+            dictionaries: self.entries.iter()
+                .map(|entry| CollectionEntryConfig {
+                    path: entry.path.clone(),
+                    priority: entry.priority,
+                    enabled: entry.enabled,
+                })
+                .collect(),
+            frame_cache_budget: self.frame_cache.as_ref().map(|cache| cache.budget()),
+        }
+    }
+
+    pub fn frame_cache(&self) -> Option<&Arc<FrameCache>> {
+        self.frame_cache.as_ref()
+    }
+
+    /// Shares `cache` between every dictionary in the collection, including
+    /// dictionaries added later. `None` turns caching off.
+    pub fn set_frame_cache(&mut self, cache: Option<Arc<FrameCache>>) {
+        for entry in &mut self.entries {
+            entry.dictionary.store.set_cache(cache.clone());
+        }
+        self.frame_cache = cache;
+    }
+
+    pub fn hash_log(&self) -> Option<&Arc<HashLogFile>> {
+        self.hash_log.as_ref()
+    }
+
+    /// Log of verified dictionaries consulted when dictionaries are added or
+    /// reloaded, so unchanged files are not verified again. `None` verifies
+    /// every time.
+    pub fn set_hash_log(&mut self, hash_log: Option<Arc<HashLogFile>>) {
+        self.hash_log = hash_log;
+    }
+
+    pub fn entries(&self) -> &[CollectionEntry] {
+        &self.entries
+    }
+
+    /// Dictionary at the given path, as found in [`CollectionResult::path`].
+    pub fn dictionary(&self, path: &str) -> Option<&Dictionary> {
+        self.entries.iter()
+            .find(|entry| entry.path == path)
+            .map(|entry| &entry.dictionary)
+    }
+
+    /// Opens the dictionary at `path` and adds it to the collection. Each
+    /// path can only be added once.
+    pub fn add(&mut self, path: &str, priority: i32, enabled: bool, verify: bool) -> Result<(), Error> {
+        if self.entries.iter().any(|entry| entry.path == path) {
+            return Err(ConfigError::DuplicateDictionary(path.into()).into());
+        }
+
+        let (header, mut dictionary) = open_container_logged::<Dictionary>(path, verify, self.hash_log.as_deref())?;
+        dictionary.store.set_cache(self.frame_cache.clone());
+
+        self.entries.push(CollectionEntry {
+            path: path.to_string(),
+            meta: header.meta,
+            priority,
+            enabled,
+            dictionary,
+        });
+        self.sort();
+
+        Ok(())
+    }
+
+    /// Reopens the dictionary at `path`, after the file was replaced (ex.
+    /// by an update). Returns false if it is not in the collection.
+    pub fn reload(&mut self, path: &str, verify: bool) -> Result<bool, Error> {
+        let Some(entry) = self.entries.iter_mut().find(|entry| entry.path == path) else {
+            return Ok(false);
+        };
+
+        let (header, mut dictionary) = open_container_logged::<Dictionary>(path, verify, self.hash_log.as_deref())?;
+        dictionary.store.set_cache(self.frame_cache.clone());
+        entry.meta = header.meta;
+        entry.dictionary = dictionary;
+        Ok(true)
+    }
+
+    pub fn remove(&mut self, path: &str) -> Option<CollectionEntry> {
+        let idx = self.entries.iter().position(|entry| entry.path == path)?;
+        Some(self.entries.remove(idx))
+    }
+
+    /// Returns false if no dictionary with the given path is in the collection.
+    pub fn set_priority(&mut self, path: &str, priority: i32) -> bool {
+        let Some(entry) = self.entries.iter_mut().find(|entry| entry.path == path) else {
+            return false;
+        };
+
+        entry.priority = priority;
+        self.sort();
+        true
+    }
+
+    /// Returns false if no dictionary with the given path is in the collection.
+    pub fn set_enabled(&mut self, path: &str, enabled: bool) -> bool {
+        let Some(entry) = self.entries.iter_mut().find(|entry| entry.path == path) else {
+            return false;
+        };
+
+        entry.enabled = enabled;
+        true
+    }
+
+    fn sort(&mut self) {
+        self.entries.sort_by_key(|entry| entry.priority);
+    }
+
+    pub fn enabled(&self) -> impl Iterator<Item = &CollectionEntry> {
+        self.entries.iter().filter(|entry| entry.enabled)
+    }
+
+    /// Finds all entries of type `T` stored under `key` in every enabled
+    /// dictionary, in priority order.
+    pub fn find<T: Queryable + bincode::Decode<()>>(&self, key: &str) -> Result<Vec<CollectionResult<T>>, StoreError> {
+        let mut results = Vec::new();
+        for entry in self.enabled() {
+            for found in entry.dictionary.find::<T>(key)? {
+                results.push(entry.result(found));
+            }
+        }
+
+        Ok(results)
+    }
+
+    pub fn find_terms(&self, term: &str) -> Result<Vec<CollectionResult<Term>>, StoreError> {
+        self.find(term)
+    }
+
+    pub fn find_terms_by(&self, key: &str, mode: LookupMode) -> Result<Vec<CollectionResult<Term>>, StoreError> {
+        let mut results = Vec::new();
+        for entry in self.enabled() {
+            for found in entry.dictionary.find_terms_by(key, mode)? {
+                results.push(entry.result(found));
+            }
+        }
+
+        Ok(results)
+    }
+
+    pub fn find_kanji(&self, character: &str) -> Result<Vec<CollectionResult<Kanji>>, StoreError> {
+        self.find(character)
+    }
+
+    pub fn find_tags(&self, name: &str) -> Result<Vec<CollectionResult<Tag>>, StoreError> {
+        self.find(name)
+    }
+
+    pub fn find_term_meta(&self, term: &str) -> Result<Vec<CollectionResult<TermMeta>>, StoreError> {
+        self.find(term)
+    }
+
+    pub fn find_kanji_meta(&self, character: &str) -> Result<Vec<CollectionResult<KanjiMeta>>, StoreError> {
+        self.find(character)
+    }
+
+    /// Finds keys matching `query` in every enabled dictionary, see
+    /// [`DictionaryIndex::search`].
+    pub fn search(
+        &self,
+        kind: QueryKindKey,
+        query: &KeyQuery,
+        after: Option<&str>,
+        limit: usize,
+    ) -> Result<KeyPage, IndexError> {
+        DictionaryIndex::search_in_collection(
+            self.enabled().map(|entry| &entry.dictionary.index),
+            kind,
+            query,
+            after,
+            limit,
+        )
+    }
+
+    /// Finds keys close to `query` in every enabled dictionary, see
+    /// [`DictionaryIndex::fuzzy_search`].
+    pub fn fuzzy_search(
+        &self,
+        kind: QueryKindKey,
+        query: &str,
+        max_distance: u32,
+        limit: usize,
+    ) -> Result<Vec<FuzzyMatch>, IndexError> {
+        DictionaryIndex::fuzzy_search_in_collection(
+            self.enabled().map(|entry| &entry.dictionary.index),
+            kind,
+            query,
+            max_distance,
+            limit,
+        )
+    }
+
+    /// Number of distinct terms across all enabled dictionaries.
+    pub fn unique_terms(&self) -> Result<usize, IndexError> {
+        DictionaryIndex::unique_terms_in_collection(
+            self.enabled().map(|entry| &entry.dictionary.index)
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::format::dictionary_convert::ConvertOptions;
+    use crate::format::test_util::{build_dictionary, scratch_dir, term};
+
+    #[test]
+    fn results_identify_dictionaries_with_the_same_name() {
+        let dir = scratch_dir("collection");
+        let options = ConvertOptions::default();
+        let old = build_dictionary(&dir, "old.tomo", "Test", &[term("犬", "いぬ", "dog")], &[], &options);
+        let new = build_dictionary(&dir, "new.tomo", "Test", &[term("犬", "いぬ", "a dog")], &[], &options);
+        let (old, new) = (old.to_str().unwrap(), new.to_str().unwrap());
+
+        let mut collection = DictionaryCollection::new();
+        collection.add(old, 1, true, true).unwrap();
+        collection.add(new, 0, true, true).unwrap();
+        assert!(matches!(
+            collection.add(old, 2, true, true),
+            Err(Error::Config(ConfigError::DuplicateDictionary(_)))
+        ));
+        assert_eq!(collection.entries().len(), 2);
+
+        let results = collection.find_terms("犬").unwrap();
+        let paths: Vec<&str> = results.iter().map(|result| result.path.as_str()).collect();
+        assert_eq!(paths, [new, old]);
+        assert!(results.iter().all(|result| result.dictionary == "Test"));
+
+        for result in &results {
+            let dictionary = collection.dictionary(&result.path).unwrap();
+            let definitions = &dictionary.find_terms("犬").unwrap()[0].definitions;
+            assert_eq!(format!("{:?}", definitions), format!("{:?}", result.entry.definitions));
+        }
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    /// Names of the dictionaries `犬` was found in, in result order.
+    fn found_in(collection: &DictionaryCollection) -> Vec<String> {
+        collection.find_terms("犬").unwrap()
+            .into_iter()
+            .map(|result| result.dictionary)
+            .collect()
+    }
+
+    #[test]
+    fn configs_reopen_the_same_collection() {
+        let dir = scratch_dir("collection-config");
+        let options = ConvertOptions::default();
+        let a = build_dictionary(&dir, "a.tomo", "A", &[term("犬", "いぬ", "dog")], &[], &options);
+        let b = build_dictionary(&dir, "b.tomo", "B", &[term("犬", "いぬ", "dog")], &[], &options);
+        let c = build_dictionary(&dir, "c.tomo", "C", &[term("犬", "いぬ", "dog")], &[], &options);
+
+        let mut collection = DictionaryCollection::new();
+        collection.add(a.to_str().unwrap(), 2, true, true).unwrap();
+        collection.add(b.to_str().unwrap(), 1, false, true).unwrap();
+        collection.add(c.to_str().unwrap(), 0, true, true).unwrap();
+        collection.set_frame_cache(Some(Arc::new(FrameCache::new(1 << 20))));
+
+        let path = dir.join("collection.json");
+        collection.config().save(&path).unwrap();
+        assert!(!dir.join("collection.json.tmp").exists());
+        let config = CollectionConfig::load(&path).unwrap();
+        assert_eq!(config.frame_cache_budget, Some(1 << 20));
+
+        let reopened = DictionaryCollection::open(&config, true).unwrap();
+        let summary = |collection: &DictionaryCollection| -> Vec<(String, i32, bool)> {
+            collection.entries().iter()
+                .map(|entry| (entry.meta.name.clone(), entry.priority, entry.enabled))
+                .collect()
+        };
+        assert_eq!(summary(&reopened), summary(&collection));
+        assert_eq!(summary(&reopened), [("C".into(), 0, true), ("B".into(), 1, false), ("A".into(), 2, true)]);
+        assert_eq!(found_in(&reopened), ["C", "A"]);
+        assert!(reopened.frame_cache().is_some());
+
+        assert!(matches!(CollectionConfig::load(dir.join("missing.json")), Err(ConfigError::Io { .. })));
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn disabled_dictionaries_are_not_searched() {
+        let dir = scratch_dir("collection-enabled");
+        let options = ConvertOptions::default();
+        let a = build_dictionary(&dir, "a.tomo", "A", &[term("犬", "いぬ", "dog")], &[], &options);
+        let b = build_dictionary(&dir, "b.tomo", "B", &[term("犬", "いぬ", "dog"), term("猫", "ねこ", "cat")], &[], &options);
+        let (a, b) = (a.to_str().unwrap(), b.to_str().unwrap());
+
+        let mut collection = DictionaryCollection::new();
+        collection.add(a, 0, true, true).unwrap();
+        collection.add(b, 1, true, true).unwrap();
+        assert_eq!(found_in(&collection), ["A", "B"]);
+        assert_eq!(collection.unique_terms().unwrap(), 2);
+
+        assert!(collection.set_enabled(b, false));
+        assert!(!collection.set_enabled("missing.tomo", false));
+        assert_eq!(found_in(&collection), ["A"]);
+        assert!(collection.find_terms_by("ねこ", LookupMode::Reading).unwrap().is_empty());
+        assert_eq!(collection.unique_terms().unwrap(), 1);
+        let page = collection.search(QueryKindKey::Term, &KeyQuery::Prefix(String::new()), None, 10).unwrap();
+        assert_eq!(page.keys, ["犬"]);
+        assert!(collection.fuzzy_search(QueryKindKey::Term, "猫", 0, 10).unwrap().is_empty());
+        // disabled dictionaries are still open, and reachable by path
+        assert!(collection.dictionary(b).is_some());
+
+        assert!(collection.set_enabled(b, true));
+        assert_eq!(collection.find_terms_by("ねこ", LookupMode::Reading).unwrap().len(), 1);
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn priorities_order_results() {
+        let dir = scratch_dir("collection-priority");
+        let options = ConvertOptions::default();
+        let a = build_dictionary(&dir, "a.tomo", "A", &[term("犬", "いぬ", "dog")], &[], &options);
+        let b = build_dictionary(&dir, "b.tomo", "B", &[term("犬", "いぬ", "dog")], &[], &options);
+        let c = build_dictionary(&dir, "c.tomo", "C", &[term("犬", "いぬ", "dog")], &[], &options);
+        let (a, b, c) = (a.to_str().unwrap(), b.to_str().unwrap(), c.to_str().unwrap());
+
+        let mut collection = DictionaryCollection::new();
+        collection.add(a, 0, true, true).unwrap();
+        collection.add(b, 1, true, true).unwrap();
+        // equal priorities keep the order dictionaries were added in
+        collection.add(c, 1, true, true).unwrap();
+        assert_eq!(found_in(&collection), ["A", "B", "C"]);
+
+        assert!(collection.set_priority(a, 5));
+        assert_eq!(found_in(&collection), ["B", "C", "A"]);
+        assert!(collection.set_priority(c, -1));
+        assert_eq!(found_in(&collection), ["C", "B", "A"]);
+        assert!(!collection.set_priority("missing.tomo", 0));
+
+        let removed = collection.remove(b).unwrap();
+        assert_eq!(removed.meta.name, "B");
+        assert!(collection.remove(b).is_none());
+        assert!(collection.dictionary(b).is_none());
+        assert_eq!(found_in(&collection), ["C", "A"]);
+        assert_eq!(collection.config().dictionaries.len(), 2);
+
+        // removed dictionaries can be added again
+        collection.add(b, 0, true, true).unwrap();
+        assert_eq!(found_in(&collection), ["C", "B", "A"]);
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
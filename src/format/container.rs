@@ -22,6 +22,9 @@ use std::fs::File;
 use std::fmt;
 use std::fmt::Display;
 use std::sync::atomic::{AtomicBool, Ordering};
+use crate::error::{ContainerError, Error};
+use super::signature::{self, ContainerSignature, SignaturePolicy};
+use super::hashlog::{self, HashLogFile, Validated};
 
 const CURRENT_HEADER_VERSION: u16 = 0;
 const CURRENT_CONTAINER_VERSION: u64 = 0;
@@ -37,10 +40,11 @@ pub fn allow_dev_version(enabled: bool) {
     ALLOW_DEV_VERSION.store(enabled, Ordering::Relaxed);
 }
 
-#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 pub enum Role {
     Dictionary,
     Deinflector,
+    Patch,
     #[serde(untagged)]
     Unknown(String),
 }
@@ -50,13 +54,14 @@ impl Display for Role {
         match self {
             Role::Dictionary => write!(f, "Dictionary"),
             Role::Deinflector => write!(f, "Deinflector"),
+            Role::Patch => write!(f, "Patch"),
             Role::Unknown(s) => write!(f, "Unknown ({})", s),
         }
     }
 }
 
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ContainerMeta {
     /// User visible name (ex. Jitendex)
     pub name: String,
@@ -67,7 +72,7 @@ pub struct ContainerMeta {
 }
 
 // TODO: probably can just hide this as impl detail
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ContainerHeader {
     /// Actual version of the container format
     pub container_version: u64,
@@ -85,6 +90,11 @@ pub struct ContainerHeader {
     // TODO: serialize as hex string
     // Hash of the data following the header
     pub payload_sha256: [u8; 32],
+
+    /// Signature of the header as written and of the payload hash. Always
+    /// the last field. See [`super::signature`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signature: Option<ContainerSignature>,
 }
 
 pub struct OpenContainer<R: Read + Seek> {
@@ -99,50 +109,58 @@ pub struct ContainerFileInfo {
 }
 
 impl ContainerFileInfo {
-    pub fn read_container<R: Read + Seek>(mut reader: R) -> io::Result<Self> {
+    pub fn read_container<R: Read + Seek>(mut reader: R) -> Result<Self, ContainerError> {
         const MAGIC_LEN: usize = "TOMOCHAN:XXXX:".len();
 
         let mut magic = [0u8; MAGIC_LEN];
         reader.read_exact(&mut magic)?;
         let magic_str = std::str::from_utf8(&magic)
-            .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "Invalid magic prefix"))?;
+            .map_err(|_| ContainerError::NotTomochan)?;
 
         if !magic_str.starts_with("TOMOCHAN:") {
-            return Err(io::Error::new(io::ErrorKind::InvalidData, "Not recognized as a tomodict file"));
+            return Err(ContainerError::NotTomochan);
         }
 
         let version_hex = &magic_str["TOMOCHAN:".len()..MAGIC_LEN-1];
-        let version = u8::from_str_radix(version_hex, 16)
-            .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "Invalid version in header"))?;
+        let version = u16::from_str_radix(version_hex, 16)
+            .map_err(|_| ContainerError::InvalidHeaderVersion)?;
 
-        if (version as u16) < MIN_COMPATIBLE_HEADER_VERSION {
-            return Err(io::Error::new(io::ErrorKind::InvalidData, "Incompatible header version"));
+        #[allow(clippy::absurd_extreme_comparisons)]
+        if version < MIN_COMPATIBLE_HEADER_VERSION {
+            return Err(ContainerError::UnsupportedHeaderVersion(version));
         }
 
         let mut de = Deserializer::from_reader(&mut reader);
         let header = ContainerHeader::deserialize(&mut de)
-            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("JSON parse error: {}", e)))?;
+            .map_err(ContainerError::Header)?;
 
         let payload_offset = reader.stream_position()?;
 
         Ok(Self {header, payload_offset})
     }
 
-    pub fn validate_payload<R: Read + Seek>(&self, mut reader: R) -> io::Result<()> {
+    /// Bytes of the magic and the header, exactly as written.
+    pub fn read_raw_header<R: Read + Seek>(&self, mut reader: R) -> Result<Vec<u8>, ContainerError> {
+        let mut raw = Vec::new();
+        reader.seek(SeekFrom::Start(0))?;
+        reader.take(self.payload_offset).read_to_end(&mut raw)?;
+
+        if raw.len() as u64 != self.payload_offset {
+            return Err(ContainerError::Io(io::ErrorKind::UnexpectedEof.into()));
+        }
+        Ok(raw)
+    }
+
+    pub fn validate_payload<R: Read + Seek>(&self, mut reader: R) -> Result<(), ContainerError> {
         reader.seek(SeekFrom::Start(self.payload_offset))?;
         let eof = reader.seek(SeekFrom::End(0))?;
-        let actual_length = eof
-            .checked_sub(self.payload_offset)
-            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Invalid file length"))?;
+        let actual_length = eof.saturating_sub(self.payload_offset);
 
         if actual_length != self.header.payload_length {
-            return Err(io::Error::new(
-                io::ErrorKind::InvalidData,
-                format!(
-                    "Payload length mismatch: header says {}, file has {}",
-                    self.header.payload_length, actual_length
-                ),
-            ));
+            return Err(ContainerError::PayloadLengthMismatch {
+                expected: self.header.payload_length,
+                found: actual_length,
+            });
         }
 
         reader.seek(SeekFrom::Start(self.payload_offset))?;
@@ -151,11 +169,8 @@ impl ContainerFileInfo {
         io::copy(&mut reader.take(self.header.payload_length), &mut hasher)?;
         let actual_hash = hasher.finalize();
 
-        if &actual_hash[..] != &self.header.payload_sha256[..] {
-            return Err(io::Error::new(
-                io::ErrorKind::InvalidData,
-                "SHA256 hash of payload does not match header",
-            ));
+        if actual_hash[..] != self.header.payload_sha256[..] {
+            return Err(ContainerError::PayloadHashMismatch);
         }
 
         Ok(())
@@ -166,7 +181,7 @@ pub fn write_container<T: ContainerFormat, W: Write>(
     writer: &mut W,
     meta: ContainerMeta,
     data: &[u8],
-) -> io::Result<()> {
+) -> Result<(), ContainerError> {
     let mut hasher = Sha256::new();
     hasher.update(data);
     let payload_sha256 = hasher.finalize();
@@ -178,11 +193,11 @@ pub fn write_container<T: ContainerFormat, W: Write>(
         min_role_version: T::role_version(),
         payload_length: data.len() as u64,
         payload_sha256: payload_sha256.into(),
+        signature: None,
     };
 
-
     let header_json = serde_json::to_vec(&header)
-        .map_err(|e| io::Error::new(io::ErrorKind::Other, format!("JSON serialization error: {}", e)))?;
+        .map_err(ContainerError::Header)?;
 
     writer.write_all(format!("TOMOCHAN:{:04X}:", CURRENT_HEADER_VERSION).as_bytes())?;
     writer.write_all(&header_json)?;
@@ -196,32 +211,88 @@ pub trait ContainerFormat: Sized {
     fn min_role_version() -> u64;
     fn role_version() -> u64;
 
-    // TODO: store verification <-> hash in .hashlog file, still use tomochan wrapper?
-    // hashlog file needs to use the hash of the entire file, not the sha256 inside. maybe the sha256 needs to go at the end?
-    fn load(path: &str, payload_offset: u64, verify: bool) -> Result<Self, String>;
+    /// Reads the payload from `file`, the same open file the header was read
+    /// from. The payload hash has already been checked when `verify` is set,
+    /// it is up to the format to check anything more specific. Checks should
+    /// stay cheap compared to hashing, anything slower belongs in a separate
+    /// method (ex. [`Dictionary::verify_deep`](super::Dictionary::verify_deep)).
+    fn load(file: File, payload_offset: u64, verify: bool) -> Result<Self, Error>;
+}
+
+pub fn open_container<T: ContainerFormat>(path: &str, verify: bool) -> Result<T, Error> {
+    open_container_with_header(path, verify).map(|(_, contents)| contents)
+}
+
+/// Same as [`open_container`], but also returns the container header.
+pub fn open_container_with_header<T: ContainerFormat>(path: &str, verify: bool) -> Result<(ContainerHeader, T), Error> {
+    open_container_logged(path, verify, None)
 }
 
-// TODO: should probably expose the header somehow
-pub fn open_container<T: ContainerFormat>(path: &str, verify: bool) -> Result<T, String> {
-    let file = File::open(path)
-        .map_err(|e| format!("Failed to read package file: {}", e))?;
+/// Same as [`open_container_with_header`], but skips verifying containers
+/// that `log` has as already verified, and logs the ones that are verified.
+pub fn open_container_logged<T: ContainerFormat>(
+    path: &str,
+    verify: bool,
+    log: Option<&HashLogFile>,
+) -> Result<(ContainerHeader, T), Error> {
+    let mut file = File::open(path)
+        .map_err(ContainerError::Io)?;
 
-    let container = ContainerFileInfo::read_container(file)
-        .map_err(|e| format!("Error opening container: {}", e))?;
+    let container = ContainerFileInfo::read_container(&mut file)?;
 
     if container.header.role != T::role() {
-        return Err(format!("container role is different than expected: {} {}", container.header.role, T::role()));
+        return Err(ContainerError::RoleMismatch {
+            expected: T::role(),
+            found: container.header.role,
+        }.into());
     }
 
     if container.header.min_role_version > T::role_version() {
-        return Err("container role format too new".to_string());
+        return Err(ContainerError::RoleVersionTooNew {
+            supported: T::role_version(),
+            required: container.header.min_role_version,
+        }.into());
+    }
+
+    if container.header.min_role_version < T::min_role_version() {
+        return Err(ContainerError::RoleVersionTooOld {
+            minimum: T::min_role_version(),
+            found: container.header.min_role_version,
+        }.into());
     }
 
     if container.header.min_role_version == 0 && !dev_version_allowed() {
-        return Err("package is a development version".to_string());
+        return Err(ContainerError::DevVersion.into());
     }
 
-    // TODO: sha256 when verify
+    // signatures only cover the payload through its hash, so checking them
+    // means checking the hash as well
+    let policy = signature::signature_policy();
+    let validated = if verify || policy != SignaturePolicy::Ignore {
+        Some(hashlog::validate_payload(log, path, &container, &mut file)?)
+    } else {
+        None
+    };
+
+    if policy != SignaturePolicy::Ignore {
+        match signature::verify_opened_header(&container, &mut file) {
+            Ok(_) => {}
+            Err(Error::Signature(e)) if policy == SignaturePolicy::Warn => {
+                signature::warn_signature(path, &e);
+            }
+            Err(e) => return Err(e),
+        }
+    }
+
+    // loaded from the handle that was checked, since the path may have been
+    // replaced by another file in the meantime
+    let logged = matches!(validated, Some(Validated::Logged));
+    let contents = T::load(file, container.payload_offset, verify && !logged)?;
+
+    // only logged once the format checks passed as well
+    if let (Some(log), Some(Validated::Hashed(Some(pending))), true) = (log, validated, verify) {
+        log.record(pending);
+    }
 
-    T::load(path, container.payload_offset, verify)
+    Ok((container.header, contents))
 }
\ No newline at end of file
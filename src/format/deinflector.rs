@@ -5,16 +5,18 @@ use std::io::SeekFrom;
 use crate::schema::BINCODE_CONFIG;
 use bincode::{Encode, Decode};
 use super::container::{ContainerFormat, Role};
+use crate::error::{ContainerError, Error};
 
 const DEPTH_LIMIT: u32 = 10;
 
-// TODO: validate using rules from yomitan dictionary, seems like we can just match with condition names
-
 #[derive(Debug, Clone, Encode, Decode)]
 pub struct Deinflector {
     pub transform_meta: Vec<TransformMeta>,
     pub suffix_map: HashMap<Vec<u8>, Vec<Production>>,
     pub suffix_lengths: Vec<usize>,
+    /// Condition bits of each dictionary rule name (ex. `v1`), including the
+    /// bits of the conditions it is a subtag of.
+    pub rule_conditions: HashMap<String, u64>,
 }
 
 #[derive(Debug, Clone, Encode, Decode)]
@@ -36,6 +38,9 @@ pub struct Production {
 pub struct DeinflectionResult {
     pub term: String,
     pub rule_chain: Vec<usize>,
+    /// Conditions produced by the last rule of the chain, which the rules of
+    /// a dictionary entry must match. 0 matches every entry.
+    pub conditions: u64,
 }
 
 impl Deinflector {
@@ -78,6 +83,7 @@ impl Deinflector {
                     results.push(DeinflectionResult {
                         term: term_str.to_string(),
                         rule_chain: rule_chain.clone(),
+                        conditions: result.produce_tags,
                     });
 
                     if self.transform_meta[result.transform_idx].is_final {
@@ -102,10 +108,27 @@ impl Deinflector {
         results.push(DeinflectionResult {
             term: term.to_string(),
             rule_chain: vec![],
+            conditions: 0,
         });
         self.deinflect_recursive(term.as_bytes(), vec![], u64::MAX, &mut results, 0);
         results
     }
+
+    /// Condition bits of the space separated rules of a dictionary entry
+    /// (ex. `v1 vt`). Unknown rule names have none.
+    pub fn conditions_of(&self, rules: &str) -> u64 {
+        rules.split_whitespace()
+            .filter_map(|rule| self.rule_conditions.get(rule))
+            .fold(0, |a, b| a | b)
+    }
+
+    /// Whether `result` can be the dictionary form of an entry with the given
+    /// rules. As in yomitan, a form constrained by its last rule (ex. the
+    /// ichidan past tense producing `v1`) only matches entries with one of
+    /// the produced conditions, so 食べた is never looked up as a godan verb.
+    pub fn accepts(&self, result: &DeinflectionResult, rules: &str) -> bool {
+        result.conditions == 0 || result.conditions & self.conditions_of(rules) != 0
+    }
 }
 
 impl ContainerFormat for Deinflector {
@@ -113,32 +136,34 @@ impl ContainerFormat for Deinflector {
         Role::Deinflector
     }
 
+    // bumped whenever the payload layout changes, older layouts are not
+    // decoded
     fn min_role_version() -> u64 {
-        0
+        1
     }
 
     fn role_version() -> u64 {
-        0
+        1
     }
 
-    fn load(path: &str, payload_offset: u64, verify: bool) -> Result<Self, String> {
-        let mut file = File::open(path)
-            .map_err(|e| format!("Failed to read package file: {}", e))?;
-
+    fn load(mut file: File, payload_offset: u64, _verify: bool) -> Result<Self, Error> {
         file.seek(SeekFrom::Start(payload_offset))
-            .map_err(|e| format!("Failed to seek to dictionary contents: {}", e))?;
+            .map_err(ContainerError::Io)?;
 
         let deinflector: Self = bincode::decode_from_std_read(&mut file, BINCODE_CONFIG)
-            .map_err(|e| format!("Failed to decode header: {}", e))?;
+            .map_err(ContainerError::Decode)?;
 
         let file_meta = file.metadata()
-            .map_err(|e| format!("Failed to get file metadata: {}", e))?;
+            .map_err(ContainerError::Io)?;
 
         let pos = file.stream_position()
-            .map_err(|e| format!("Failed to get stream position: {}", e))?;
+            .map_err(ContainerError::Io)?;
 
         if pos != file_meta.len() {
-            return Err(format!("expected file length {}, found {}", file_meta.len(), pos))
+            return Err(ContainerError::PayloadLengthMismatch {
+                expected: pos,
+                found: file_meta.len(),
+            }.into());
         }
 
         // TODO: verify rules, utf8, meta indexes
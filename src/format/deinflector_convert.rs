@@ -2,6 +2,7 @@ use serde::{Deserialize, Serialize};
 use std::collections::{HashMap, HashSet};
 use crate::schema::BINCODE_CONFIG;
 use super::container::{write_container, ContainerMeta};
+use crate::error::ConvertError;
 use std::fs::File;
 use std::fs;
 
@@ -35,11 +36,11 @@ pub struct Transform {
     pub rules: Vec<Rule>
 }
 
-pub fn convert_deinflector(src_path: &str, dst_path: &str, meta: ContainerMeta) -> Result<(), String> {
+pub fn convert_deinflector(src_path: &str, dst_path: &str, meta: ContainerMeta) -> Result<(), ConvertError> {
     let json = fs::read_to_string(src_path)
-        .map_err(|e| format!("Failed to read source file: {}", e))?;
+        .map_err(|source| ConvertError::Io { path: src_path.into(), source })?;
     let s: TransformSet = serde_json::from_str(&json)
-        .map_err(|e| format!("Failed to parse source file: {}", e))?;
+        .map_err(|source| ConvertError::Json { path: src_path.into(), source })?;
 
     let mut next_leaf_id: u64 = 1;
     let mut key_to_id: HashMap<String, u64> = HashMap::new();
@@ -62,7 +63,7 @@ pub fn convert_deinflector(src_path: &str, dst_path: &str, meta: ContainerMeta)
             for tag in rule.accept.tags.iter().chain(rule.produce.tags.iter()) {
                 if !key_to_id.contains_key(tag) {
                     if next_leaf_id == 0 {
-                        return Err("too many tags".to_string());
+                        return Err(ConvertError::TooManyTags);
                     }
                     key_to_id.insert(tag.clone(), next_leaf_id);
                     next_leaf_id <<= 1;
@@ -71,6 +72,18 @@ pub fn convert_deinflector(src_path: &str, dst_path: &str, meta: ContainerMeta)
         }
     }
 
+    // an entry with a subtag (ex. v1) also matches rules producing its
+    // parent (ex. v)
+    let rule_conditions: HashMap<String, u64> = key_to_id.iter()
+        .map(|(tag, &id)| {
+            let parents = s.subtags.iter()
+                .filter(|(_, children)| children.contains(tag))
+                .filter_map(|(parent, _)| key_to_id.get(parent))
+                .fold(0, |a, b| a | b);
+            (tag.clone(), id | parents)
+        })
+        .collect();
+
     let mut suffix_map: HashMap<Vec<u8>, Vec<Production>> = HashMap::new();
     let mut suffix_lengths = HashSet::new();
 
@@ -107,18 +120,18 @@ pub fn convert_deinflector(src_path: &str, dst_path: &str, meta: ContainerMeta)
     let mut suffix_lengths: Vec<usize> = suffix_lengths.into_iter().collect();
     suffix_lengths.sort();
 
-    let mut encoded: Vec<u8> = bincode::encode_to_vec(
+    let encoded: Vec<u8> = bincode::encode_to_vec(
         Deinflector {
             transform_meta,
             suffix_map,
             suffix_lengths,
+            rule_conditions,
         },
         BINCODE_CONFIG
-    ).map_err(|e| format!("Failed to encode deinflector: {}", e))?;
+    )?;
     
     let mut file = File::create(dst_path)
-        .map_err(|e| format!("Failed to open package file: {}", e))?;
+        .map_err(|source| ConvertError::Io { path: dst_path.into(), source })?;
 
-    write_container::<Deinflector, _>(&mut file, meta, &encoded)
-        .map_err(|e| format!("Failed to write container: {}", e))
+    Ok(write_container::<Deinflector, _>(&mut file, meta, &encoded)?)
 }
\ No newline at end of file
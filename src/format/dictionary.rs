@@ -1,75 +1,347 @@
-use super::store::Store;
+use super::store::{Store, StoreOptions, MappedRegion, RawRegion, RAW_OFFSET_FLAG};
 use super::index::DictionaryIndex;
 use super::container::{ContainerFormat, Role};
-use crate::schema::BINCODE_CONFIG;
+use super::types::{Queryable, QueryKindKey, LookupMode};
+use crate::schema::{Term, Kanji, Tag, TermMeta, KanjiMeta, BINCODE_CONFIG};
+use crate::schema::DictionaryIndex as IndexInfo;
+use crate::error::{ContainerError, Error, StoreError};
+use std::borrow::Cow;
+use std::collections::HashSet;
 use std::fs::File;
 use bincode::{Encode, Decode};
 use std::io::{Seek, SeekFrom};
+use std::sync::Arc;
+use memmap2::Mmap;
 
 // TODO: make sure we are not using usize in any serialized data
 #[derive(Encode, Decode)]
 pub struct DictionaryHeader {
     pub fst_len: u64,
     pub store_len: u64,
+    /// Length of the raw region, which follows the store.
+    pub raw_len: u64,
+    /// Contents of the source dictionary's `index.json`, if it had one.
+    pub info: Option<IndexInfo>,
+    /// Options the store was compressed with.
+    pub store_options: StoreOptions,
+    /// zstd dictionary the store was compressed with, if it was trained one.
+    pub store_dictionary: Option<Vec<u8>>,
 }
 
+/// Dictionaries are `Send + Sync`, and all lookups take `&self`, so one
+/// dictionary can serve lookups from any number of threads at once.
 pub struct Dictionary {
     pub index: DictionaryIndex,
-    pub store: Store<File>,
+    pub store: Store<MappedRegion>,
+    /// Media files that zstd could not compress.
+    pub raw: RawRegion,
+    /// Metadata from the source dictionary's `index.json` (title, author,
+    /// attribution, languages, update URLs...).
+    pub info: Option<IndexInfo>,
+    pub store_options: StoreOptions,
 }
 
+impl Dictionary {
+    /// Finds all entries of type `T` stored under `key`, in import order.
+    pub fn find<T: Queryable + Decode<()>>(&self, key: &str) -> Result<Vec<T>, StoreError> {
+        self.index.lookup(T::KIND, key)?
+            .into_iter()
+            .map(|offset| self.store.get(offset))
+            .collect()
+    }
+
+    pub fn find_terms(&self, term: &str) -> Result<Vec<Term>, StoreError> {
+        self.find(term)
+    }
+
+    /// Finds terms by headword, reading or both. Terms matching both by
+    /// headword and by reading are only returned once.
+    pub fn find_terms_by(&self, key: &str, mode: LookupMode) -> Result<Vec<Term>, StoreError> {
+        let mut offsets = Vec::new();
+        let mut seen = HashSet::new();
+        for &kind in mode.kinds() {
+            for offset in self.index.lookup(kind, key)? {
+                if seen.insert(offset) {
+                    offsets.push(offset);
+                }
+            }
+        }
+
+        offsets.into_iter()
+            .map(|offset| self.store.get(offset))
+            .collect()
+    }
+
+    pub fn find_kanji(&self, character: &str) -> Result<Vec<Kanji>, StoreError> {
+        self.find(character)
+    }
+
+    pub fn find_tags(&self, name: &str) -> Result<Vec<Tag>, StoreError> {
+        self.find(name)
+    }
+
+    pub fn find_term_meta(&self, term: &str) -> Result<Vec<TermMeta>, StoreError> {
+        self.find(term)
+    }
+
+    pub fn find_kanji_meta(&self, character: &str) -> Result<Vec<KanjiMeta>, StoreError> {
+        self.find(character)
+    }
+
+    /// Reads a media file by its path relative to the root of the source
+    /// dictionary (ex. `img/foo.png`). Files in the raw region are borrowed
+    /// from the dictionary file instead of copied.
+    pub fn find_file(&self, path: &str) -> Result<Option<Cow<'_, [u8]>>, StoreError> {
+        let Some(&offset) = self.index.lookup(QueryKindKey::File, path)?.first() else {
+            return Ok(None);
+        };
+
+        if offset & RAW_OFFSET_FLAG != 0 {
+            Ok(Some(Cow::Borrowed(self.raw.get(offset)?)))
+        } else {
+            Ok(Some(Cow::Owned(self.store.get(offset)?)))
+        }
+    }
+}
+
+impl Dictionary {
+    /// Checks the index, and that every offset in it points into the store or
+    /// the raw region.
+    fn verify(&self) -> Result<(), Error> {
+        self.index.verify()?;
+
+        for kind in QueryKindKey::ALL {
+            for offset in self.index.offsets(kind) {
+                if offset & RAW_OFFSET_FLAG != 0 {
+                    self.raw.get(offset)?;
+                } else {
+                    self.store.check_offset(offset)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Decompresses the whole store, which checks every frame. This takes
+    /// about as long as converting the dictionary, so it is never done when
+    /// opening one.
+    pub fn verify_deep(&self) -> Result<(), Error> {
+        self.verify()?;
+        self.store.verify()?;
+        Ok(())
+    }
+}
+
+const _: () = {
+    const fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<Dictionary>();
+};
+
 impl ContainerFormat for Dictionary {
     fn role() -> Role {
         Role::Dictionary
     }
 
+    // bumped whenever the payload layout changes, older layouts are not
+    // decoded
     fn min_role_version() -> u64 {
-        0
+        6
     }
 
     fn role_version() -> u64 {
-        0
+        6
     }
 
-    fn load(path: &str, payload_offset: u64, verify: bool) -> Result<Self, String> {
-        let mut file = File::open(path)
-            .map_err(|e| format!("Failed to read package file: {}", e))?;
-
+    fn load(mut file: File, payload_offset: u64, verify: bool) -> Result<Self, Error> {
         file.seek(SeekFrom::Start(payload_offset))
-            .map_err(|e| format!("Failed to seek to dictionary contents: {}", e))?;
+            .map_err(ContainerError::Io)?;
 
         let header: DictionaryHeader = bincode::decode_from_std_read(&mut file, BINCODE_CONFIG)
-            .map_err(|e| format!("Failed to decode header: {}", e))?;
+            .map_err(ContainerError::Decode)?;
 
         let base_offset = file.stream_position()
-            .map_err(|e| format!("Failed to get stream position: {}", e))?;
+            .map_err(ContainerError::Io)?;
 
         let file_meta = file.metadata()
-            .map_err(|e| format!("Failed to get file metadata: {}", e))?;
+            .map_err(ContainerError::Io)?;
 
-        if file_meta.len() != base_offset + header.fst_len + header.store_len {
-            return Err(format!("expected file length {}, found {}", file_meta.len(), base_offset + header.fst_len + header.store_len))
+        if file_meta.len() != base_offset + header.fst_len + header.store_len + header.raw_len {
+            return Err(ContainerError::PayloadLengthMismatch {
+                expected: base_offset + header.fst_len + header.store_len + header.raw_len,
+                found: file_meta.len(),
+            }.into());
         }
 
-        // setup file for store
-        file.seek(SeekFrom::Start(base_offset + header.fst_len))
-            .map_err(|e| format!("Failed to seek to store contents: {}", e))?;
+        // files are replaced by renaming over them, never modified in place
+        let mmap = Arc::new(unsafe {
+            Mmap::map(&file)
+                .map_err(ContainerError::Io)?
+        });
 
         let dict = Self {
             index: DictionaryIndex::load_mmap(
-                path,
+                Arc::clone(&mmap),
                 base_offset,
                 header.fst_len,
             )?,
-            store: Store::new(file)?,
+            store: Store::with_dictionary(
+                MappedRegion::new(Arc::clone(&mmap), base_offset + header.fst_len, header.store_len),
+                header.store_dictionary,
+            )?,
+            raw: RawRegion::new(MappedRegion::new(
+                mmap,
+                base_offset + header.fst_len + header.store_len,
+                header.raw_len,
+            )),
+            info: header.info,
+            store_options: header.store_options,
         };
 
         if verify {
-            dict.index.verify()?;
-            // TODO: verify FST
-            // TODO: verify store
+            dict.verify()?;
         }
 
         Ok(dict)
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::format::container::open_container;
+    use crate::format::dictionary_convert::ConvertOptions;
+    use crate::format::container::{ContainerFileInfo, open_container_logged};
+    use crate::format::frame_cache::FrameCache;
+    use crate::format::hashlog::{HashLogFile, HashLogPolicy};
+    use crate::format::test_util::{build_dictionary, overwrite_in_place, scratch_dir, term};
+    use crate::error::ContainerError;
+    use std::fs;
+    use std::thread;
+
+    #[test]
+    fn concurrent_lookups() {
+        let dir = scratch_dir("concurrent");
+        let terms: Vec<_> = (0..2000)
+            .map(|i| term(&format!("term{}", i), &format!("reading{}", i), &format!("definition {}", i)))
+            .collect();
+        // small frames, so lookups spread over many of them
+        let options = ConvertOptions {
+            store: StoreOptions { frame_size: 1 << 10, ..StoreOptions::default() },
+            ..ConvertOptions::default()
+        };
+        let path = build_dictionary(&dir, "dict.tomo", "Dict", &terms, &[], &options);
+        let mut dict: Dictionary = open_container(path.to_str().unwrap(), true).unwrap();
+
+        // a cache too small for every frame, so threads evict each other's
+        for cache in [None, Some(Arc::new(FrameCache::new(8 << 10)))] {
+            dict.store.set_cache(cache.clone());
+
+            thread::scope(|scope| {
+                for thread in 0..8 {
+                    let dict = &dict;
+                    scope.spawn(move || {
+                        for i in (0..2000).map(|i| (i * 7 + thread * 251) % 2000) {
+                            let found = dict.find_terms(&format!("term{}", i)).unwrap();
+                            assert_eq!(found.len(), 1);
+                            assert_eq!(found[0].reading, format!("reading{}", i));
+                        }
+                    });
+                }
+            });
+
+            if let Some(cache) = cache {
+                let stats = cache.stats();
+                // items crossing a frame boundary read two frames
+                assert!(stats.hits + stats.misses >= 8 * 2000);
+                assert!(stats.bytes <= stats.budget);
+            }
+        }
+
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn terms_are_found_by_reading() {
+        let dir = scratch_dir("find-by");
+        let terms = [
+            term("犬", "いぬ", "dog"),
+            term("戌", "いぬ", "sign of the dog"),
+            // kana-only headwords are looked up by the term itself
+            term("いぬ", "", "dog"),
+            term("ねこ", "ねこ", "cat"),
+        ];
+        let path = build_dictionary(&dir, "dict.tomo", "Dict", &terms, &[], &ConvertOptions::default());
+        let dict: Dictionary = open_container(path.to_str().unwrap(), true).unwrap();
+
+        let find = |key: &str, mode: LookupMode| {
+            let mut found: Vec<_> = dict.find_terms_by(key, mode).unwrap()
+                .into_iter()
+                .map(|term| format!("{}/{}", term.term, term.reading))
+                .collect();
+            found.sort();
+            found
+        };
+
+        assert_eq!(find("いぬ", LookupMode::Term), ["いぬ/"]);
+        assert_eq!(find("いぬ", LookupMode::Reading), ["いぬ/", "戌/いぬ", "犬/いぬ"]);
+        assert_eq!(find("いぬ", LookupMode::TermOrReading), ["いぬ/", "戌/いぬ", "犬/いぬ"]);
+        assert_eq!(find("犬", LookupMode::Reading), Vec::<String>::new());
+        assert_eq!(find("犬", LookupMode::TermOrReading), ["犬/いぬ"]);
+        assert_eq!(find("ねこ", LookupMode::TermOrReading), ["ねこ/ねこ"]);
+
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn media_files_are_borrowed_from_the_raw_region() {
+        let dir = scratch_dir("find-file");
+        let mut state = 1u64;
+        let image: Vec<u8> = (0..4096).map(|_| {
+            state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            (state >> 56) as u8
+        }).collect();
+        let text = "compressible ".repeat(100);
+        let files: [(&str, &[u8]); 2] = [("img/noise.png", &image), ("text.txt", text.as_bytes())];
+        let path = build_dictionary(&dir, "dict.tomo", "Dict", &[term("犬", "いぬ", "dog")], &files, &ConvertOptions::default());
+        let dict: Dictionary = open_container(path.to_str().unwrap(), true).unwrap();
+
+        let offset = dict.index.lookup(QueryKindKey::File, "img/noise.png").unwrap()[0];
+        assert_ne!(offset & RAW_OFFSET_FLAG, 0);
+        assert!(matches!(dict.find_file("img/noise.png").unwrap(), Some(Cow::Borrowed(data)) if data == &image[..]));
+        assert!(matches!(dict.find_file("text.txt").unwrap(), Some(Cow::Owned(data)) if data == text.as_bytes()));
+        assert!(dict.find_file("missing.png").unwrap().is_none());
+
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn logged_dictionaries_are_not_decompressed_when_opened() {
+        let dir = scratch_dir("deep-verify");
+        let path = build_dictionary(&dir, "dict.tomo", "Dict", &[term("犬", "いぬ", "dog")], &[], &ConvertOptions::default());
+        let path = path.to_str().unwrap();
+        let log = HashLogFile::open(dir.join("log.json"), HashLogPolicy::Metadata);
+
+        let (_, dict) = open_container_logged::<Dictionary>(path, true, Some(&log)).unwrap();
+        dict.verify_deep().unwrap();
+        drop(dict);
+
+        // break the magic number of the first store frame, so decompressing
+        // the store fails
+        let mut file = File::open(path).unwrap();
+        let container = ContainerFileInfo::read_container(&mut file).unwrap();
+        file.seek(SeekFrom::Start(container.payload_offset)).unwrap();
+        let header: DictionaryHeader = bincode::decode_from_std_read(&mut file, BINCODE_CONFIG).unwrap();
+        let store_start = file.stream_position().unwrap() + header.fst_len;
+        overwrite_in_place(path, SeekFrom::Start(store_start), &[0; 4]);
+
+        let (_, dict) = open_container_logged::<Dictionary>(path, true, Some(&log)).unwrap();
+        assert!(matches!(dict.verify_deep(), Err(Error::Store(_))));
+        assert!(matches!(
+            open_container::<Dictionary>(path, true),
+            Err(Error::Container(ContainerError::PayloadHashMismatch))
+        ));
+
+        let _ = fs::remove_dir_all(dir);
+    }
+}
@@ -1,118 +1,361 @@
-use super::store::StoreBuilder;
+use super::store::{StoreBuilder, StoreOptions, RawBuilder, is_compressible};
 use super::types::{Queryable, QueryKindKey};
 use super::index::DictionaryIndex;
 use super::container::{ContainerMeta, write_container};
 use super::dictionary::*;
-use crate::schema::{Term, Tag, Kanji, KanjiMeta, TermMeta, BINCODE_CONFIG};
+use super::dictionary_source::{DictionarySource, open_source};
+use crate::schema::{Term, Tag, Kanji, KanjiMeta, TermMeta, Definition, StructuredContent, Attributes, BINCODE_CONFIG};
 use crate::schema::JsonParseable;
-use std::fs;
-use std::fs::File;
-use std::path::Path;
+use crate::schema::DictionaryIndex as IndexInfo;
+use crate::error::{ConvertError, SchemaError};
+use std::collections::BTreeMap;
+use super::atomic_file::AtomicFile;
 
-// TODO: fix error handling throughout codebase
+const BANK_PREFIXES: &[&str] = &["term_bank_", "term_meta_bank_", "kanji_bank_", "kanji_meta_bank_", "tag_bank_"];
 
+/// Imports every file that is not part of the dictionary data itself (ex.
+/// images) so it can be found by its path. Files that are compressed already
+/// go to the raw region rather than the store.
 fn import_files(
-    base_dir: &str,
-    current_dir: &str,
+    source: &mut dyn DictionarySource,
     store: &mut StoreBuilder,
+    raw: &mut RawBuilder,
     mapping: &mut Vec<(QueryKindKey, String, u64)>,
-) -> Result<(), String> {
-    let entries = std::fs::read_dir(current_dir)
-        .map_err(|e| format!("Failed to read directory {}: {}", current_dir, e))?;
-    
-    for entry in entries {
-        let entry = entry.map_err(|e| format!("Failed to read entry: {}", e))?;
-        let path = entry.path();
-        
-        if path.is_file() {
-            let file_name = path.file_name()
-                .and_then(|n| n.to_str())
-                .ok_or("Invalid filename")?;
-            
-            const SKIP_PREFIXES: &[&str] = &["term_bank_", "term_meta_bank_", "kanji_bank_", "kanji_meta_bank_", "tag_bank_"];
-            if file_name == "index.json" || SKIP_PREFIXES.iter().any(|prefix| file_name.starts_with(prefix)) {
-                continue;
-            }
-            
-            let file_data = fs::read(&path)
-                .map_err(|e| format!("Failed to read file {:?}: {}", path, e))?;
-            
-            let rel_path = path.strip_prefix(base_dir)
-                .map_err(|e| format!("Failed to get relative path: {}", e))?;
-            let rel_path_str = rel_path.to_str()
-                .ok_or("Invalid path string")?
-                .to_string();
-
-            mapping.push((QueryKindKey::File, rel_path_str, store.insert(&file_data)?));
-            
-        } else if path.is_dir() {
-            let dir_str = path.to_str().ok_or("Invalid directory path")?;
-            import_files(base_dir, dir_str, store, mapping)?;
-        }
+) -> Result<(), ConvertError> {
+    let paths: Vec<String> = source.files()
+        .iter()
+        .filter(|path| {
+            // banks and index.json are only read from the root
+            path.contains('/') || (path.as_str() != "index.json" && !BANK_PREFIXES.iter().any(|prefix| path.starts_with(prefix)))
+        })
+        .cloned()
+        .collect();
+
+    for path in paths {
+        let file_data = source.read(&path)?;
+        let offset = if is_compressible(&file_data) {
+            store.insert(&file_data)?
+        } else {
+            raw.insert(&file_data)
+        };
+        mapping.push((QueryKindKey::File, path, offset));
     }
-    
+
     Ok(())
 }
 
-pub fn load_typed_banks<P, T>(dir: P, prefix: &str, type_name: &str, store: &mut StoreBuilder,
-    mapping: &mut Vec<(QueryKindKey, String, u64)>,) -> Result<(), String>
+fn load_typed_banks<T>(source: &mut dyn DictionarySource, prefix: &str, type_name: &'static str, store: &mut StoreBuilder,
+    mapping: &mut Vec<(QueryKindKey, String, u64)>, mut inspect: impl FnMut(&T)) -> Result<(), ConvertError>
 where
-    P: AsRef<Path>,
     T: JsonParseable + Queryable + bincode::Encode,
 {
     for i in 1.. {
-        let file = dir.as_ref().join(format!("{}{}.json", prefix, i));
-        if !file.exists() { break; }
+        let name = format!("{}{}.json", prefix, i);
+        if !source.contains(&name) { break; }
 
-        let content = fs::read_to_string(&file)
-            .map_err(|e| format!("Failed to read {}: {}", file.display(), e))?;
+        let file = source.display_path(&name);
+        let content = source.read(&name)?;
 
-        let arr = serde_json::from_str::<Vec<serde_json::Value>>(&content)
-            .map_err(|e| format!("Failed to parse {}: {}", file.display(), e))?;
+        let arr = serde_json::from_slice::<Vec<serde_json::Value>>(&content)
+            .map_err(|source| ConvertError::Json { path: file.clone(), source })?;
 
-        for item in arr.iter() {
-            let item_arr = item.as_array()
-                .ok_or(format!("{} entry must be an array", type_name))?;
+        for (row, item) in arr.iter().enumerate() {
+            let elem = item.as_array()
+                .ok_or(SchemaError::ExpectedArray("entry"))
+                .and_then(|item_arr| T::from_json_array(item_arr))
+                .map_err(|source| ConvertError::Bank { file: file.clone(), bank: type_name, row, source })?;
 
-            let elem = T::from_json_array(item_arr)?;
-            mapping.push((T::KIND, elem.key(), store.insert(&elem)?));
+            inspect(&elem);
+            let offset = store.insert(&elem)?;
+            mapping.push((T::KIND, elem.key(), offset));
+            for (kind, key) in elem.additional_keys() {
+                mapping.push((kind, key, offset));
+            }
         }
     }
     Ok(())
 }
 
-pub fn convert_yomitan_dictionary(src_dir: &str, dst: &str, meta: ContainerMeta) -> Result<(), String> {
+/// Container metadata for a converted dictionary, and how to compress it.
+/// Metadata left unset is taken from the dictionary's `index.json`.
+#[derive(Debug, Clone, Default)]
+pub struct ConvertOptions {
+    pub name: Option<String>,
+    pub revision_name: Option<String>,
+    pub revision: Option<u64>,
+    pub store: StoreOptions,
+    /// zstd dictionary to compress the store with instead of training one,
+    /// usually the previous revision's, so patches between the revisions
+    /// only hold the frames that changed.
+    pub store_dictionary: Option<Vec<u8>>,
+}
+
+impl ConvertOptions {
+    fn container_meta(&self, info: Option<&IndexInfo>) -> Result<ContainerMeta, ConvertError> {
+        let name = self.name.clone()
+            .or_else(|| info.map(|info| info.title.clone()))
+            .ok_or(ConvertError::MissingMetadata("name"))?;
+        let revision_name = self.revision_name.clone()
+            .or_else(|| info.map(|info| info.revision.clone()))
+            .ok_or(ConvertError::MissingMetadata("revision name"))?;
+        // revisions that can't be packed into a number are numbered 0, which
+        // later updates are then numbered after
+        let revision = match (self.revision, info) {
+            (Some(revision), _) => revision,
+            (None, Some(info)) => revision_number(&info.revision).unwrap_or(0),
+            (None, None) => return Err(ConvertError::MissingMetadata("revision")),
+        };
+
+        Ok(ContainerMeta { name, revision_name, revision })
+    }
+}
+
+/// Numeric parts of a yomitan revision string separated by `.` or `-` (ex.
+/// `2025.01.31.0`), which order revisions when compared part by part.
+/// Trailing zero parts are dropped, so `1.0` and `1` are the same revision.
+/// Returns `None` if any part is not a number.
+pub(super) fn revision_parts(revision: &str) -> Option<Vec<u64>> {
+    let mut parts = revision.split(['.', '-'])
+        .map(|part| part.parse::<u64>().ok())
+        .collect::<Option<Vec<_>>>()?;
+
+    while parts.last() == Some(&0) {
+        parts.pop();
+    }
+    Some(parts)
+}
+
+/// Revision number from a yomitan revision string, with up to four parts
+/// (see [`revision_parts`]) of 16 bits each packed from the most significant
+/// end, so that larger numbers are always newer revisions. Returns `None`
+/// for revisions that don't fit.
+pub(super) fn revision_number(revision: &str) -> Option<u64> {
+    let parts = revision_parts(revision)?;
+    if parts.len() > 4 {
+        return None;
+    }
+
+    let mut number = 0;
+    for (i, part) in parts.into_iter().enumerate() {
+        let part = u16::try_from(part).ok()?;
+        number |= (part as u64) << (48 - 16 * i);
+    }
+    Some(number)
+}
+
+fn load_index(source: &mut dyn DictionarySource) -> Result<Option<IndexInfo>, ConvertError> {
+    if !source.contains("index.json") {
+        return Ok(None);
+    }
+
+    let path = source.display_path("index.json");
+    let content = source.read("index.json")?;
+    let value = serde_json::from_slice::<serde_json::Value>(&content)
+        .map_err(|source| ConvertError::Json { path: path.clone(), source })?;
+
+    value.as_object()
+        .ok_or(SchemaError::ExpectedObject("index"))
+        .and_then(IndexInfo::from_json)
+        .map(Some)
+        .map_err(|source| ConvertError::Metadata { path, source })
+}
+
+/// Structured content that was kept as is because it was not understood,
+/// counted by tag and attribute name. Unknown style properties are counted
+/// as `style.<name>`.
+#[derive(Debug, Default)]
+pub struct ConversionReport {
+    pub unknown_tags: BTreeMap<String, usize>,
+    pub unknown_attributes: BTreeMap<String, usize>,
+}
+
+impl ConversionReport {
+    pub fn is_empty(&self) -> bool {
+        self.unknown_tags.is_empty() && self.unknown_attributes.is_empty()
+    }
+
+    fn inspect_term(&mut self, term: &Term) {
+        for definition in &term.definitions {
+            if let Definition::StructuredContent(content) = definition {
+                self.inspect_content(content);
+            }
+        }
+    }
+
+    fn inspect_content(&mut self, content: &StructuredContent) {
+        match content {
+            StructuredContent::Text(_) => {}
+            StructuredContent::Array(items) => {
+                for item in items {
+                    self.inspect_content(item);
+                }
+            }
+            StructuredContent::Element { content, attrs, .. } => {
+                self.inspect_attributes(attrs);
+                if let Some(content) = content {
+                    self.inspect_content(content);
+                }
+            }
+            StructuredContent::UnknownElement { tag, content, attrs } => {
+                *self.unknown_tags.entry(tag.clone()).or_default() += 1;
+                self.inspect_attributes(attrs);
+                if let Some(content) = content {
+                    self.inspect_content(content);
+                }
+            }
+        }
+    }
+
+    fn inspect_attributes(&mut self, attrs: &Attributes) {
+        for (name, value) in attrs.extra.iter().flatten() {
+            if name == "style" && let Ok(serde_json::Value::Object(style)) = serde_json::from_str(value) {
+                for property in style.keys() {
+                    *self.unknown_attributes.entry(format!("style.{}", property)).or_default() += 1;
+                }
+            } else {
+                *self.unknown_attributes.entry(name.clone()).or_default() += 1;
+            }
+        }
+    }
+}
+
+/// Converts a yomitan dictionary, either a zip archive or a directory it was
+/// extracted to, into a tomochan container at `dst`.
+pub fn convert_yomitan_dictionary(src: &str, dst: &str, options: &ConvertOptions) -> Result<ConversionReport, ConvertError> {
+    let (report, converted) = convert_uncommitted(src, dst, options)?;
+    converted.commit()
+        .map_err(|source| ConvertError::Io { path: dst.into(), source })?;
+    Ok(report)
+}
+
+/// Converts into a temporary file next to `dst`, which only replaces `dst`
+/// once committed, so a failed conversion never touches an existing file.
+pub(crate) fn convert_uncommitted(src: &str, dst: &str, options: &ConvertOptions) -> Result<(ConversionReport, AtomicFile), ConvertError> {
+    let mut source = open_source(src)?;
+    let source = source.as_mut();
+    let info = load_index(source)?;
+    let meta = options.container_meta(info.as_ref())?;
     let mut mapping: Vec<(QueryKindKey, String, u64)> = Vec::new();
-    let mut store = StoreBuilder::new()?;
+    let mut store = match &options.store_dictionary {
+        Some(dictionary) => StoreBuilder::with_dictionary(&options.store, dictionary.clone())?,
+        None => StoreBuilder::with_options(&options.store)?,
+    };
+    let mut raw = RawBuilder::new();
+    let mut report = ConversionReport::default();
 
     // TODO: why does dict 08/09 have so many empty keys? need to look into fixing importer
 
-    load_typed_banks::<&str, Term>(src_dir, "term_bank_", "Term", &mut store, &mut mapping)?;
-    load_typed_banks::<&str, Kanji>(src_dir, "kanji_bank_", "Kanji", &mut store, &mut mapping)?;
-    load_typed_banks::<&str, Tag>(src_dir, "tag_bank_", "Tag", &mut store, &mut mapping)?;
-    load_typed_banks::<&str, TermMeta>(src_dir, "term_meta_bank_", "Term meta", &mut store, &mut mapping)?;
-    load_typed_banks::<&str, KanjiMeta>(src_dir, "kanji_meta_bank_", "Kanji meta", &mut store, &mut mapping)?;
-    import_files(src_dir, src_dir, &mut store, &mut mapping)?;
+    load_typed_banks::<Term>(source, "term_bank_", "Term", &mut store, &mut mapping, |term| report.inspect_term(term))?;
+    load_typed_banks::<Kanji>(source, "kanji_bank_", "Kanji", &mut store, &mut mapping, |_| {})?;
+    load_typed_banks::<Tag>(source, "tag_bank_", "Tag", &mut store, &mut mapping, |_| {})?;
+    load_typed_banks::<TermMeta>(source, "term_meta_bank_", "Term meta", &mut store, &mut mapping, |_| {})?;
+    load_typed_banks::<KanjiMeta>(source, "kanji_meta_bank_", "Kanji meta", &mut store, &mut mapping, |_| {})?;
+    import_files(source, &mut store, &mut raw, &mut mapping)?;
 
     let fst = DictionaryIndex::build(mapping)?.as_bytes().to_vec();
+    let store_options = *store.options();
     let store = store.finalize()?;
+    let raw = raw.finalize();
 
     let mut encoded: Vec<u8> = bincode::encode_to_vec(
         DictionaryHeader {
             fst_len: fst.len() as u64,
-            store_len: store.len() as u64,
+            store_len: store.data.len() as u64,
+            raw_len: raw.len() as u64,
+            info,
+            store_options,
+            store_dictionary: store.dictionary,
         },
         BINCODE_CONFIG
-    ).map_err(|e| format!("Failed to encode package: {}", e))?;
+    )?;
 
     encoded.extend(fst);
-    encoded.extend(store);
+    encoded.extend(store.data);
+    encoded.extend(raw);
     
-    let mut file = File::create(dst)
-        .map_err(|e| format!("Failed to open package file: {}", e))?;
+    let mut file = AtomicFile::create(dst)
+        .map_err(|source| ConvertError::Io { path: dst.into(), source })?;
 
-    write_container::<Dictionary, _>(&mut file, meta, &encoded)
-        .map_err(|e| format!("Failed to write package file: {}", e))?;
+    write_container::<Dictionary, _>(file.file(), meta, &encoded)?;
 
-    Ok(())
-}
\ No newline at end of file
+    Ok((report, file))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::format::test_util::{build_dictionary, scratch_dir, term};
+    use std::fs;
+
+    #[test]
+    fn revisions_compare_part_by_part() {
+        assert_eq!(revision_parts("2025.01.31.10"), Some(vec![2025, 1, 31, 10]));
+        assert_eq!(revision_parts("2025-01-31"), Some(vec![2025, 1, 31]));
+        assert_eq!(revision_parts("1.0.0"), Some(vec![1]));
+        assert_eq!(revision_parts("jitendex-2025-01-31"), None);
+        assert_eq!(revision_parts(""), None);
+
+        assert!(revision_parts("2025.02.01.0") > revision_parts("2025.01.31.10"));
+        assert!(revision_parts("2") > revision_parts("1.10"));
+    }
+
+    #[test]
+    fn revision_numbers_are_monotonic() {
+        let ordered = ["1", "1.10", "2", "2025.01.31.10", "2025.02.01.0", "2025.02.01.1"];
+        let numbers: Vec<u64> = ordered.iter().map(|revision| revision_number(revision).unwrap()).collect();
+        assert!(numbers.windows(2).all(|pair| pair[0] < pair[1]), "{:?}", numbers);
+
+        assert_eq!(revision_number("2025-01-31T12:34:56.123456"), None);
+        assert_eq!(revision_number("1.2.3.4.5"), None);
+        assert_eq!(revision_number("70000"), None);
+    }
+
+    #[test]
+    fn failed_conversions_leave_the_destination_alone() {
+        let dir = scratch_dir("convert-atomic");
+        let dst = build_dictionary(&dir, "dict.tomo", "Dict", &[term("犬", "いぬ", "dog")], &[], &ConvertOptions::default());
+        let installed = fs::read(&dst).unwrap();
+
+        let src = dir.join("broken");
+        fs::create_dir_all(&src).unwrap();
+        fs::write(src.join("index.json"), r#"{"title": "Dict", "revision": "2", "format": 3}"#).unwrap();
+        fs::write(src.join("term_bank_1.json"), "[[").unwrap();
+
+        let result = convert_yomitan_dictionary(src.to_str().unwrap(), dst.to_str().unwrap(), &ConvertOptions::default());
+        assert!(matches!(result, Err(ConvertError::Json { .. })));
+        assert_eq!(fs::read(&dst).unwrap(), installed);
+        assert!(!dir.join("dict.tomo.tmp").exists());
+
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn reports_unknown_tags_and_attributes() {
+        let dir = scratch_dir("convert-report");
+        let src = dir.join("src");
+        fs::create_dir_all(&src).unwrap();
+        let content = serde_json::json!({ "type": "structured-content", "content": [
+            { "tag": "marquee", "content": { "tag": "marquee", "content": "x" } },
+            { "tag": "span", "colSpan": "2", "onclick": "y", "style": { "textGlow": "red", "fontWeight": "bold" }, "content": "z" },
+            { "tag": "div", "data": { "count": 1 }, "onclick": "y" },
+        ] });
+        let terms = serde_json::json!([["犬", "いぬ", "", "", 0, [content], 0, ""]]);
+        fs::write(src.join("index.json"), r#"{"title": "Dict", "revision": "1", "format": 3}"#).unwrap();
+        fs::write(src.join("term_bank_1.json"), terms.to_string()).unwrap();
+
+        crate::format::container::allow_dev_version(true);
+        let dst = dir.join("dict.tomo");
+        let report = convert_yomitan_dictionary(src.to_str().unwrap(), dst.to_str().unwrap(), &ConvertOptions::default()).unwrap();
+
+        let counts = |map: &BTreeMap<String, usize>| map.iter().map(|(k, &v)| (k.clone(), v)).collect::<Vec<_>>();
+        assert_eq!(counts(&report.unknown_tags), [("marquee".to_string(), 2)]);
+        assert_eq!(counts(&report.unknown_attributes), [
+            ("colSpan".to_string(), 1),
+            ("data".to_string(), 1),
+            ("onclick".to_string(), 2),
+            ("style.textGlow".to_string(), 1),
+        ]);
+
+        let _ = fs::remove_dir_all(dir);
+    }
+}
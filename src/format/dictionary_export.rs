@@ -0,0 +1,173 @@
+use super::types::{Queryable, QueryKindKey};
+use super::container::ContainerMeta;
+use super::dictionary::Dictionary;
+use crate::schema::{Term, Tag, Kanji, KanjiMeta, TermMeta, JsonSerializable};
+use crate::schema::DictionaryIndex as IndexInfo;
+use crate::error::{ExportError, StoreError};
+use std::io::{Seek, Write};
+use zip::ZipWriter;
+use zip::write::SimpleFileOptions;
+use zip::CompressionMethod;
+
+/// Number of entries per bank file, the same as yomitan's own exports.
+const BANK_SIZE: usize = 10_000;
+
+fn export_banks<T, W>(
+    dictionary: &Dictionary,
+    prefix: &str,
+    zip: &mut ZipWriter<W>,
+) -> Result<(), ExportError>
+where
+    T: Queryable + JsonSerializable + bincode::Decode<()>,
+    W: Write + Seek,
+{
+    let offsets = dictionary.index.offsets(T::KIND);
+    let options = SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+
+    for (i, chunk) in offsets.chunks(BANK_SIZE).enumerate() {
+        let rows = chunk.iter()
+            .map(|&offset| dictionary.store.get::<T>(offset).map(|entry| entry.to_json_array()))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        zip.start_file(format!("{}{}.json", prefix, i + 1), options)?;
+        serde_json::to_writer(&mut *zip, &rows)?;
+    }
+
+    Ok(())
+}
+
+/// Writes a dictionary as a yomitan (format 3) dictionary archive: bank
+/// files for every kind of entry, media files under their original paths,
+/// and an `index.json`. The `index.json` is the one the dictionary was
+/// converted from if it was kept, or one built from `meta` otherwise.
+pub fn export_yomitan_dictionary<W: Write + Seek>(
+    dictionary: &Dictionary,
+    meta: &ContainerMeta,
+    writer: W,
+) -> Result<(), ExportError> {
+    let mut zip = ZipWriter::new(writer);
+
+    let mut index = dictionary.info.clone().unwrap_or_else(|| IndexInfo {
+        title: meta.name.clone(),
+        revision: meta.revision_name.clone(),
+        minimum_yomitan_version: None,
+        sequenced: false,
+        format: 3,
+        author: None,
+        is_updatable: false,
+        index_url: None,
+        download_url: None,
+        url: None,
+        description: None,
+        attribution: None,
+        source_language: None,
+        target_language: None,
+        frequency_mode: None,
+        tag_meta: None,
+    });
+    // banks are always written in the current format
+    index.format = 3;
+
+    let options = SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+    zip.start_file("index.json", options)?;
+    serde_json::to_writer_pretty(&mut zip, &index.to_json())?;
+
+    export_banks::<Term, _>(dictionary, "term_bank_", &mut zip)?;
+    export_banks::<TermMeta, _>(dictionary, "term_meta_bank_", &mut zip)?;
+    export_banks::<Kanji, _>(dictionary, "kanji_bank_", &mut zip)?;
+    export_banks::<KanjiMeta, _>(dictionary, "kanji_meta_bank_", &mut zip)?;
+    export_banks::<Tag, _>(dictionary, "tag_bank_", &mut zip)?;
+
+    // media is usually compressed already
+    let options = SimpleFileOptions::default().compression_method(CompressionMethod::Stored);
+    for path in dictionary.index.keys(QueryKindKey::File).map_err(StoreError::Index)? {
+        let Some(data) = dictionary.find_file(&path)? else {
+            continue;
+        };
+
+        zip.start_file(path.as_str(), options)?;
+        zip.write_all(&data)?;
+    }
+
+    zip.finish()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::format::container::{allow_dev_version, open_container_with_header};
+    use crate::format::dictionary_convert::{ConvertOptions, convert_yomitan_dictionary};
+    use crate::format::test_util::scratch_dir;
+    use crate::format::types::LookupMode;
+    use serde_json::{Value, json};
+    use std::fs::{self, File};
+    use std::io::Read;
+    use std::path::Path;
+    use zip::ZipArchive;
+
+    fn terms() -> Value {
+        json!([
+            ["食べる", "たべる", "v1", "v1", 10, ["to eat", {
+                "type": "structured-content",
+                "content": [
+                    { "tag": "span", "lang": 5, "content": "typed" },
+                    { "tag": "table", "content": { "tag": "tr", "content": { "tag": "td", "colSpan": 70000, "content": "cell" } } },
+                    { "tag": "div", "data": { "kind": "note", "count": 1 }, "style": { "fontWeight": "bold", "madeUp": "x" }, "content": "data" },
+                    { "tag": "marquee", "behavior": "alternate", "content": "unknown" },
+                    { "tag": "img", "path": "img/a.png", "width": 1.5 },
+                ],
+            }], 1, ""],
+            ["ラーメン", "", "n", "", 0.5, ["ramen"], 2, "common"],
+        ])
+    }
+
+    fn write_source(dir: &Path) {
+        let index = json!({ "title": "Test", "revision": "2025.01.31.1", "format": 3 });
+        fs::write(dir.join("index.json"), index.to_string()).unwrap();
+        fs::write(dir.join("term_bank_1.json"), terms().to_string()).unwrap();
+        fs::write(dir.join("tag_bank_1.json"), json!([["v1", "partOfSpeech", 0, "Ichidan verb", 0]]).to_string()).unwrap();
+        fs::write(dir.join("img/a.png"), b"\x89PNG\r\n\x1a\n not really an image").unwrap();
+    }
+
+    fn convert_and_export(src: &Path, tomo: &Path, zip: &Path) {
+        convert_yomitan_dictionary(src.to_str().unwrap(), tomo.to_str().unwrap(), &ConvertOptions::default()).unwrap();
+        let (header, dictionary) = open_container_with_header::<Dictionary>(tomo.to_str().unwrap(), true).unwrap();
+        export_yomitan_dictionary(&dictionary, &header.meta, File::create(zip).unwrap()).unwrap();
+    }
+
+    fn read_entry(archive: &mut ZipArchive<File>, name: &str) -> Vec<u8> {
+        let mut data = Vec::new();
+        archive.by_name(name).unwrap().read_to_end(&mut data).unwrap();
+        data
+    }
+
+    #[test]
+    fn convert_export_round_trip() {
+        allow_dev_version(true);
+        let dir = scratch_dir("export");
+        let src = dir.join("src");
+        fs::create_dir_all(src.join("img")).unwrap();
+        write_source(&src);
+
+        convert_and_export(&src, &dir.join("first.tomo"), &dir.join("first.zip"));
+        convert_and_export(&dir.join("first.zip"), &dir.join("second.tomo"), &dir.join("second.zip"));
+
+        for name in ["first.zip", "second.zip"] {
+            let mut archive = ZipArchive::new(File::open(dir.join(name)).unwrap()).unwrap();
+            let exported: Value = serde_json::from_slice(&read_entry(&mut archive, "term_bank_1.json")).unwrap();
+            assert_eq!(exported, terms(), "terms of {}", name);
+
+            let index: Value = serde_json::from_slice(&read_entry(&mut archive, "index.json")).unwrap();
+            assert_eq!(index["revision"], "2025.01.31.1");
+            assert_eq!(read_entry(&mut archive, "img/a.png"), fs::read(src.join("img/a.png")).unwrap());
+        }
+
+        // kana-only headwords have no separate reading, but are still found by it
+        let (_, dictionary) = open_container_with_header::<Dictionary>(dir.join("second.tomo").to_str().unwrap(), true).unwrap();
+        let found = dictionary.find_terms_by("ラーメン", LookupMode::Reading).unwrap();
+        assert_eq!(found.iter().map(|term| term.term.as_str()).collect::<Vec<_>>(), ["ラーメン"]);
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,270 @@
+//! Access to the files of a yomitan dictionary, either extracted to a
+//! directory or still inside the zip archive it was distributed as.
+//!
+//! Paths are relative to the root of the dictionary (the directory holding
+//! `index.json`) and always use `/` as the separator, so media keys are the
+//! same whichever way the dictionary was imported.
+
+use crate::error::ConvertError;
+use std::fs;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use zip::ZipArchive;
+
+/// Largest archive entry that will be read. Bank files are split well below
+/// this, so anything larger is most likely a corrupt or malicious archive.
+const MAX_ENTRY_SIZE: u64 = 1 << 30;
+
+pub(super) trait DictionarySource {
+    /// Paths of every file in the dictionary, sorted.
+    fn files(&self) -> &[String];
+
+    fn read(&mut self, path: &str) -> Result<Vec<u8>, ConvertError>;
+
+    /// Location of `path` for error messages.
+    fn display_path(&self, path: &str) -> PathBuf;
+
+    fn contains(&self, path: &str) -> bool {
+        self.files().binary_search_by(|file| file.as_str().cmp(path)).is_ok()
+    }
+}
+
+/// Opens `path` as a zip archive if it is a file, or as an extracted
+/// dictionary if it is a directory.
+pub(super) fn open_source(path: &str) -> Result<Box<dyn DictionarySource>, ConvertError> {
+    let metadata = fs::metadata(path)
+        .map_err(|source| ConvertError::Io { path: path.into(), source })?;
+
+    if metadata.is_dir() {
+        Ok(Box::new(DirectorySource::open(path)?))
+    } else {
+        Ok(Box::new(ZipSource::open(path)?))
+    }
+}
+
+pub(super) struct DirectorySource {
+    root: PathBuf,
+    files: Vec<String>,
+}
+
+impl DirectorySource {
+    pub(super) fn open(root: &str) -> Result<Self, ConvertError> {
+        let root = PathBuf::from(root);
+        let mut files = Vec::new();
+        Self::list(&root, "", &mut files)?;
+        files.sort();
+
+        Ok(Self { root, files })
+    }
+
+    fn list(dir: &Path, prefix: &str, files: &mut Vec<String>) -> Result<(), ConvertError> {
+        let entries = fs::read_dir(dir)
+            .map_err(|source| ConvertError::Io { path: dir.into(), source })?;
+
+        for entry in entries {
+            let entry = entry.map_err(|source| ConvertError::Io { path: dir.into(), source })?;
+            let path = entry.path();
+            let name = path.file_name()
+                .and_then(|n| n.to_str())
+                .ok_or_else(|| ConvertError::InvalidPath(path.clone()))?;
+            let rel_path = format!("{}{}", prefix, name);
+
+            // symlinked directories are not followed, they could point back
+            // up the tree and never end
+            let file_type = entry.file_type()
+                .map_err(|source| ConvertError::Io { path: path.clone(), source })?;
+            if file_type.is_dir() {
+                Self::list(&path, &format!("{}/", rel_path), files)?;
+            } else if path.is_file() {
+                files.push(rel_path);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl DictionarySource for DirectorySource {
+    fn files(&self) -> &[String] {
+        &self.files
+    }
+
+    fn read(&mut self, path: &str) -> Result<Vec<u8>, ConvertError> {
+        let full_path = self.display_path(path);
+        fs::read(&full_path).map_err(|source| ConvertError::Io { path: full_path, source })
+    }
+
+    fn display_path(&self, path: &str) -> PathBuf {
+        self.root.join(path)
+    }
+}
+
+pub(super) struct ZipSource {
+    archive_path: PathBuf,
+    archive: ZipArchive<File>,
+    /// Directory inside the archive holding `index.json`, empty if it is at
+    /// the root. Some dictionaries are zipped with their enclosing folder.
+    root: String,
+    files: Vec<String>,
+    /// [`MAX_ENTRY_SIZE`], only lowered by tests.
+    max_entry_size: u64,
+}
+
+impl ZipSource {
+    pub(super) fn open(path: &str) -> Result<Self, ConvertError> {
+        let archive_path = PathBuf::from(path);
+        let file = File::open(&archive_path)
+            .map_err(|source| ConvertError::Io { path: archive_path.clone(), source })?;
+        let archive = ZipArchive::new(file)
+            .map_err(|source| ConvertError::Zip { path: archive_path.clone(), source })?;
+
+        // names are stored with / separators, but some tools write \ anyway
+        let names: Vec<String> = archive.file_names()
+            .filter(|name| !name.ends_with('/') && !name.ends_with('\\'))
+            .map(|name| name.replace('\\', "/"))
+            .collect();
+
+        let root = if names.iter().any(|name| name == "index.json") {
+            String::new()
+        } else {
+            let mut roots = names.iter().filter_map(|name| name.strip_suffix("index.json").filter(|dir| {
+                dir.ends_with('/') && !dir[..dir.len() - 1].contains('/')
+            }));
+            match (roots.next(), roots.next()) {
+                (Some(root), None) => root.to_string(),
+                _ => String::new(),
+            }
+        };
+
+        let mut files: Vec<String> = names.into_iter()
+            .filter_map(|name| name.strip_prefix(root.as_str()).map(String::from))
+            .filter(|name| !name.is_empty())
+            .collect();
+        files.sort();
+
+        Ok(Self { archive_path, archive, root, files, max_entry_size: MAX_ENTRY_SIZE })
+    }
+}
+
+impl DictionarySource for ZipSource {
+    fn files(&self) -> &[String] {
+        &self.files
+    }
+
+    fn read(&mut self, path: &str) -> Result<Vec<u8>, ConvertError> {
+        let name = format!("{}{}", self.root, path);
+        let display_path = self.display_path(path);
+        let index = self.archive.index_for_name(&name)
+            .or_else(|| self.archive.index_for_name(&name.replace('/', "\\")))
+            .ok_or_else(|| ConvertError::Zip { path: display_path.clone(), source: zip::result::ZipError::FileNotFound })?;
+
+        let entry = self.archive.by_index(index)
+            .map_err(|source| ConvertError::Zip { path: display_path.clone(), source })?;
+
+        // the stored size can not be trusted, so it is not used to allocate,
+        // and reading stops once the limit is passed
+        if entry.size() > self.max_entry_size {
+            return Err(ConvertError::EntryTooLarge(display_path));
+        }
+
+        let mut data = Vec::new();
+        entry.take(self.max_entry_size + 1).read_to_end(&mut data)
+            .map_err(|source| ConvertError::Io { path: display_path.clone(), source })?;
+        if data.len() as u64 > self.max_entry_size {
+            return Err(ConvertError::EntryTooLarge(display_path));
+        }
+
+        Ok(data)
+    }
+
+    fn display_path(&self, path: &str) -> PathBuf {
+        self.archive_path.join(format!("{}{}", self.root, path))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::format::test_util::{scratch_dir, write_zip};
+
+    fn open_zip(name: &str, files: &[(&str, &[u8])]) -> (PathBuf, ZipSource) {
+        let dir = scratch_dir(name);
+        let path = dir.join("dict.zip");
+        write_zip(&path, files);
+        let source = ZipSource::open(path.to_str().unwrap()).unwrap();
+        (dir, source)
+    }
+
+    #[test]
+    fn zip_sources_find_a_nested_root() {
+        let (dir, mut source) = open_zip("source-nested", &[
+            ("dict/index.json", b"{}"),
+            ("dict/term_bank_1.json", b"[]"),
+            ("dict/img/a.png", b"png"),
+            ("readme.txt", b"hi"),
+        ]);
+
+        assert_eq!(source.files(), ["img/a.png", "index.json", "term_bank_1.json"]);
+        assert_eq!(source.read("img/a.png").unwrap(), b"png");
+        assert!(source.display_path("index.json").ends_with("dict.zip/dict/index.json"));
+
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn zip_sources_ignore_ambiguous_roots() {
+        let (dir, source) = open_zip("source-ambiguous", &[
+            ("a/index.json", b"{}"),
+            ("b/index.json", b"{}"),
+        ]);
+
+        assert_eq!(source.files(), ["a/index.json", "b/index.json"]);
+
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn zip_sources_accept_backslash_names() {
+        let (dir, mut source) = open_zip("source-backslash", &[
+            ("dict\\index.json", b"{}"),
+            ("dict\\img\\a.png", b"png"),
+        ]);
+
+        assert_eq!(source.files(), ["img/a.png", "index.json"]);
+        assert_eq!(source.read("img/a.png").unwrap(), b"png");
+
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn zip_sources_refuse_large_entries() {
+        let (dir, mut source) = open_zip("source-large", &[
+            ("index.json", b"{}"),
+            ("term_bank_1.json", b"[1, 2, 3]"),
+        ]);
+        source.max_entry_size = 4;
+
+        assert_eq!(source.read("index.json").unwrap(), b"{}");
+        assert!(matches!(source.read("term_bank_1.json"), Err(ConvertError::EntryTooLarge(_))));
+
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn directory_sources_do_not_follow_symlinked_directories() {
+        let dir = scratch_dir("source-symlink");
+        fs::create_dir_all(dir.join("img")).unwrap();
+        fs::write(dir.join("index.json"), b"{}").unwrap();
+        fs::write(dir.join("img/a.png"), b"png").unwrap();
+        std::os::unix::fs::symlink(&dir, dir.join("img/loop")).unwrap();
+        std::os::unix::fs::symlink(dir.join("index.json"), dir.join("linked.json")).unwrap();
+
+        let mut source = DirectorySource::open(dir.to_str().unwrap()).unwrap();
+        assert_eq!(source.files(), ["img/a.png", "index.json", "linked.json"]);
+        assert_eq!(source.read("linked.json").unwrap(), b"{}");
+
+        let _ = fs::remove_dir_all(dir);
+    }
+}
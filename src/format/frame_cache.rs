@@ -0,0 +1,197 @@
+//! Cache of decompressed store frames.
+//!
+//! Entries are read by decompressing the zstd frame holding them, and lookups
+//! that land in the same frame (such as the many lookups made while scanning
+//! a sentence) would otherwise decompress it again every time. A
+//! [`FrameCache`] keeps the most recently used frames up to a memory budget.
+//! It can be given to a single store, or shared by every dictionary of a
+//! collection, in which case they share the budget as well.
+
+use lru::LruCache;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+static NEXT_STORE_ID: AtomicU64 = AtomicU64::new(0);
+
+/// Identifies a store among the others sharing a cache.
+pub(crate) fn next_store_id() -> u64 {
+    NEXT_STORE_ID.fetch_add(1, Ordering::Relaxed)
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FrameCacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    /// Number of frames currently cached.
+    pub frames: usize,
+    /// Decompressed size of the cached frames.
+    pub bytes: usize,
+    pub budget: usize,
+}
+
+impl FrameCacheStats {
+    /// Fraction of frame reads served from the cache.
+    pub fn hit_rate(&self) -> f64 {
+        match self.hits + self.misses {
+            0 => 0.0,
+            total => self.hits as f64 / total as f64,
+        }
+    }
+}
+
+struct CacheState {
+    frames: LruCache<(u64, u32), Arc<[u8]>>,
+    bytes: usize,
+    hits: u64,
+    misses: u64,
+}
+
+pub struct FrameCache {
+    budget: usize,
+    state: Mutex<CacheState>,
+}
+
+impl FrameCache {
+    /// Cache holding at most `budget` bytes of decompressed frames. Frames
+    /// larger than the whole budget are never cached.
+    pub fn new(budget: usize) -> Self {
+        Self {
+            budget,
+            state: Mutex::new(CacheState {
+                frames: LruCache::unbounded(),
+                bytes: 0,
+                hits: 0,
+                misses: 0,
+            }),
+        }
+    }
+
+    pub fn budget(&self) -> usize {
+        self.budget
+    }
+
+    pub fn stats(&self) -> FrameCacheStats {
+        let state = self.lock();
+        FrameCacheStats {
+            hits: state.hits,
+            misses: state.misses,
+            frames: state.frames.len(),
+            bytes: state.bytes,
+            budget: self.budget,
+        }
+    }
+
+    /// Drops every cached frame. Statistics are kept.
+    pub fn clear(&self) {
+        let mut state = self.lock();
+        state.frames.clear();
+        state.bytes = 0;
+    }
+
+    fn lock(&self) -> MutexGuard<'_, CacheState> {
+        // every update leaves the state consistent, so a poisoned state is still whole
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Frame `frame` of store `store`, decompressed with `load` if it is not
+    /// cached. The lock is not held while loading, so a frame missed by two
+    /// threads at once may be decompressed twice.
+    pub(crate) fn get_or_load<E>(
+        &self,
+        store: u64,
+        frame: u32,
+        load: impl FnOnce() -> Result<Vec<u8>, E>,
+    ) -> Result<Arc<[u8]>, E> {
+        {
+            let mut state = self.lock();
+            if let Some(data) = state.frames.get(&(store, frame)) {
+                let data = Arc::clone(data);
+                state.hits += 1;
+                return Ok(data);
+            }
+            state.misses += 1;
+        }
+
+        let data: Arc<[u8]> = load()?.into();
+
+        if data.len() <= self.budget {
+            let mut state = self.lock();
+            state.bytes += data.len();
+            if let Some(old) = state.frames.put((store, frame), Arc::clone(&data)) {
+                state.bytes -= old.len();
+            }
+            while state.bytes > self.budget {
+                let Some((_, evicted)) = state.frames.pop_lru() else {
+                    break;
+                };
+                state.bytes -= evicted.len();
+            }
+        }
+
+        Ok(data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load(cache: &FrameCache, frame: u32, len: usize) -> Arc<[u8]> {
+        cache.get_or_load(0, frame, || Ok::<_, ()>(vec![frame as u8; len])).unwrap()
+    }
+
+    #[test]
+    fn evicts_least_recently_used() {
+        let cache = FrameCache::new(300);
+        load(&cache, 0, 100);
+        load(&cache, 1, 100);
+        load(&cache, 2, 100);
+        // frame 0 becomes the most recently used, so frame 1 is evicted
+        load(&cache, 0, 100);
+        load(&cache, 3, 100);
+
+        let stats = cache.stats();
+        assert_eq!((stats.frames, stats.bytes), (3, 300));
+
+        let misses = stats.misses;
+        for frame in [0, 2, 3] {
+            load(&cache, frame, 100);
+        }
+        assert_eq!(cache.stats().misses, misses);
+        load(&cache, 1, 100);
+        assert_eq!(cache.stats().misses, misses + 1);
+    }
+
+    #[test]
+    fn zero_budget_caches_nothing() {
+        let cache = FrameCache::new(0);
+        assert_eq!(&load(&cache, 0, 10)[..], &[0; 10]);
+        assert_eq!(&load(&cache, 0, 10)[..], &[0; 10]);
+
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.misses, stats.frames, stats.bytes), (0, 2, 0, 0));
+    }
+
+    #[test]
+    fn counts_hits_and_misses() {
+        let cache = FrameCache::new(1 << 10);
+        assert_eq!(cache.stats().hit_rate(), 0.0);
+
+        load(&cache, 0, 10);
+        load(&cache, 0, 10);
+        load(&cache, 0, 10);
+        load(&cache, 1, 10);
+        // frames of other stores are separate
+        cache.get_or_load(1, 0, || Ok::<_, ()>(vec![0; 10])).unwrap();
+
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.misses, stats.frames, stats.bytes), (2, 3, 3, 30));
+        assert_eq!(stats.hit_rate(), 0.4);
+
+        // failed loads count as misses and cache nothing
+        assert!(cache.get_or_load(0, 2, || Err(())).is_err());
+        cache.clear();
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.misses, stats.frames, stats.bytes), (2, 4, 0, 0));
+    }
+}
@@ -0,0 +1,393 @@
+//! Log of containers whose payload hash has already been verified.
+//!
+//! Hashing the payload of a large dictionary takes seconds, which is too slow
+//! to do every time it is opened. Once a container has been verified, the
+//! hash log records its size, modification time and inode along with a hash
+//! of the whole file, computed while verifying it. Later opens can then skip
+//! hashing the payload and the format specific checks, depending on the
+//! [`HashLogPolicy`] of the log.
+//!
+//! A log is a JSON file opened as a [`HashLogFile`] and passed to
+//! [`open_container_logged`](super::container::open_container_logged) or
+//! [`DictionaryCollection::set_hash_log`](super::collection::DictionaryCollection::set_hash_log).
+//! Without one, every verified open hashes the payload.
+
+use super::atomic_file;
+use super::container::ContainerFileInfo;
+use crate::error::{ConfigError, ContainerError};
+use serde::{Serialize, Deserialize};
+use sha2::{Sha256, Digest};
+use std::collections::BTreeMap;
+use std::fs;
+use std::fs::{File, Metadata};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// When a logged container is trusted without being verified again.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HashLogPolicy {
+    /// Trust the file as long as its size, modification time and inode are
+    /// unchanged.
+    Metadata,
+    /// Always hash the whole file, but skip the format specific checks if
+    /// the hash is the one that was logged.
+    Rehash,
+    /// Like [`HashLogPolicy::Metadata`], but verify the file again once the
+    /// entry is older than the given duration.
+    MaxAge(Duration),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HashLogEntry {
+    pub len: u64,
+    pub modified_secs: u64,
+    pub modified_nanos: u32,
+    /// Always 0 on platforms without inodes.
+    pub inode: u64,
+    /// Hash of the whole file, header included, as it was verified.
+    pub file_sha256: [u8; 32],
+    /// When the file was verified, in seconds since the Unix epoch.
+    #[serde(default)]
+    pub verified_secs: u64,
+}
+
+impl HashLogEntry {
+    /// Returns `None` if the file system does not report modification times,
+    /// in which case the file can't be logged.
+    fn new(metadata: &Metadata, file_sha256: [u8; 32]) -> Option<Self> {
+        let modified = metadata.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+
+        #[cfg(unix)]
+        let inode = std::os::unix::fs::MetadataExt::ino(metadata);
+        #[cfg(not(unix))]
+        let inode = 0;
+
+        Some(Self {
+            len: metadata.len(),
+            modified_secs: modified.as_secs(),
+            modified_nanos: modified.subsec_nanos(),
+            inode,
+            file_sha256,
+            verified_secs: now_secs(),
+        })
+    }
+
+    /// Whether the entry was logged for a file with this metadata.
+    fn matches(&self, metadata: &Metadata) -> bool {
+        Self::new(metadata, self.file_sha256).is_some_and(|entry| {
+            (entry.len, entry.modified_secs, entry.modified_nanos, entry.inode)
+                == (self.len, self.modified_secs, self.modified_nanos, self.inode)
+        })
+    }
+
+    fn is_older_than(&self, max_age: Duration) -> bool {
+        now_secs().saturating_sub(self.verified_secs) > max_age.as_secs()
+    }
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|since| since.as_secs())
+        .unwrap_or(0)
+}
+
+/// Persisted hash log, keyed by canonical path.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct HashLog {
+    pub entries: BTreeMap<String, HashLogEntry>,
+}
+
+impl HashLog {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, ConfigError> {
+        let path = path.as_ref();
+        let json = fs::read_to_string(path)
+            .map_err(|source| ConfigError::Io { path: path.into(), source })?;
+
+        serde_json::from_str(&json)
+            .map_err(|source| ConfigError::Json { path: path.into(), source })
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), ConfigError> {
+        let path = path.as_ref();
+        let json = serde_json::to_vec_pretty(self)
+            .map_err(|source| ConfigError::Json { path: path.into(), source })?;
+
+        atomic_file::write(path, &json)
+            .map_err(|source| ConfigError::Io { path: path.into(), source })
+    }
+}
+
+/// A hash log backed by a file, which is rewritten whenever a container is
+/// logged. Can be shared between threads and collections.
+#[derive(Debug)]
+pub struct HashLogFile {
+    path: PathBuf,
+    policy: HashLogPolicy,
+    log: Mutex<HashLog>,
+    discarded: Option<ConfigError>,
+}
+
+impl HashLogFile {
+    /// Opens the log at `path`, which is created on the first verification
+    /// if it does not exist. The log is only a cache, so one that can't be
+    /// read starts out empty, see [`HashLogFile::discarded`].
+    pub fn open(path: impl AsRef<Path>, policy: HashLogPolicy) -> Self {
+        let path = path.as_ref();
+        let (log, discarded) = match HashLog::load(path) {
+            Ok(log) => (log, None),
+            Err(ConfigError::Io { source, .. }) if source.kind() == io::ErrorKind::NotFound => (HashLog::default(), None),
+            Err(e) => (HashLog::default(), Some(e)),
+        };
+
+        Self { path: path.to_path_buf(), policy, log: Mutex::new(log), discarded }
+    }
+
+    /// Why the log on disk was ignored when it was opened, if it was.
+    pub fn discarded(&self) -> Option<&ConfigError> {
+        self.discarded.as_ref()
+    }
+
+    pub fn policy(&self) -> HashLogPolicy {
+        self.policy
+    }
+
+    pub fn entry(&self, key: &str) -> Option<HashLogEntry> {
+        self.lock().entries.get(key).cloned()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashLog> {
+        // the lock only guards plain map updates, so a poisoned log is still whole
+        self.log.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Whether `entry` lets a file with `metadata` skip hashing.
+    fn trusts(&self, entry: &HashLogEntry, metadata: &Metadata) -> bool {
+        match self.policy {
+            HashLogPolicy::Metadata => entry.matches(metadata),
+            HashLogPolicy::Rehash => false,
+            HashLogPolicy::MaxAge(max_age) => entry.matches(metadata) && !entry.is_older_than(max_age),
+        }
+    }
+
+    /// Records a container that passed every check.
+    pub(crate) fn record(&self, pending: PendingEntry) {
+        let mut log = self.lock();
+        log.entries.insert(pending.key, pending.entry);
+        // the log is only a cache, failing to write it just means verifying
+        // again next time
+        let _ = log.save(&self.path);
+    }
+}
+
+/// Outcome of [`validate_payload`].
+pub(crate) enum Validated {
+    /// The log has the file as verified, no checks are needed.
+    Logged,
+    /// The payload hash was checked. The entry should be recorded once the
+    /// format specific checks pass too.
+    Hashed(Option<PendingEntry>),
+}
+
+pub(crate) struct PendingEntry {
+    key: String,
+    entry: HashLogEntry,
+}
+
+/// Checks the payload of an open container against its header, unless `log`
+/// has it as verified.
+pub(crate) fn validate_payload(
+    log: Option<&HashLogFile>,
+    path: &str,
+    container: &ContainerFileInfo,
+    file: &mut File,
+) -> Result<Validated, ContainerError> {
+    let Some(log) = log else {
+        validate_and_hash(container, file)?;
+        return Ok(Validated::Hashed(None));
+    };
+
+    let metadata = file.metadata()?;
+    let key = fs::canonicalize(path)
+        .map(|path| path.to_string_lossy().into_owned())
+        .unwrap_or_else(|_| path.to_string());
+
+    let logged = log.entry(&key);
+    if logged.as_ref().is_some_and(|entry| log.trusts(entry, &metadata)) {
+        return Ok(Validated::Logged);
+    }
+
+    // hashed without holding the lock, so other containers can be opened meanwhile
+    let file_sha256 = validate_and_hash(container, file)?;
+
+    if log.policy == HashLogPolicy::Rehash && logged.is_some_and(|entry| entry.file_sha256 == file_sha256) {
+        return Ok(Validated::Logged);
+    }
+
+    Ok(Validated::Hashed(
+        HashLogEntry::new(&metadata, file_sha256).map(|entry| PendingEntry { key, entry })
+    ))
+}
+
+/// Checks the payload like [`ContainerFileInfo::validate_payload`], and
+/// hashes the whole file in the same pass.
+fn validate_and_hash(container: &ContainerFileInfo, file: &mut File) -> Result<[u8; 32], ContainerError> {
+    let mut file_hasher = Sha256::new();
+    file.seek(SeekFrom::Start(0))?;
+    io::copy(&mut (&mut *file).take(container.payload_offset), &mut file_hasher)?;
+
+    let mut both = BothHashers { file: file_hasher, payload: Sha256::new() };
+    let copied = io::copy(file, &mut both)?;
+
+    if copied != container.header.payload_length {
+        return Err(ContainerError::PayloadLengthMismatch {
+            expected: container.header.payload_length,
+            found: copied,
+        });
+    }
+    if both.payload.finalize()[..] != container.header.payload_sha256[..] {
+        return Err(ContainerError::PayloadHashMismatch);
+    }
+
+    Ok(both.file.finalize().into())
+}
+
+struct BothHashers {
+    file: Sha256,
+    payload: Sha256,
+}
+
+impl Write for BothHashers {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.file.update(buf);
+        self.payload.update(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::format::dictionary_convert::ConvertOptions;
+    use crate::format::test_util::{build_dictionary, overwrite_in_place, scratch_dir, term};
+    use std::fs::OpenOptions;
+
+    /// Validates like opening a container would, recording the file if it
+    /// was hashed. Returns whether the log had it as verified.
+    fn validate(log: &HashLogFile, path: &str) -> Result<bool, ContainerError> {
+        let mut file = File::open(path)?;
+        let container = ContainerFileInfo::read_container(&mut file)?;
+        match validate_payload(Some(log), path, &container, &mut file)? {
+            Validated::Logged => Ok(true),
+            Validated::Hashed(pending) => {
+                log.record(pending.unwrap());
+                Ok(false)
+            }
+        }
+    }
+
+    /// Flips the last byte without changing the size or modification time.
+    fn tamper_in_place(path: &str) {
+        let last = *fs::read(path).unwrap().last().unwrap();
+        overwrite_in_place(path, SeekFrom::End(-1), &[last ^ 1]);
+    }
+
+    fn dictionary(name: &str) -> (PathBuf, String) {
+        let dir = scratch_dir(name);
+        let path = build_dictionary(&dir, "dict.tomo", "Dict", &[term("犬", "いぬ", "dog")], &[], &ConvertOptions::default());
+        let path = path.to_str().unwrap().to_string();
+        (dir, path)
+    }
+
+    fn key(path: &str) -> String {
+        fs::canonicalize(path).unwrap().to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn metadata_policy_skips_unchanged_files_only() {
+        let (dir, path) = dictionary("hashlog-metadata");
+        let log_path = dir.join("log.json");
+        let log = HashLogFile::open(&log_path, HashLogPolicy::Metadata);
+
+        // miss: the file is hashed and logged with the hash of all of it
+        assert!(!validate(&log, &path).unwrap());
+        let saved = HashLog::load(&log_path).unwrap();
+        assert_eq!(saved.entries[&key(&path)].file_sha256[..], Sha256::digest(fs::read(&path).unwrap())[..]);
+
+        // hit: an unchanged size, modification time and inode skip hashing,
+        // also in a log reopened from the file
+        tamper_in_place(&path);
+        assert!(validate(&log, &path).unwrap());
+        let reopened = HashLogFile::open(&log_path, HashLogPolicy::Metadata);
+        assert!(validate(&reopened, &path).unwrap());
+
+        // invalidation: any other modification time hashes again
+        let modified = fs::metadata(&path).unwrap().modified().unwrap();
+        OpenOptions::new().write(true).open(&path).unwrap().set_modified(modified + Duration::from_secs(1)).unwrap();
+        assert!(matches!(validate(&log, &path), Err(ContainerError::PayloadHashMismatch)));
+
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn rehash_policy_compares_whole_file_hashes() {
+        let (dir, path) = dictionary("hashlog-rehash");
+        let log = HashLogFile::open(dir.join("log.json"), HashLogPolicy::Rehash);
+
+        assert!(!validate(&log, &path).unwrap());
+        assert!(validate(&log, &path).unwrap());
+
+        tamper_in_place(&path);
+        assert!(matches!(validate(&log, &path), Err(ContainerError::PayloadHashMismatch)));
+
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn max_age_policy_verifies_old_entries_again() {
+        let (dir, path) = dictionary("hashlog-max-age");
+        let log_path = dir.join("log.json");
+        let max_age = HashLogPolicy::MaxAge(Duration::from_secs(60 * 60));
+
+        let log = HashLogFile::open(&log_path, max_age);
+        assert!(!validate(&log, &path).unwrap());
+        assert!(validate(&log, &path).unwrap());
+
+        let mut saved = HashLog::load(&log_path).unwrap();
+        saved.entries.get_mut(&key(&path)).unwrap().verified_secs -= 2 * 60 * 60;
+        saved.save(&log_path).unwrap();
+
+        let log = HashLogFile::open(&log_path, max_age);
+        assert!(!validate(&log, &path).unwrap());
+        assert!(validate(&log, &path).unwrap());
+
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn unreadable_logs_start_empty() {
+        let (dir, path) = dictionary("hashlog-truncated");
+        let log_path = dir.join("log.json");
+
+        let log = HashLogFile::open(&log_path, HashLogPolicy::Metadata);
+        assert!(log.discarded().is_none());
+        assert!(!validate(&log, &path).unwrap());
+
+        // a log cut off by a crash is ignored, and replaced by the next record
+        let json = fs::read(&log_path).unwrap();
+        fs::write(&log_path, &json[..json.len() / 2]).unwrap();
+        let log = HashLogFile::open(&log_path, HashLogPolicy::Metadata);
+        assert!(matches!(log.discarded(), Some(ConfigError::Json { .. })));
+        assert!(!validate(&log, &path).unwrap());
+        assert!(HashLog::load(&log_path).unwrap().entries.contains_key(&key(&path)));
+        assert!(!dir.join("log.json.tmp").exists());
+
+        let _ = fs::remove_dir_all(dir);
+    }
+}
@@ -1,8 +1,13 @@
 use super::types::QueryKindKey;
-use fst::{Map, MapBuilder, IntoStreamer, Streamer};
+use super::normalize::index_key;
+use super::automaton::{CompositeKey, Levenshtein, Prefix, Wildcard};
+use crate::error::IndexError;
+use fst::{Automaton, Map, MapBuilder, IntoStreamer, Streamer};
+use fst::automaton::AlwaysMatch;
 use fst::map::OpBuilder;
-use std::fs::File;
 use memmap2::Mmap;
+use std::sync::Arc;
+use std::borrow::Cow;
 
 // TODO: fsts traversal can panic at any point, there is no way to
 // realistically validate the structure, so we will probably need to spawn a
@@ -10,7 +15,7 @@ use memmap2::Mmap;
 
 enum BackingStore {
     Memory(Vec<u8>),
-    Mmap((Mmap, usize, usize)),
+    Mmap((Arc<Mmap>, usize, usize)),
 }
 
 impl AsRef<[u8]> for BackingStore {
@@ -22,6 +27,47 @@ impl AsRef<[u8]> for BackingStore {
     }
 }
 
+/// Pattern for [`DictionaryIndex::search`]. Patterns are width folded the
+/// same way as keys for normalized kinds.
+#[derive(Debug, Clone)]
+pub enum KeyQuery {
+    /// Keys starting with the given text.
+    Prefix(String),
+    /// Keys matching a pattern, where `*` matches any number of characters
+    /// and `?` matches exactly one.
+    Wildcard(String),
+    /// Keys in `start..end`. Missing bounds are unbounded.
+    Range { start: Option<String>, end: Option<String> },
+}
+
+impl KeyQuery {
+    /// Wildcard query if `text` contains `*` or `?`, prefix query otherwise.
+    pub fn from_pattern(text: &str) -> Self {
+        if text.contains(['*', '?']) {
+            KeyQuery::Wildcard(text.to_string())
+        } else {
+            KeyQuery::Prefix(text.to_string())
+        }
+    }
+}
+
+/// One page of distinct keys, in lexicographic order.
+#[derive(Debug, Clone, Default)]
+pub struct KeyPage {
+    pub keys: Vec<String>,
+    /// Set if there are more results. Pass as `after` to get the next page.
+    pub next: Option<String>,
+}
+
+/// Key found by [`DictionaryIndex::fuzzy_search`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FuzzyMatch {
+    pub key: String,
+    /// Number of characters inserted, deleted or substituted to get from
+    /// the query to `key`.
+    pub distance: u32,
+}
+
 pub struct DictionaryIndex {
     fst_map: Map<BackingStore>,
 }
@@ -36,20 +82,38 @@ impl DictionaryIndex {
         composite
     }
 
-    fn destructure_key(key: &[u8]) -> Result<(QueryKindKey, &str, u32), String> {
-        if key.len() < 5 {
-            return Err("malformed key".to_string());
-        }
+    fn destructure_key(key: &[u8]) -> Result<(QueryKindKey, &str, u32), IndexError> {
+        let Some((rest, idx)) = key.split_last_chunk::<4>() else {
+            return Err(IndexError::MalformedKey);
+        };
+        let Some((&kind, rest)) = rest.split_first() else {
+            return Err(IndexError::MalformedKey);
+        };
+        let Some((&0, key_str)) = rest.split_last() else {
+            return Err(IndexError::MalformedKey);
+        };
+
+        let kind = QueryKindKey::from_byte(kind)?;
+        let key_str = std::str::from_utf8(key_str)?;
 
-        let kind = QueryKindKey::from_byte(key[0])?;
-        let key_str = std::str::from_utf8(&key[1..key.len()-5])
-            .map_err(|e| format!("invalid utf8 key: {}", e))?;
-        let idx = u32::from_be_bytes(key[key.len()-4..].try_into().unwrap());
+        Ok((kind, key_str, u32::from_be_bytes(*idx)))
+    }
 
-        Ok((kind, key_str, idx))
+    fn normalize_key(data_type: QueryKindKey, key: &str) -> Cow<'_, str> {
+        if data_type.is_normalized() {
+            Cow::Owned(index_key(key))
+        } else {
+            Cow::Borrowed(key)
+        }
     }
 
-    pub fn build(mut mappings: Vec<(QueryKindKey, String, u64)>) -> Result<Self, String> {
+    pub fn build(mut mappings: Vec<(QueryKindKey, String, u64)>) -> Result<Self, IndexError> {
+        for (kind, key, _) in &mut mappings {
+            if let Cow::Owned(normalized) = Self::normalize_key(*kind, key) {
+                *key = normalized;
+            }
+        }
+
         mappings.sort_by(|a, b| (a.0, &a.1).cmp(&(b.0, &b.1)));
 
         let mut fst_builder = MapBuilder::memory();
@@ -60,17 +124,15 @@ impl DictionaryIndex {
                 reps = 0;
             }
             fst_builder.insert(
-                &Self::make_composite_key(*kind, key, reps),
+                Self::make_composite_key(*kind, key, reps),
                 *offset,
-            ).map_err(|e| format!("Failed to insert key into FST: {} {:#?} {:#?} {} {:#?}", e, prev, (kind, key), reps, &Self::make_composite_key(*kind, key, reps)))?;
+            )?;
             prev = Some((*kind, key));
             reps += 1;
         }
 
-        let fst_bytes = fst_builder.into_inner()
-            .map_err(|e| format!("FST build failed: {}", e))?;
-        let fst_map = Map::new(BackingStore::Memory(fst_bytes))
-            .map_err(|e| format!("FST creation failed: {}", e))?;
+        let fst_bytes = fst_builder.into_inner()?;
+        let fst_map = Map::new(BackingStore::Memory(fst_bytes))?;
 
         Ok(Self { fst_map })
     }
@@ -79,35 +141,28 @@ impl DictionaryIndex {
         self.fst_map.as_fst().as_bytes()
     }
 
-    pub fn from_bytes(bytes: Vec<u8>) -> Result<Self, String> {
-        let fst_map = Map::new(BackingStore::Memory(bytes))
-            .map_err(|e| format!("FST creation failed: {}", e))?;
+    pub fn from_bytes(bytes: Vec<u8>) -> Result<Self, IndexError> {
+        let fst_map = Map::new(BackingStore::Memory(bytes))?;
         Ok(Self { fst_map })
     }
 
-    pub fn load_mmap(path: &str, offset: u64, len: u64) -> Result<Self, String> {
-        let file = File::open(path)
-            .map_err(|e| format!("Failed to open FST file: {}", e))?;
-
+    /// Loads the index from `len` bytes at `offset` of a mapped file.
+    pub fn load_mmap(mmap: Arc<Mmap>, offset: u64, len: u64) -> Result<Self, IndexError> {
         let offset: usize = offset.try_into()
-            .map_err(|_| "dictionary file too large for 32 bit platform")?;
+            .map_err(|_| IndexError::TooLarge)?;
 
         let len: usize = len.try_into()
-            .map_err(|_| "dictionary file too large for 32 bit platform")?;
-        
-        let mmap = unsafe { 
-            Mmap::map(&file)
-                .map_err(|e| format!("Failed to mmap file: {}", e))?
-        };
-        
-        let fst_map = Map::new(BackingStore::Mmap((mmap, offset, offset+len)))
-            .map_err(|e| format!("FST creation failed: {}", e))?;
+            .map_err(|_| IndexError::TooLarge)?;
+
+        let fst_map = Map::new(BackingStore::Mmap((mmap, offset, offset+len)))?;
         
         Ok(Self { fst_map })
     }
 
-    pub fn lookup(&self, data_type: QueryKindKey, key: &str) -> Vec<u64> {
+    pub fn lookup(&self, data_type: QueryKindKey, key: &str) -> Result<Vec<u64>, IndexError> {
         let mut results = Vec::new();
+        let key = Self::normalize_key(data_type, key);
+        let key = key.as_ref();
         
         let mut prefix = Vec::new();
         prefix.push(data_type.as_byte());
@@ -121,8 +176,8 @@ impl DictionaryIndex {
                 break;
             }
             
-            // unwrap is ok b/c checked by verify
-            let (_, found_key, _) = Self::destructure_key(composite_key).unwrap();
+            // verify checks every key, but indexes can be opened without it
+            let (_, found_key, _) = Self::destructure_key(composite_key)?;
             if found_key != key {
                 break;
             }
@@ -130,10 +185,10 @@ impl DictionaryIndex {
             results.push(id_value);
         }
         
-        results
+        Ok(results)
     }
 
-    pub fn keys(&self, data_type: QueryKindKey) -> Vec<String> {
+    pub fn keys(&self, data_type: QueryKindKey) -> Result<Vec<String>, IndexError> {
         let mut keys = Vec::new();
         let type_byte = data_type.as_byte();
         
@@ -146,22 +201,205 @@ impl DictionaryIndex {
                 break;
             }
             
-            // unwrap is ok b/c checked by verify
-            let (_, key, _) = Self::destructure_key(composite_key).unwrap();
+            let (_, key, _) = Self::destructure_key(composite_key)?;
             if last_key.as_deref() != Some(key) {
                 keys.push(key.to_string());
                 last_key = Some(key.to_string());
             }
         }
         
-        keys
+        Ok(keys)
+    }
+
+    /// Offsets of every entry of kind `data_type`, in store order. Entries
+    /// stored under several keys are only listed once.
+    pub fn offsets(&self, data_type: QueryKindKey) -> Vec<u64> {
+        let kind = data_type.as_byte();
+        let mut stream = self.fst_map.range().ge([kind]).lt([kind + 1]).into_stream();
+
+        let mut offsets = Vec::new();
+        while let Some((_, offset)) = stream.next() {
+            offsets.push(offset);
+        }
+
+        offsets.sort_unstable();
+        offsets.dedup();
+        offsets
+    }
+
+    /// Finds up to `limit` distinct keys of kind `data_type` matching
+    /// `query`, starting after the key `after`.
+    pub fn search(
+        &self,
+        data_type: QueryKindKey,
+        query: &KeyQuery,
+        after: Option<&str>,
+        limit: usize,
+    ) -> Result<KeyPage, IndexError> {
+        Self::search_in_collection([self], data_type, query, after, limit)
+    }
+
+    /// Same as [`DictionaryIndex::search`], but merges the keys of several
+    /// indexes. Keys present in more than one index are only returned once.
+    pub fn search_in_collection<'a>(
+        indexes: impl IntoIterator<Item = &'a DictionaryIndex>,
+        data_type: QueryKindKey,
+        query: &KeyQuery,
+        after: Option<&str>,
+        limit: usize,
+    ) -> Result<KeyPage, IndexError> {
+        let indexes: Vec<&DictionaryIndex> = indexes.into_iter().collect();
+        let kind = data_type.as_byte();
+        // `after` is a key from a previous page, but can also come from user
+        // input, so it is folded like the query
+        let after = after.map(|after| Self::normalize_key(data_type, after));
+        let after = after.as_deref();
+
+        match query {
+            KeyQuery::Prefix(prefix) => {
+                let prefix = Self::normalize_key(data_type, prefix);
+                let aut = CompositeKey::new(kind, Prefix::new(&prefix));
+                Self::collect_keys(&indexes, &aut, data_type, Some(&prefix), None, after, limit)
+            }
+            KeyQuery::Wildcard(pattern) => {
+                let pattern = Self::normalize_key(data_type, pattern);
+                let aut = CompositeKey::new(kind, Wildcard::new(&pattern));
+                Self::collect_keys(&indexes, &aut, data_type, None, None, after, limit)
+            }
+            KeyQuery::Range { start, end } => {
+                let start = start.as_deref().map(|start| Self::normalize_key(data_type, start));
+                let end = end.as_deref().map(|end| Self::normalize_key(data_type, end));
+                let aut = CompositeKey::new(kind, AlwaysMatch);
+                Self::collect_keys(&indexes, &aut, data_type, start.as_deref(), end.as_deref(), after, limit)
+            }
+        }
+    }
+
+    fn collect_keys<A: Automaton>(
+        indexes: &[&DictionaryIndex],
+        aut: &A,
+        data_type: QueryKindKey,
+        start: Option<&str>,
+        end: Option<&str>,
+        after: Option<&str>,
+        limit: usize,
+    ) -> Result<KeyPage, IndexError>
+    where
+        A::State: Clone,
+    {
+        let kind = data_type.as_byte();
+
+        let mut lower = vec![kind];
+        lower.extend_from_slice(start.unwrap_or_default().as_bytes());
+        if let Some(after) = after {
+            // skip every repetition of `after`, all of them are followed by
+            // a \0 byte
+            let mut skip = vec![kind];
+            skip.extend_from_slice(after.as_bytes());
+            skip.push(1);
+            lower = lower.max(skip);
+        }
+
+        let upper = match end {
+            Some(end) => [&[kind], end.as_bytes()].concat(),
+            None => vec![kind + 1],
+        };
+
+        let mut op = OpBuilder::new();
+        for index in indexes {
+            op.push(index.fst_map.search(aut).ge(&lower).lt(&upper));
+        }
+
+        let mut stream = op.union();
+        let mut keys: Vec<String> = Vec::new();
+        while let Some((composite_key, _)) = stream.next() {
+            let (_, key, _) = Self::destructure_key(composite_key)?;
+            if keys.last().map(String::as_str) == Some(key) {
+                continue;
+            }
+
+            if keys.len() == limit {
+                let next = keys.last().cloned();
+                return Ok(KeyPage { keys, next });
+            }
+
+            keys.push(key.to_string());
+        }
+
+        Ok(KeyPage { keys, next: None })
+    }
+
+    /// Finds up to `limit` distinct keys of kind `data_type` within
+    /// `max_distance` edits of `query`, closest first. Keys with the same
+    /// distance are in lexicographic order.
+    pub fn fuzzy_search(
+        &self,
+        data_type: QueryKindKey,
+        query: &str,
+        max_distance: u32,
+        limit: usize,
+    ) -> Result<Vec<FuzzyMatch>, IndexError> {
+        Self::fuzzy_search_in_collection([self], data_type, query, max_distance, limit)
     }
 
-    pub fn unique_terms_in_collection(indexes: &[DictionaryIndex]) -> Result<usize, String> {
+    /// Same as [`DictionaryIndex::fuzzy_search`], but merges the keys of
+    /// several indexes.
+    pub fn fuzzy_search_in_collection<'a>(
+        indexes: impl IntoIterator<Item = &'a DictionaryIndex>,
+        data_type: QueryKindKey,
+        query: &str,
+        max_distance: u32,
+        limit: usize,
+    ) -> Result<Vec<FuzzyMatch>, IndexError> {
+        let indexes: Vec<&DictionaryIndex> = indexes.into_iter().collect();
+        let query = Self::normalize_key(data_type, query);
+
+        // the automaton only tells whether a key is within the distance, so
+        // the distance is raised one step at a time, and each step only keeps
+        // the keys at exactly that distance. Short queries match most of the
+        // index at larger distances, and this stops before reaching them if
+        // closer keys already fill the limit.
+        let mut matches: Vec<FuzzyMatch> = Vec::new();
+        if limit == 0 {
+            return Ok(matches);
+        }
+
+        for distance in 0..=max_distance {
+            let aut = CompositeKey::new(data_type.as_byte(), Levenshtein::new(&query, distance));
+
+            let mut op = OpBuilder::new();
+            for index in &indexes {
+                op.push(index.fst_map.search(&aut));
+            }
+
+            let mut stream = op.union();
+            let mut last_key: Option<String> = None;
+            while let Some((composite_key, _)) = stream.next() {
+                let (_, key, _) = Self::destructure_key(composite_key)?;
+                if last_key.as_deref() == Some(key) {
+                    continue;
+                }
+                last_key = Some(key.to_string());
+
+                if edit_distance(&query, key) == distance {
+                    matches.push(FuzzyMatch { key: key.to_string(), distance });
+                    if matches.len() >= limit {
+                        return Ok(matches);
+                    }
+                }
+            }
+        }
+
+        Ok(matches)
+    }
+
+    pub fn unique_terms_in_collection<'a>(
+        indexes: impl IntoIterator<Item = &'a DictionaryIndex>,
+    ) -> Result<usize, IndexError> {
         let mut op = OpBuilder::new();
         let prefix = QueryKindKey::Term.as_byte();
         for index in indexes {
-            op.push(index.fst_map.range().ge(&[prefix]).lt(&[prefix + 1]));
+            op.push(index.fst_map.range().ge([prefix]).lt([prefix + 1]));
         }
 
         let mut stream = op.union();
@@ -176,18 +414,97 @@ impl DictionaryIndex {
         Ok(count)
     }
 
-    pub fn verify(&self) -> Result<(), String> {
+    pub fn verify(&self) -> Result<(), IndexError> {
         let mut stream = self.fst_map.stream();
 
         // probably extraneous because this only checks that the checksum is intact, but whatever
-        map.as_fst().verify()
-            .map_err(|e| format!("fst verification failed: {}", e))?;
+        self.fst_map.as_fst().verify()?;
 
         // TODO: verify value, not just key
         while let Some((key, _)) = stream.next() {
-            Self::destructure_key(&key)?;
+            Self::destructure_key(key)?;
         }
 
         Ok(())
     }
-}
\ No newline at end of file
+}
+
+/// Levenshtein distance between `a` and `b`, counted in characters.
+fn edit_distance(a: &str, b: &str) -> u32 {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<u32> = (0..=b.len() as u32).collect();
+
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i as u32 + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = diagonal + u32::from(ca != *cb);
+            diagonal = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(diagonal + 1);
+        }
+    }
+
+    row[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn index(keys: &[&str]) -> DictionaryIndex {
+        let mappings = keys.iter()
+            .enumerate()
+            .map(|(i, key)| (QueryKindKey::Term, key.to_string(), i as u64))
+            .collect();
+        DictionaryIndex::build(mappings).unwrap()
+    }
+
+    #[test]
+    fn fuzzy_search_ranks_closest_first() {
+        let index = index(&["たべる", "たべた", "たべれる", "のむ", "たべ", "たべる"]);
+
+        let matches = index.fuzzy_search(QueryKindKey::Term, "たべる", 1, 10).unwrap();
+        let found: Vec<(&str, u32)> = matches.iter().map(|m| (m.key.as_str(), m.distance)).collect();
+        assert_eq!(found, [("たべる", 0), ("たべ", 1), ("たべた", 1), ("たべれる", 1)]);
+
+        let matches = index.fuzzy_search(QueryKindKey::Term, "たべる", 2, 2).unwrap();
+        let found: Vec<&str> = matches.iter().map(|m| m.key.as_str()).collect();
+        assert_eq!(found, ["たべる", "たべ"]);
+
+        assert!(index.fuzzy_search(QueryKindKey::Term, "たべる", 2, 0).unwrap().is_empty());
+    }
+
+    #[test]
+    fn search_pages_with_folded_cursor() {
+        let index = index(&["ab", "ac", "ad", "ｶﾞ", "ガム"]);
+
+        let page = index.search(QueryKindKey::Term, &KeyQuery::Prefix("a".into()), None, 1).unwrap();
+        assert_eq!(page.keys, ["ab"]);
+        assert_eq!(page.next.as_deref(), Some("ab"));
+
+        // cursors typed in full-width skip the same keys as folded ones
+        let page = index.search(QueryKindKey::Term, &KeyQuery::Prefix("ａ".into()), Some("ａｂ"), 10).unwrap();
+        assert_eq!(page.keys, ["ac", "ad"]);
+        assert_eq!(page.next, None);
+
+        let page = index.search(QueryKindKey::Term, &KeyQuery::Prefix("ｶﾞ".into()), Some("ｶﾞ"), 10).unwrap();
+        assert_eq!(page.keys, ["ガム"]);
+    }
+
+    #[test]
+    fn malformed_keys_are_errors() {
+        let kind = QueryKindKey::Term.as_byte();
+        for key in [&[][..], &[kind, 0, 0, 0, 1], &[kind, b'a', 1, 0, 0, 0, 1], &[kind, 0xff, 0, 0, 0, 0, 1]] {
+            assert!(DictionaryIndex::destructure_key(key).is_err(), "{:?}", key);
+        }
+        assert_eq!(DictionaryIndex::destructure_key(&[kind, b'a', 0, 0, 0, 0, 1]).unwrap().1, "a");
+
+        // an index that was not verified reports bad keys instead of panicking
+        let mut builder = MapBuilder::memory();
+        builder.insert([kind, b'a', 0, 1], 7).unwrap();
+        let index = DictionaryIndex::from_bytes(builder.into_inner().unwrap()).unwrap();
+        assert!(matches!(index.lookup(QueryKindKey::Term, "a"), Err(IndexError::MalformedKey)));
+        assert!(matches!(index.keys(QueryKindKey::Term), Err(IndexError::MalformedKey)));
+        assert!(index.verify().is_err());
+    }
+}
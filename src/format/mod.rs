@@ -2,14 +2,40 @@ pub mod types;
 pub mod store;
 pub mod index;
 pub mod container;
+pub mod collection;
+pub mod scanner;
+pub mod normalize;
+pub mod update;
+pub mod patch;
+pub mod signature;
+pub mod hashlog;
+pub mod frame_cache;
 
 mod dictionary;
+mod automaton;
 mod dictionary_convert;
+mod dictionary_source;
+mod dictionary_export;
+mod atomic_file;
 
 mod deinflector;
 mod deinflector_convert;
 
+#[cfg(test)]
+pub(crate) mod test_util;
+
 pub use dictionary::Dictionary;
-pub use dictionary_convert::convert_yomitan_dictionary;
-pub use deinflector::Deinflector;
+pub use types::LookupMode;
+pub use collection::{DictionaryCollection, CollectionConfig, CollectionResult};
+pub use scanner::{Scanner, ScanMatch};
+pub use normalize::{Normalizer, Transformation};
+pub use dictionary_convert::{convert_yomitan_dictionary, ConvertOptions, ConversionReport};
+pub use dictionary_export::export_yomitan_dictionary;
+pub use update::{Updater, Transport, FileTransport, CurlTransport, UpdateStatus, AvailableUpdate};
+pub use patch::{create_patch, apply_patch};
+pub use signature::{SignaturePolicy, SignatureWarningHandler, TrustedKeys, TrustedKey, set_signature_policy, set_signature_warning_handler, sign_container, verify_container};
+pub use frame_cache::{FrameCache, FrameCacheStats};
+pub use hashlog::{HashLogFile, HashLogPolicy};
+pub use store::StoreOptions;
+pub use deinflector::{Deinflector, DeinflectionResult, TransformMeta, Production};
 pub use deinflector_convert::convert_deinflector;
\ No newline at end of file
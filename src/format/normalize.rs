@@ -0,0 +1,372 @@
+//! Text normalization for Japanese lookups.
+//!
+//! Index keys of text kinds (see [`QueryKindKey::is_normalized`]) are width
+//! folded with [`index_key`] both when a dictionary is built and when it is
+//! queried, so half-width katakana and full-width ASCII always match.
+//!
+//! Other normalizations can change the meaning of a key (ex. katakana vs.
+//! hiragana headwords), so instead of being applied to the index they
+//! produce lookup variants through a [`Normalizer`], and every variant
+//! records which transformations produced it.
+//!
+//! [`QueryKindKey::is_normalized`]: super::types::QueryKindKey::is_normalized
+
+use unicode_normalization::UnicodeNormalization;
+use std::collections::HashSet;
+
+const LONG_VOWEL_MARK: char = 'ー';
+
+type Step = (Transformation, fn(&str) -> String);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Transformation {
+    /// Half-width katakana to full-width, full-width ASCII to ASCII. This is
+    /// always applied by the index, it is only recorded to show that it
+    /// changed the text.
+    Width,
+    Nfkc,
+    KatakanaToHiragana,
+    HiraganaToKatakana,
+    /// 々, ゝ, ゞ, ヽ and ヾ replaced by the character they repeat.
+    IterationMarks,
+    /// ー replaced by the vowel of the preceding kana.
+    ExpandLongVowels,
+    /// ー removed entirely.
+    CollapseLongVowels,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Variant {
+    pub text: String,
+    /// Transformations applied to the original text, in order.
+    pub transformations: Vec<Transformation>,
+}
+
+/// Configurable pipeline producing lookup variants of a query.
+#[derive(Debug, Clone, Copy)]
+pub struct Normalizer {
+    pub nfkc: bool,
+    pub katakana_to_hiragana: bool,
+    pub hiragana_to_katakana: bool,
+    pub iteration_marks: bool,
+    pub expand_long_vowels: bool,
+    pub collapse_long_vowels: bool,
+}
+
+impl Default for Normalizer {
+    fn default() -> Self {
+        Self {
+            nfkc: true,
+            katakana_to_hiragana: true,
+            hiragana_to_katakana: true,
+            iteration_marks: true,
+            expand_long_vowels: true,
+            collapse_long_vowels: false,
+        }
+    }
+}
+
+impl Normalizer {
+    /// Normalizer that only produces the width folded text.
+    pub fn disabled() -> Self {
+        Self {
+            nfkc: false,
+            katakana_to_hiragana: false,
+            hiragana_to_katakana: false,
+            iteration_marks: false,
+            expand_long_vowels: false,
+            collapse_long_vowels: false,
+        }
+    }
+
+    fn steps(&self) -> Vec<Step> {
+        let mut steps: Vec<Step> = Vec::new();
+        if self.nfkc {
+            steps.push((Transformation::Nfkc, |text| text.nfkc().collect()));
+        }
+        if self.iteration_marks {
+            steps.push((Transformation::IterationMarks, expand_iteration_marks));
+        }
+        if self.expand_long_vowels {
+            steps.push((Transformation::ExpandLongVowels, expand_long_vowels));
+        }
+        if self.collapse_long_vowels {
+            steps.push((Transformation::CollapseLongVowels, |text| text.replace(LONG_VOWEL_MARK, "")));
+        }
+        if self.katakana_to_hiragana {
+            steps.push((Transformation::KatakanaToHiragana, |text| text.chars().map(katakana_to_hiragana).collect()));
+        }
+        if self.hiragana_to_katakana {
+            steps.push((Transformation::HiraganaToKatakana, |text| text.chars().map(hiragana_to_katakana).collect()));
+        }
+        steps
+    }
+
+    /// Returns every distinct variant of `text`, starting with the width
+    /// folded `text` itself. Each step of the pipeline is applied to all
+    /// variants produced by the previous steps, so variants can combine
+    /// several transformations.
+    pub fn variants(&self, text: &str) -> Vec<Variant> {
+        let folded = index_key(text);
+        let transformations = if folded != text {
+            vec![Transformation::Width]
+        } else {
+            Vec::new()
+        };
+
+        let mut seen: HashSet<String> = HashSet::from([folded.clone()]);
+        let mut variants = vec![Variant { text: folded, transformations }];
+
+        for (transformation, apply) in self.steps() {
+            for i in 0..variants.len() {
+                let transformed = index_key(&apply(&variants[i].text));
+                if seen.contains(&transformed) {
+                    continue;
+                }
+
+                let mut transformations = variants[i].transformations.clone();
+                transformations.push(transformation);
+                seen.insert(transformed.clone());
+                variants.push(Variant { text: transformed, transformations });
+            }
+        }
+
+        variants
+    }
+}
+
+/// Width folds `text` for use as an index key. Half-width katakana (including
+/// separate voicing marks) become full-width katakana, and full-width ASCII
+/// becomes ASCII.
+pub fn index_key(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        let Some(full) = half_width_katakana(c) else {
+            result.push(full_width_ascii(c).unwrap_or(c));
+            continue;
+        };
+
+        let combined = match chars.peek() {
+            Some('ﾞ') => voiced(full),
+            Some('ﾟ') => semi_voiced(full),
+            _ => None,
+        };
+
+        match combined {
+            Some(combined) => {
+                chars.next();
+                result.push(combined);
+            }
+            None => result.push(full),
+        }
+    }
+
+    result
+}
+
+fn full_width_ascii(c: char) -> Option<char> {
+    match c {
+        '\u{FF01}'..='\u{FF5E}' => char::from_u32(c as u32 - 0xFEE0),
+        _ => None,
+    }
+}
+
+fn half_width_katakana(c: char) -> Option<char> {
+    const TABLE: &str = "。「」、・ヲァィゥェォャュョッーアイウエオカキクケコサシスセソタチツテトナニヌネノハヒフヘホマミムメモヤユヨラリルレロワン゛゜";
+
+    match c {
+        '\u{FF61}'..='\u{FF9F}' => TABLE.chars().nth(c as usize - 0xFF61),
+        _ => None,
+    }
+}
+
+fn voiced(c: char) -> Option<char> {
+    match c {
+        'カ' | 'キ' | 'ク' | 'ケ' | 'コ' | 'サ' | 'シ' | 'ス' | 'セ' | 'ソ' | 'タ' | 'チ' | 'ツ' | 'テ' | 'ト'
+        | 'ハ' | 'ヒ' | 'フ' | 'ヘ' | 'ホ'
+        | 'か' | 'き' | 'く' | 'け' | 'こ' | 'さ' | 'し' | 'す' | 'せ' | 'そ' | 'た' | 'ち' | 'つ' | 'て' | 'と'
+        | 'は' | 'ひ' | 'ふ' | 'へ' | 'ほ' => char::from_u32(c as u32 + 1),
+        'ウ' => Some('ヴ'),
+        'う' => Some('ゔ'),
+        'ワ' => Some('ヷ'),
+        'ヲ' => Some('ヺ'),
+        _ => None,
+    }
+}
+
+fn semi_voiced(c: char) -> Option<char> {
+    match c {
+        'ハ' | 'ヒ' | 'フ' | 'ヘ' | 'ホ' | 'は' | 'ひ' | 'ふ' | 'へ' | 'ほ' => char::from_u32(c as u32 + 2),
+        _ => None,
+    }
+}
+
+pub fn katakana_to_hiragana(c: char) -> char {
+    match c {
+        'ァ'..='ヶ' | 'ヽ' | 'ヾ' => char::from_u32(c as u32 - 0x60).unwrap_or(c),
+        _ => c,
+    }
+}
+
+pub fn hiragana_to_katakana(c: char) -> char {
+    match c {
+        'ぁ'..='ゖ' | 'ゝ' | 'ゞ' => char::from_u32(c as u32 + 0x60).unwrap_or(c),
+        _ => c,
+    }
+}
+
+fn is_katakana(c: char) -> bool {
+    matches!(c, 'ァ'..='ヺ')
+}
+
+fn expand_iteration_marks(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut prev: Option<char> = None;
+
+    for c in text.chars() {
+        let expanded = match (c, prev) {
+            ('々', Some(p)) => p,
+            ('ゝ' | 'ヽ', Some(p)) => p,
+            ('ゞ' | 'ヾ', Some(p)) => voiced(p).unwrap_or(p),
+            _ => c,
+        };
+
+        result.push(expanded);
+        prev = Some(expanded);
+    }
+
+    result
+}
+
+fn kana_vowel(c: char) -> Option<char> {
+    const VOWELS: [(char, &str); 5] = [
+        ('あ', "あぁかがさざただなはばぱまやゃらわゎ"),
+        ('い', "いぃきぎしじちぢにひびぴみりゐ"),
+        ('う', "うぅくぐすずつづっぬふぶぷむゆゅるゔ"),
+        ('え', "えぇけげせぜてでねへべぺめれゑ"),
+        ('お', "おぉこごそぞとどのほぼぽもよょろを"),
+    ];
+
+    let c = katakana_to_hiragana(c);
+    VOWELS.iter()
+        .find(|(_, kana)| kana.contains(c))
+        .map(|(vowel, _)| *vowel)
+}
+
+fn expand_long_vowels(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut prev: Option<char> = None;
+
+    for c in text.chars() {
+        let expanded = match (c, prev.and_then(|p| kana_vowel(p).map(|v| (p, v)))) {
+            (LONG_VOWEL_MARK, Some((p, vowel))) if is_katakana(p) => hiragana_to_katakana(vowel),
+            (LONG_VOWEL_MARK, Some((_, vowel))) => vowel,
+            _ => c,
+        };
+
+        result.push(expanded);
+        prev = Some(expanded);
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn index_key_folds_width() {
+        assert_eq!(index_key("ﾗｰﾒﾝ"), "ラーメン");
+        assert_eq!(index_key("ｶﾞｯﾂﾎﾟｰｽﾞ"), "ガッツポーズ");
+        assert_eq!(index_key("ｳﾞｧｲｵﾘﾝ"), "ヴァイオリン");
+        assert_eq!(index_key("ＡＢＣ１２３！"), "ABC123!");
+        assert_eq!(index_key("｡｢ﾜｦﾝ｣"), "。「ワヲン」");
+        // marks that can't combine are kept as full-width marks
+        assert_eq!(index_key("ｱﾞ"), "ア゛");
+        assert_eq!(index_key("食べる"), "食べる");
+    }
+
+    #[test]
+    fn half_width_table_is_complete() {
+        for c in '\u{FF61}'..='\u{FF9F}' {
+            assert!(half_width_katakana(c).is_some(), "{:?}", c);
+        }
+        assert_eq!(half_width_katakana('\u{FF9F}'), Some('゜'));
+        assert_eq!(half_width_katakana('ｱ'), Some('ア'));
+        assert_eq!(half_width_katakana('ア'), None);
+    }
+
+    fn variant<'a>(variants: &'a [Variant], text: &str) -> Option<&'a [Transformation]> {
+        variants.iter().find(|v| v.text == text).map(|v| v.transformations.as_slice())
+    }
+
+    #[test]
+    fn variants_convert_between_kana() {
+        let variants = Normalizer::default().variants("タベル");
+        assert_eq!(variant(&variants, "たべる"), Some(&[Transformation::KatakanaToHiragana][..]));
+
+        let variants = Normalizer::default().variants("たべる");
+        assert_eq!(variant(&variants, "タベル"), Some(&[Transformation::HiraganaToKatakana][..]));
+
+        let variants = Normalizer::disabled().variants("たべる");
+        assert_eq!(variant(&variants, "タベル"), None);
+    }
+
+    #[test]
+    fn variants_expand_iteration_marks() {
+        let normalizer = Normalizer::default();
+        assert_eq!(variant(&normalizer.variants("人々"), "人人"), Some(&[Transformation::IterationMarks][..]));
+        assert_eq!(variant(&normalizer.variants("こゝろ"), "こころ"), Some(&[Transformation::IterationMarks][..]));
+        assert_eq!(variant(&normalizer.variants("いすゞ"), "いすず"), Some(&[Transformation::IterationMarks][..]));
+        assert_eq!(
+            variant(&normalizer.variants("いすゞ"), "イスズ"),
+            Some(&[Transformation::IterationMarks, Transformation::HiraganaToKatakana][..]),
+        );
+    }
+
+    #[test]
+    fn variants_expand_and_collapse_long_vowels() {
+        let normalizer = Normalizer { collapse_long_vowels: true, ..Normalizer::default() };
+        let variants = normalizer.variants("ラーメン");
+        assert_eq!(variant(&variants, "ラアメン"), Some(&[Transformation::ExpandLongVowels][..]));
+        assert_eq!(variant(&variants, "ラメン"), Some(&[Transformation::CollapseLongVowels][..]));
+        assert_eq!(
+            variant(&variants, "らあめん"),
+            Some(&[Transformation::ExpandLongVowels, Transformation::KatakanaToHiragana][..]),
+        );
+
+        // collapsing is off by default
+        assert_eq!(variant(&Normalizer::default().variants("ラーメン"), "ラメン"), None);
+    }
+
+    #[test]
+    fn variants_apply_nfkc() {
+        let variants = Normalizer::default().variants("㍿");
+        assert_eq!(variant(&variants, "株式会社"), Some(&[Transformation::Nfkc][..]));
+
+        let variants = Normalizer::default().variants("ｶﾞｯﾂ①");
+        assert_eq!(variant(&variants, "ガッツ1"), Some(&[Transformation::Width, Transformation::Nfkc][..]));
+    }
+
+    #[test]
+    fn variants_start_with_the_query_and_are_distinct() {
+        let normalizer = Normalizer { collapse_long_vowels: true, ..Normalizer::default() };
+
+        for query in ["ラーメン", "たべる", "人々", "ｶﾞｯﾂﾎﾟｰｽﾞ", "いすゞ", "食べる"] {
+            let variants = normalizer.variants(query);
+            assert_eq!(variants[0].text, index_key(query));
+            assert_eq!(variants[0].transformations.is_empty(), index_key(query) == query);
+
+            let distinct: HashSet<&str> = variants.iter().map(|v| v.text.as_str()).collect();
+            assert_eq!(distinct.len(), variants.len(), "{:?}", variants);
+        }
+
+        assert_eq!(Normalizer::disabled().variants("ﾀﾍﾞﾙ"), [Variant {
+            text: "タベル".into(),
+            transformations: vec![Transformation::Width],
+        }]);
+    }
+}
@@ -0,0 +1,477 @@
+//! Binary patches between revisions of a container.
+//!
+//! A patch rebuilds one revision of a container (the target) from another
+//! revision with the same name (the base), as a list of ranges to copy from
+//! the base and bytes to insert. The base is identified by its name, revision
+//! and payload hash, and the rebuilt file is checked against the hash of the
+//! target, so applying a patch either produces the exact target file or
+//! fails.
+//!
+//! Both containers are memory mapped rather than read into memory, the ops
+//! are stored as separate items so they can be decoded one at a time, and the
+//! target is written out as it is rebuilt.
+//!
+//! Matches are found on blocks of the raw files, so patches are only small if
+//! unchanged data stays byte for byte the same between revisions. Dictionary
+//! stores meant to be patched should choose their frame boundaries by
+//! content for this reason (see [`StoreOptions::content_defined_frames`]).
+//!
+//! [`StoreOptions::content_defined_frames`]: super::store::StoreOptions::content_defined_frames
+
+use super::atomic_file::AtomicFile;
+use super::container::{ContainerFormat, ContainerFileInfo, ContainerHeader, Role, write_container, open_container};
+use super::store::{Store, StoreBuilder, MappedRegion};
+use crate::schema::BINCODE_CONFIG;
+use crate::error::{ContainerError, Error, PatchError, StoreError};
+use bincode::{Encode, Decode};
+use bincode::config::{Configuration, Limit, LittleEndian, Varint};
+use memmap2::Mmap;
+use sha2::{Sha256, Digest};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufWriter, Cursor, Seek, SeekFrom, Write};
+use std::sync::Arc;
+
+/// Size of the blocks of the base that are looked for in the target.
+const BLOCK_SIZE: usize = 64;
+const HASH_PRIME: u64 = 0x100_0000_01b3;
+
+/// Longest run of bytes inserted by one op. Longer runs are split, so that
+/// decoding an op never allocates more than [`OP_CONFIG`] allows.
+const MAX_INSERT: usize = 1 << 20;
+
+/// Patches are untrusted until the target hash is checked, which is only
+/// possible after decoding them, so their decoding is limited.
+const HEADER_CONFIG: Configuration<LittleEndian, Varint, Limit<{ 1 << 16 }>> = BINCODE_CONFIG.with_limit();
+const OP_CONFIG: Configuration<LittleEndian, Varint, Limit<{ MAX_INSERT + 64 }>> = BINCODE_CONFIG.with_limit();
+
+#[derive(Encode, Decode, Debug, Clone)]
+pub struct PatchHeader {
+    pub base_name: String,
+    pub base_revision: u64,
+    /// `payload_sha256` of the base container.
+    pub base_sha256: [u8; 32],
+    pub target_length: u64,
+    /// Hash of the whole target file, header included.
+    pub target_sha256: [u8; 32],
+    /// Number of ops in the patch store.
+    pub op_count: u64,
+}
+
+#[derive(Encode, Decode, Debug, Clone)]
+enum PatchOp {
+    Copy { offset: u64, len: u64 },
+    Insert(Vec<u8>),
+}
+
+pub struct Patch {
+    pub header: PatchHeader,
+    /// Each op is a separate item, in order.
+    ops: Store<MappedRegion>,
+}
+
+impl Patch {
+    /// Finds the blocks of `base` in `target`, and passes the ops that
+    /// rebuild `target` to `emit` in order.
+    fn diff<E>(base: &[u8], target: &[u8], mut emit: impl FnMut(PatchOp) -> Result<(), E>) -> Result<(), E> {
+        let mut blocks: HashMap<u64, usize> = HashMap::new();
+        for (i, block) in base.chunks_exact(BLOCK_SIZE).enumerate() {
+            blocks.entry(block_hash(block)).or_insert(i * BLOCK_SIZE);
+        }
+
+        // weight of the byte leaving the window
+        let out_weight = (1..BLOCK_SIZE).fold(1u64, |w, _| w.wrapping_mul(HASH_PRIME));
+
+        let mut literal_start = 0;
+        let mut pos = 0;
+        let mut hash = None;
+
+        while pos + BLOCK_SIZE <= target.len() {
+            let h = hash.unwrap_or_else(|| block_hash(&target[pos..pos + BLOCK_SIZE]));
+
+            if let Some(&offset) = blocks.get(&h)
+                && base[offset..offset + BLOCK_SIZE] == target[pos..pos + BLOCK_SIZE]
+            {
+                // grow the match in both directions
+                let (mut start, mut base_start) = (pos, offset);
+                while start > literal_start && base_start > 0 && base[base_start - 1] == target[start - 1] {
+                    start -= 1;
+                    base_start -= 1;
+                }
+                let (mut end, mut base_end) = (pos + BLOCK_SIZE, offset + BLOCK_SIZE);
+                while end < target.len() && base_end < base.len() && base[base_end] == target[end] {
+                    end += 1;
+                    base_end += 1;
+                }
+
+                for chunk in target[literal_start..start].chunks(MAX_INSERT) {
+                    emit(PatchOp::Insert(chunk.to_vec()))?;
+                }
+                emit(PatchOp::Copy { offset: base_start as u64, len: (end - start) as u64 })?;
+
+                pos = end;
+                literal_start = end;
+                hash = None;
+            } else {
+                hash = target.get(pos + BLOCK_SIZE).map(|&next| {
+                    h.wrapping_sub(byte_weight(target[pos]).wrapping_mul(out_weight))
+                        .wrapping_mul(HASH_PRIME)
+                        .wrapping_add(byte_weight(next))
+                });
+                pos += 1;
+            }
+        }
+
+        for chunk in target[literal_start..].chunks(MAX_INSERT) {
+            emit(PatchOp::Insert(chunk.to_vec()))?;
+        }
+
+        Ok(())
+    }
+
+    /// Decodes the ops one at a time.
+    fn ops(&self) -> Result<impl Iterator<Item = Result<PatchOp, Error>> + '_, Error> {
+        let mut reader = self.ops.reader(0)?;
+        Ok((0..self.header.op_count).map(move |_| {
+            bincode::decode_from_std_read(&mut reader, OP_CONFIG)
+                .map_err(|e| StoreError::Decode(e).into())
+        }))
+    }
+}
+
+/// Rebuilds the target described by `header` from `base` and writes it to
+/// `out` as it goes. `dst` is the path of `out`, for errors.
+fn apply_ops(
+    header: &PatchHeader,
+    ops: impl IntoIterator<Item = Result<PatchOp, Error>>,
+    base: &[u8],
+    out: &mut impl Write,
+    dst: &str,
+) -> Result<(), Error> {
+    let mut hasher = Sha256::new();
+    let mut length = 0u64;
+
+    for op in ops {
+        let op = op?;
+        let data = match &op {
+            PatchOp::Copy { offset, len } => offset.checked_add(*len)
+                .and_then(|end| base.get(usize::try_from(*offset).ok()?..usize::try_from(end).ok()?))
+                .ok_or(PatchError::InvalidCopy)?,
+            PatchOp::Insert(data) => data,
+        };
+
+        length += data.len() as u64;
+        if length > header.target_length {
+            return Err(PatchError::TargetMismatch.into());
+        }
+        hasher.update(data);
+        out.write_all(data)
+            .map_err(|source| PatchError::Io { path: dst.into(), source })?;
+    }
+
+    if length != header.target_length || hasher.finalize()[..] != header.target_sha256[..] {
+        return Err(PatchError::TargetMismatch.into());
+    }
+
+    Ok(())
+}
+
+fn byte_weight(b: u8) -> u64 {
+    // offset by one so runs of zeros still change the hash
+    b as u64 + 1
+}
+
+fn block_hash(block: &[u8]) -> u64 {
+    block.iter().fold(0, |h, &b| h.wrapping_mul(HASH_PRIME).wrapping_add(byte_weight(b)))
+}
+
+fn map_file(path: &str) -> Result<Mmap, PatchError> {
+    let file = File::open(path)
+        .map_err(|source| PatchError::Io { path: path.into(), source })?;
+    unsafe { Mmap::map(&file) }
+        .map_err(|source| PatchError::Io { path: path.into(), source })
+}
+
+/// Reads and validates the header of a container held in memory.
+fn read_header(data: &[u8]) -> Result<ContainerHeader, ContainerError> {
+    let container = ContainerFileInfo::read_container(Cursor::new(data))?;
+    container.validate_payload(Cursor::new(data))?;
+    Ok(container.header)
+}
+
+/// Writes a patch to `dst` that rebuilds the container at `target` from the
+/// one at `base`. Both must be revisions of the same container. The patch
+/// container takes the name and revision of the target.
+pub fn create_patch(base_path: &str, target_path: &str, dst: &str) -> Result<(), Error> {
+    let base = map_file(base_path)?;
+    let target = map_file(target_path)?;
+    let base_header = read_header(&base)?;
+    let target_header = read_header(&target)?;
+
+    if base_header.meta.name != target_header.meta.name {
+        return Err(PatchError::NameMismatch {
+            base: base_header.meta.name,
+            target: target_header.meta.name,
+        }.into());
+    }
+
+    let mut store = StoreBuilder::new()?;
+    let mut op_count = 0;
+    Patch::diff(&base, &target, |op| {
+        store.insert(&op)?;
+        op_count += 1;
+        Ok::<_, StoreError>(())
+    })?;
+
+    let header = PatchHeader {
+        base_name: base_header.meta.name,
+        base_revision: base_header.meta.revision,
+        base_sha256: base_header.payload_sha256,
+        target_length: target.len() as u64,
+        target_sha256: Sha256::digest(&target[..]).into(),
+        op_count,
+    };
+
+    let mut encoded = bincode::encode_to_vec(&header, BINCODE_CONFIG)
+        .map_err(PatchError::Encode)?;
+    encoded.extend(store.finalize()?.data);
+
+    // a failed write leaves any previous patch at `dst` in place
+    let io_error = |source: io::Error| PatchError::Io { path: dst.into(), source };
+    let mut file = AtomicFile::create(dst).map_err(io_error)?;
+    write_container::<Patch, _>(file.file(), target_header.meta, &encoded)?;
+    file.commit().map_err(io_error)?;
+
+    Ok(())
+}
+
+/// Rebuilds the target of the patch at `patch_path` from the container at
+/// `base_path`, and writes it to `dst`. Nothing is written if the base is not
+/// the one the patch was made from, and `dst` is only replaced once the
+/// rebuilt target matches its hash.
+pub fn apply_patch(base_path: &str, patch_path: &str, dst: &str) -> Result<(), Error> {
+    let patch: Patch = open_container(patch_path, true)?;
+    let base = map_file(base_path)?;
+    let base_header = read_header(&base)?;
+
+    if base_header.meta.name != patch.header.base_name
+        || base_header.meta.revision != patch.header.base_revision
+        || base_header.payload_sha256 != patch.header.base_sha256
+    {
+        return Err(PatchError::WrongBase {
+            name: patch.header.base_name,
+            revision: patch.header.base_revision,
+        }.into());
+    }
+
+    // written next to `dst` and renamed over it, so that a failed patch
+    // leaves no partial target behind, and the base in place when patching
+    // in place
+    let io_error = |source: io::Error| PatchError::Io { path: dst.into(), source };
+    let mut target = AtomicFile::create(dst).map_err(io_error)?;
+    let mut out = BufWriter::new(target.file());
+    apply_ops(&patch.header, patch.ops()?, &base, &mut out, dst)?;
+    out.flush().map_err(io_error)?;
+    drop(out);
+
+    target.commit().map_err(io_error)?;
+    Ok(())
+}
+
+impl ContainerFormat for Patch {
+    fn role() -> Role {
+        Role::Patch
+    }
+
+    // version 0 stored all ops as a single item
+    fn min_role_version() -> u64 {
+        1
+    }
+
+    fn role_version() -> u64 {
+        1
+    }
+
+    fn load(mut file: File, payload_offset: u64, _verify: bool) -> Result<Self, Error> {
+        file.seek(SeekFrom::Start(payload_offset))
+            .map_err(ContainerError::Io)?;
+
+        let header: PatchHeader = bincode::decode_from_std_read(&mut file, HEADER_CONFIG)
+            .map_err(ContainerError::Decode)?;
+
+        let ops_offset = file.stream_position()
+            .map_err(ContainerError::Io)?;
+        let mmap = unsafe {
+            Mmap::map(&file)
+                .map_err(ContainerError::Io)?
+        };
+        let ops_len = (mmap.len() as u64).saturating_sub(ops_offset);
+
+        let ops = Store::new(MappedRegion::new(Arc::new(mmap), ops_offset, ops_len))?;
+
+        Ok(Self { header, ops })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::format::container::ContainerMeta;
+    use crate::format::test_util::scratch_dir;
+    use std::fs;
+
+    struct TestPatch {
+        header: PatchHeader,
+        ops: Vec<PatchOp>,
+    }
+
+    impl TestPatch {
+        fn apply(&self, base: &[u8]) -> Result<Vec<u8>, Error> {
+            let mut target = Vec::new();
+            apply_ops(&self.header, self.ops.iter().cloned().map(Ok), base, &mut target, "test")?;
+            Ok(target)
+        }
+
+        fn inserted_len(&self) -> usize {
+            self.ops.iter()
+                .map(|op| match op {
+                    PatchOp::Insert(data) => data.len(),
+                    PatchOp::Copy { .. } => 0,
+                })
+                .sum()
+        }
+    }
+
+    fn patch(base: &[u8], target: &[u8]) -> TestPatch {
+        let mut ops = Vec::new();
+        Patch::diff(base, target, |op| {
+            ops.push(op);
+            Ok::<_, ()>(())
+        }).unwrap();
+
+        TestPatch {
+            header: PatchHeader {
+                base_name: "test".into(),
+                base_revision: 0,
+                base_sha256: [0; 32],
+                target_length: target.len() as u64,
+                target_sha256: Sha256::digest(target).into(),
+                op_count: ops.len() as u64,
+            },
+            ops,
+        }
+    }
+
+    /// Deterministic bytes that don't repeat within a block.
+    fn noise(len: usize, seed: u64) -> Vec<u8> {
+        let mut state = seed;
+        (0..len).map(|_| {
+            state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            (state >> 56) as u8
+        }).collect()
+    }
+
+    #[test]
+    fn rebuilds_target() {
+        let base = noise(10_000, 1);
+        let mut target = base[..3000].to_vec();
+        target.extend_from_slice(b"inserted bytes");
+        target.extend_from_slice(&base[3100..8000]);
+        target.extend_from_slice(&base[..500]);
+
+        let patch = patch(&base, &target);
+        assert_eq!(patch.apply(&base).unwrap(), target);
+        assert!(patch.inserted_len() < 2 * BLOCK_SIZE);
+    }
+
+    #[test]
+    fn unrelated_and_short_inputs() {
+        let base = noise(1000, 1);
+        let target = noise(1000, 2);
+        let patch = patch(&base, &target);
+        assert_eq!(patch.apply(&base).unwrap(), target);
+        assert_eq!(patch.inserted_len(), target.len());
+
+        for (base, target) in [(&b""[..], &b"short"[..]), (&b"short"[..], &b""[..])] {
+            assert_eq!(self::patch(base, target).apply(base).unwrap(), target);
+        }
+    }
+
+    #[test]
+    fn long_inserts_are_split() {
+        let target = noise(MAX_INSERT * 2 + 10, 3);
+        let patch = patch(b"", &target);
+        assert_eq!(patch.ops.len(), 3);
+        assert_eq!(patch.apply(b"").unwrap(), target);
+    }
+
+    #[test]
+    fn wrong_base_fails() {
+        let base = noise(4096, 1);
+        let mut target = base.clone();
+        target[2000] ^= 1;
+
+        let patch = patch(&base, &target);
+        let mut other = base.clone();
+        other[100] ^= 1;
+        assert!(matches!(patch.apply(&other), Err(Error::Patch(PatchError::TargetMismatch))));
+        assert!(matches!(patch.apply(&base[..1000]), Err(Error::Patch(PatchError::InvalidCopy))));
+    }
+
+    #[test]
+    fn oversized_ops_are_rejected() {
+        let mut store = StoreBuilder::new().unwrap();
+        store.insert(&PatchOp::Insert(vec![0; MAX_INSERT * 2])).unwrap();
+        let data = store.finalize().unwrap().data;
+
+        let dir = scratch_dir("patch-ops");
+        let path = dir.join("ops");
+        fs::write(&path, &data).unwrap();
+        let mmap = map_file(path.to_str().unwrap()).unwrap();
+        let len = mmap.len() as u64;
+
+        let patch = Patch {
+            header: PatchHeader { op_count: 1, ..self::patch(b"", b"").header },
+            ops: Store::new(MappedRegion::new(Arc::new(mmap), 0, len)).unwrap(),
+        };
+        let op = patch.ops().unwrap().next().unwrap();
+        assert!(matches!(op, Err(Error::Store(StoreError::Decode(_)))));
+
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn apply_replaces_target_only_when_rebuilt() {
+        let dir = scratch_dir("patch-apply");
+        let path = |name: &str| dir.join(name).to_str().unwrap().to_string();
+        let meta = |revision: u64| ContainerMeta { name: "test".into(), revision_name: revision.to_string(), revision };
+
+        let base = noise(50_000, 1);
+        let mut target = base.clone();
+        target[20_000..20_100].copy_from_slice(&noise(100, 2));
+        // any containers with the same name can be patched, the payloads
+        // don't need to be patches
+        for (name, revision, payload) in [("base", 1, &base), ("target", 2, &target)] {
+            let mut file = File::create(path(name)).unwrap();
+            write_container::<Patch, _>(&mut file, meta(revision), payload).unwrap();
+        }
+
+        create_patch(&path("base"), &path("target"), &path("patch")).unwrap();
+        apply_patch(&path("base"), &path("patch"), &path("rebuilt")).unwrap();
+        assert_eq!(fs::read(path("rebuilt")).unwrap(), fs::read(path("target")).unwrap());
+        assert!(fs::metadata(path("patch")).unwrap().len() < 10_000);
+
+        // patching the wrong base leaves the existing file alone
+        fs::write(path("rebuilt"), b"untouched").unwrap();
+        assert!(apply_patch(&path("target"), &path("patch"), &path("rebuilt")).is_err());
+        assert_eq!(fs::read(path("rebuilt")).unwrap(), b"untouched");
+        assert!(!dir.join("rebuilt.tmp").exists());
+
+        // the base can be patched in place
+        apply_patch(&path("base"), &path("patch"), &path("base")).unwrap();
+        assert_eq!(fs::read(path("base")).unwrap(), fs::read(path("target")).unwrap());
+        assert!(!dir.join("base.tmp").exists());
+
+        let _ = fs::remove_dir_all(dir);
+    }
+}
@@ -0,0 +1,351 @@
+//! Longest-match scanning of running text, as used by pop-up readers.
+//!
+//! Starting at a cursor position, the scanner tries successively shorter
+//! prefixes of the remaining text, normalizes and deinflects each of them,
+//! and looks every candidate up in a [`DictionaryCollection`]. Entries found
+//! for a deinflected form are only kept if their rules match the conditions
+//! of the deinflection (see [`Deinflector::accepts`]).
+
+use super::collection::{CollectionResult, DictionaryCollection};
+use super::deinflector::{Deinflector, DeinflectionResult};
+use super::normalize::{Normalizer, Transformation};
+use super::types::LookupMode;
+use crate::schema::Term;
+use crate::error::StoreError;
+use std::collections::{HashMap, HashSet};
+use std::collections::hash_map::Entry;
+use std::ops::Range;
+
+const DEFAULT_MAX_LENGTH: usize = 16;
+
+#[derive(Debug, Clone)]
+pub struct ScanMatch {
+    /// Number of characters of the source text consumed by this match.
+    pub source_len: usize,
+    /// Consumed source text.
+    pub source: String,
+    /// Byte range of `source` in the scanned text.
+    pub byte_range: Range<usize>,
+    /// Dictionary form that was found, after normalization and deinflection.
+    pub term: String,
+    /// Normalizations applied to `source` before deinflection.
+    pub transformations: Vec<Transformation>,
+    /// Indexes into [`Deinflector::transform_meta`] that were applied to get
+    /// from `source` to `term`. Empty if no deinflection happened.
+    pub rule_chain: Vec<usize>,
+    pub entries: Vec<CollectionResult<Term>>,
+}
+
+pub struct Scanner<'a> {
+    deinflector: Option<&'a Deinflector>,
+    normalizer: Normalizer,
+    mode: LookupMode,
+    max_length: usize,
+}
+
+impl Default for Scanner<'_> {
+    fn default() -> Self {
+        Self {
+            deinflector: None,
+            normalizer: Normalizer::default(),
+            mode: LookupMode::Term,
+            max_length: DEFAULT_MAX_LENGTH,
+        }
+    }
+}
+
+impl<'a> Scanner<'a> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn deinflector(mut self, deinflector: &'a Deinflector) -> Self {
+        self.deinflector = Some(deinflector);
+        self
+    }
+
+    pub fn normalizer(mut self, normalizer: Normalizer) -> Self {
+        self.normalizer = normalizer;
+        self
+    }
+
+    pub fn lookup_mode(mut self, mode: LookupMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    /// Maximum number of characters considered from the cursor position.
+    pub fn max_length(mut self, max_length: usize) -> Self {
+        self.max_length = max_length;
+        self
+    }
+
+    /// Finds dictionary entries for the text starting at character offset
+    /// `cursor` of `text`. Matches are ordered from longest to shortest
+    /// source text, so the first match is the longest match. An entry is
+    /// only reported for the longest source text it was found for.
+    pub fn scan(
+        &self,
+        collection: &DictionaryCollection,
+        text: &str,
+        cursor: usize,
+    ) -> Result<Vec<ScanMatch>, StoreError> {
+        let boundaries: Vec<usize> = text
+            .char_indices()
+            .map(|(i, _)| i)
+            .chain(std::iter::once(text.len()))
+            .skip(cursor)
+            .take(self.max_length + 1)
+            .collect();
+
+        let Some(&start) = boundaries.first() else {
+            return Ok(Vec::new());
+        };
+
+        let mut matches = Vec::new();
+        // entries found for each term, taken out once they are reported, so
+        // homographs with other rules can still be reported for shorter
+        // sources
+        let mut found: HashMap<String, Vec<Option<CollectionResult<Term>>>> = HashMap::new();
+        for (source_len, &end) in boundaries.iter().enumerate().skip(1).rev() {
+            let source = &text[start..end];
+
+            for candidate in self.candidates(source) {
+                let term = &candidate.deinflection.term;
+                let slots = match found.entry(term.clone()) {
+                    Entry::Occupied(slots) => slots.into_mut(),
+                    Entry::Vacant(slots) => slots.insert(
+                        collection.find_terms_by(term, self.mode)?.into_iter().map(Some).collect()
+                    ),
+                };
+
+                let entries: Vec<_> = slots.iter_mut()
+                    .filter(|slot| slot.as_ref().is_some_and(|result| {
+                        self.deinflector.is_none_or(|deinflector| deinflector.accepts(&candidate.deinflection, &result.entry.rules))
+                    }))
+                    .filter_map(Option::take)
+                    .collect();
+                if entries.is_empty() {
+                    continue;
+                }
+
+                matches.push(ScanMatch {
+                    source_len,
+                    source: source.to_string(),
+                    byte_range: start..end,
+                    term: candidate.deinflection.term,
+                    transformations: candidate.transformations,
+                    rule_chain: candidate.deinflection.rule_chain,
+                    entries,
+                });
+            }
+        }
+
+        Ok(matches)
+    }
+
+    fn candidates(&self, source: &str) -> Vec<Candidate> {
+        let mut candidates = Vec::new();
+
+        // the same term can be reached through several variants and rule
+        // chains, only keep the first one found for each set of conditions
+        let mut seen = HashSet::new();
+        for variant in self.normalizer.variants(source) {
+            let Some(deinflector) = self.deinflector else {
+                if seen.insert((variant.text.clone(), 0)) {
+                    candidates.push(Candidate {
+                        transformations: variant.transformations,
+                        deinflection: DeinflectionResult { term: variant.text, rule_chain: Vec::new(), conditions: 0 },
+                    });
+                }
+                continue;
+            };
+
+            for result in deinflector.deinflect(&variant.text) {
+                if seen.insert((result.term.clone(), result.conditions)) {
+                    candidates.push(Candidate {
+                        transformations: variant.transformations.clone(),
+                        deinflection: result,
+                    });
+                }
+            }
+        }
+
+        candidates
+    }
+}
+
+struct Candidate {
+    transformations: Vec<Transformation>,
+    deinflection: DeinflectionResult,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::format::container::open_container;
+    use crate::format::dictionary_convert::ConvertOptions;
+    use crate::format::test_util::{build_deinflector, build_dictionary, scratch_dir, term, term_with_rules};
+    use serde_json::{Value, json};
+    use std::fs;
+    use std::path::Path;
+
+    fn collection(dir: &Path, terms: &[Value]) -> DictionaryCollection {
+        let path = build_dictionary(dir, "dict.tomo", "Dict", terms, &[], &ConvertOptions::default());
+        let mut collection = DictionaryCollection::new();
+        collection.add(path.to_str().unwrap(), 0, true, true).unwrap();
+        collection
+    }
+
+    fn rule(accept: &str, produce: &str, tags: &[&str]) -> Value {
+        json!({ "accept": { "suffix": accept, "tags": [] }, "produce": { "suffix": produce, "tags": tags } })
+    }
+
+    fn deinflector(dir: &Path) -> Deinflector {
+        let transforms = json!({
+            "dict_tags": ["v1", "v5r"],
+            "subtags": {},
+            "transforms": [
+                { "name": "past", "desc": null, "is_final": false, "rules": [
+                    rule("た", "る", &["v1"]),
+                    rule("った", "る", &["v5r"]),
+                ] },
+                { "name": "-tai", "desc": null, "is_final": false, "rules": [
+                    rule("たい", "る", &["v1"]),
+                ] },
+            ],
+        });
+        let path = build_deinflector(dir, "deinflector.tomo", transforms);
+        open_container(path.to_str().unwrap(), true).unwrap()
+    }
+
+    fn summary(matches: &[ScanMatch]) -> Vec<(&str, &str, usize)> {
+        matches.iter()
+            .map(|m| (m.source.as_str(), m.term.as_str(), m.source_len))
+            .collect()
+    }
+
+    #[test]
+    fn longest_match_comes_first() {
+        let dir = scratch_dir("scan-longest");
+        let collection = collection(&dir, &[
+            term("日本", "にほん", "Japan"),
+            term("日本語", "にほんご", "Japanese"),
+            term("本", "ほん", "book"),
+        ]);
+        let scanner = Scanner::new();
+
+        let matches = scanner.scan(&collection, "日本語を話す", 0).unwrap();
+        assert_eq!(summary(&matches), [("日本語", "日本語", 3), ("日本", "日本", 2)]);
+        assert_eq!(matches[0].byte_range, 0..9);
+
+        // the cursor counts characters, the range is in bytes
+        let matches = scanner.scan(&collection, "日本語を話す", 1).unwrap();
+        assert_eq!(summary(&matches), [("本", "本", 1)]);
+        assert_eq!(matches[0].byte_range, 3..6);
+        assert_eq!(matches[0].entries[0].entry.reading, "ほん");
+
+        assert!(scanner.scan(&collection, "日本語を話す", 3).unwrap().is_empty());
+        assert!(scanner.scan(&collection, "日本語", 10).unwrap().is_empty());
+
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn normalized_variants_are_found() {
+        let dir = scratch_dir("scan-normalize");
+        let collection = collection(&dir, &[term("ラーメン", "ラーメン", "ramen")]);
+        let scanner = Scanner::new();
+
+        let matches = scanner.scan(&collection, "らーめんを", 0).unwrap();
+        assert_eq!(summary(&matches), [("らーめん", "ラーメン", 4)]);
+        assert_eq!(matches[0].transformations, [Transformation::HiraganaToKatakana]);
+
+        let matches = scanner.scan(&collection, "ﾗｰﾒﾝ", 0).unwrap();
+        assert_eq!(summary(&matches), [("ﾗｰﾒﾝ", "ラーメン", 4)]);
+        assert_eq!(matches[0].transformations, [Transformation::Width]);
+        assert_eq!(matches[0].byte_range, 0.."ﾗｰﾒﾝ".len());
+
+        assert!(Scanner::new().normalizer(Normalizer::disabled()).scan(&collection, "らーめん", 0).unwrap().is_empty());
+
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn deinflected_forms_are_found_once() {
+        let dir = scratch_dir("scan-deinflect");
+        let collection = collection(&dir, &[term_with_rules("食べる", "たべる", "v1", "to eat")]);
+        let deinflector = deinflector(&dir);
+        let scanner = Scanner::new().deinflector(&deinflector);
+
+        let matches = scanner.scan(&collection, "食べた。", 0).unwrap();
+        assert_eq!(summary(&matches), [("食べた", "食べる", 3)]);
+        assert_eq!(matches[0].rule_chain, [0]);
+        assert_eq!(deinflector.transform_meta[matches[0].rule_chain[0]].name, "past");
+
+        // 食べた also deinflects to 食べる, but only the longest source is kept
+        let matches = scanner.scan(&collection, "食べたい", 0).unwrap();
+        assert_eq!(summary(&matches), [("食べたい", "食べる", 4)]);
+        assert_eq!(deinflector.transform_meta[matches[0].rule_chain[0]].name, "-tai");
+
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn deinflections_only_match_entries_with_their_conditions() {
+        let dir = scratch_dir("scan-conditions");
+        let collection = collection(&dir, &[
+            term_with_rules("いる", "いる", "v1", "to be"),
+            term_with_rules("いる", "いる", "v5r", "to need"),
+            term("いった", "いった", "no rules"),
+        ]);
+        let deinflector = deinflector(&dir);
+        let scanner = Scanner::new().deinflector(&deinflector).normalizer(Normalizer::disabled());
+        let rules = |m: &ScanMatch| m.entries.iter().map(|result| result.entry.rules.clone()).collect::<Vec<_>>();
+
+        let matches = scanner.scan(&collection, "いた", 0).unwrap();
+        assert_eq!(summary(&matches), [("いた", "いる", 2)]);
+        assert_eq!(rules(&matches[0]), ["v1"]);
+
+        // the undeinflected text matches entries without rules
+        let matches = scanner.scan(&collection, "いった", 0).unwrap();
+        assert_eq!(summary(&matches), [("いった", "いった", 3), ("いった", "いる", 3)]);
+        assert_eq!(rules(&matches[0]), [""]);
+        assert_eq!(rules(&matches[1]), ["v5r"]);
+
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn homographs_with_other_rules_are_found_for_shorter_sources() {
+        let dir = scratch_dir("scan-homographs");
+        let collection = collection(&dir, &[
+            term_with_rules("いる", "いる", "v1", "to be"),
+            term_with_rules("いる", "いる", "v5r", "to need"),
+        ]);
+        let transforms = json!({
+            "dict_tags": ["v1", "v5r"],
+            "subtags": {},
+            "transforms": [
+                { "name": "past", "desc": null, "is_final": false, "rules": [
+                    rule("った", "る", &["v5r"]),
+                ] },
+                // made up, so a longer source reaches the v1 homograph
+                { "name": "made-up", "desc": null, "is_final": false, "rules": [
+                    rule("ったり", "る", &["v1"]),
+                ] },
+            ],
+        });
+        let path = build_deinflector(&dir, "deinflector.tomo", transforms);
+        let deinflector: Deinflector = open_container(path.to_str().unwrap(), true).unwrap();
+        let scanner = Scanner::new().deinflector(&deinflector).normalizer(Normalizer::disabled());
+        let rules = |m: &ScanMatch| m.entries.iter().map(|result| result.entry.rules.clone()).collect::<Vec<_>>();
+
+        let matches = scanner.scan(&collection, "いったり", 0).unwrap();
+        assert_eq!(summary(&matches), [("いったり", "いる", 4), ("いった", "いる", 3)]);
+        assert_eq!(rules(&matches[0]), ["v1"]);
+        assert_eq!(rules(&matches[1]), ["v5r"]);
+
+        let _ = fs::remove_dir_all(dir);
+    }
+}
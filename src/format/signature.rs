@@ -0,0 +1,379 @@
+//! Ed25519 signatures of containers.
+//!
+//! A signature covers the bytes of the magic and the container header exactly
+//! as they are written, with the signature itself left empty, followed by the
+//! payload hash. Headers are never serialized again to check a signature, so
+//! fields this version does not know about are covered as well. The
+//! `signature` field is always written last, which makes it easy to blank.
+//! The header holds the length and hash of the payload, so checking a
+//! signature always includes checking the payload against them.
+//!
+//! Which signers are accepted comes from [`TrustedKeys`], and what happens to
+//! containers that are not signed by one of them from the [`SignaturePolicy`].
+//! Both are set for the whole process with [`set_signature_policy`], and are
+//! applied by [`open_container`](super::container::open_container).
+//!
+//! Keys are stored and shown as base64: public keys in the trusted keys file
+//! and container headers, and secret keys in key files.
+
+use super::atomic_file::{self, AtomicFile};
+use super::container::ContainerFileInfo;
+use crate::error::{ConfigError, ContainerError, Error, SignatureError};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use ed25519_dalek::{Signature, Signer, VerifyingKey};
+use serde::{Serialize, Deserialize};
+use std::fs;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::sync::{Arc, RwLock};
+
+pub use ed25519_dalek::SigningKey;
+
+/// Prefix of signed messages, so container signatures can't be mistaken for
+/// signatures of anything else made with the same key.
+const SIGNATURE_CONTEXT: &[u8] = b"TOMOCHAN SIGNATURE:";
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ContainerSignature {
+    /// Public key of the signer.
+    pub public_key: String,
+    pub signature: String,
+}
+
+/// What [`open_container`](super::container::open_container) does with
+/// containers that are not signed by a trusted key.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SignaturePolicy {
+    /// Signatures are not checked.
+    #[default]
+    Ignore,
+    /// Containers are opened anyway, and reported to the handler set with
+    /// [`set_signature_warning_handler`].
+    Warn,
+    /// Containers fail to open.
+    Require,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TrustedKey {
+    /// Who the key belongs to, for messages.
+    pub name: String,
+    pub public_key: String,
+}
+
+/// Persisted set of keys whose signatures are accepted.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TrustedKeys {
+    pub keys: Vec<TrustedKey>,
+}
+
+impl TrustedKeys {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, ConfigError> {
+        let path = path.as_ref();
+        let json = fs::read_to_string(path)
+            .map_err(|source| ConfigError::Io { path: path.into(), source })?;
+
+        serde_json::from_str(&json)
+            .map_err(|source| ConfigError::Json { path: path.into(), source })
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), ConfigError> {
+        let path = path.as_ref();
+        let json = serde_json::to_vec_pretty(self)
+            .map_err(|source| ConfigError::Json { path: path.into(), source })?;
+
+        atomic_file::write(path, &json)
+            .map_err(|source| ConfigError::Io { path: path.into(), source })
+    }
+
+    pub fn find(&self, key: &VerifyingKey) -> Option<&TrustedKey> {
+        self.keys.iter().find(|trusted| decode_public_key(&trusted.public_key).is_ok_and(|k| k == *key))
+    }
+}
+
+/// Called with the path of a container and why its signature was not
+/// accepted, when it is opened under [`SignaturePolicy::Warn`].
+pub type SignatureWarningHandler = Arc<dyn Fn(&str, &SignatureError) + Send + Sync>;
+
+struct Verification {
+    policy: SignaturePolicy,
+    trusted: TrustedKeys,
+    warn: Option<SignatureWarningHandler>,
+}
+
+static VERIFICATION: RwLock<Verification> = RwLock::new(Verification {
+    policy: SignaturePolicy::Ignore,
+    trusted: TrustedKeys { keys: Vec::new() },
+    warn: None,
+});
+
+pub fn set_signature_policy(policy: SignaturePolicy, trusted: TrustedKeys) {
+    // the lock only guards plain assignments, so a poisoned value is still whole
+    let mut verification = VERIFICATION.write().unwrap_or_else(|e| e.into_inner());
+    verification.policy = policy;
+    verification.trusted = trusted;
+}
+
+/// Sets what is done with containers opened under
+/// [`SignaturePolicy::Warn`]. Without a handler they are opened silently.
+pub fn set_signature_warning_handler(handler: impl Fn(&str, &SignatureError) + Send + Sync + 'static) {
+    VERIFICATION.write().unwrap_or_else(|e| e.into_inner()).warn = Some(Arc::new(handler));
+}
+
+pub(crate) fn warn_signature(path: &str, error: &SignatureError) {
+    // cloned out so the handler runs without holding the lock
+    let handler = VERIFICATION.read().unwrap_or_else(|e| e.into_inner()).warn.clone();
+    if let Some(handler) = handler {
+        handler(path, error);
+    }
+}
+
+pub fn signature_policy() -> SignaturePolicy {
+    VERIFICATION.read().unwrap_or_else(|e| e.into_inner()).policy
+}
+
+pub fn generate_signing_key() -> Result<SigningKey, SignatureError> {
+    let mut secret = [0u8; 32];
+    getrandom::getrandom(&mut secret).map_err(SignatureError::Random)?;
+    Ok(SigningKey::from_bytes(&secret))
+}
+
+pub fn read_signing_key(path: &str) -> Result<SigningKey, SignatureError> {
+    let text = fs::read_to_string(path)
+        .map_err(|source| SignatureError::Io { path: path.into(), source })?;
+
+    let secret: [u8; 32] = BASE64.decode(text.trim()).ok()
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or(SignatureError::InvalidKey)?;
+
+    Ok(SigningKey::from_bytes(&secret))
+}
+
+/// Writes a new key file that only its owner can read. Existing files are
+/// never overwritten, so a key can't be lost by generating another one.
+pub fn write_signing_key(path: &str, key: &SigningKey) -> Result<(), SignatureError> {
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+
+    options.open(path)
+        .and_then(|mut file| file.write_all(format!("{}\n", BASE64.encode(key.to_bytes())).as_bytes()))
+        .map_err(|source| SignatureError::Io { path: path.into(), source })
+}
+
+pub fn encode_public_key(key: &VerifyingKey) -> String {
+    BASE64.encode(key.to_bytes())
+}
+
+fn decode_public_key(key: &str) -> Result<VerifyingKey, SignatureError> {
+    let bytes: [u8; 32] = BASE64.decode(key).ok()
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or(SignatureError::InvalidKey)?;
+
+    VerifyingKey::from_bytes(&bytes).map_err(|_| SignatureError::InvalidKey)
+}
+
+/// The `signature` field as it ends a signed header, closing brace included.
+fn signature_field(public_key: &str, signature: &str) -> Result<Vec<u8>, ContainerError> {
+    let string = |s: &str| serde_json::to_string(s).map_err(ContainerError::Header);
+    Ok(format!(
+        ",\"signature\":{{\"public_key\":{},\"signature\":{}}}}}",
+        string(public_key)?,
+        string(signature)?,
+    ).into_bytes())
+}
+
+/// The raw header up to where its `signature` field is (or would be), which
+/// is everything but the closing brace for unsigned headers.
+fn unsigned_prefix<'a>(container: &ContainerFileInfo, raw: &'a [u8]) -> Result<&'a [u8], Error> {
+    match &container.header.signature {
+        Some(signed) => raw.strip_suffix(&signature_field(&signed.public_key, &signed.signature)?[..])
+            .ok_or_else(|| SignatureError::Invalid.into()),
+        // reading the header stops right after its closing brace
+        None => raw.strip_suffix(b"}")
+            .ok_or_else(|| ContainerError::NotTomochan.into()),
+    }
+}
+
+fn signed_message(prefix: &[u8], public_key: &str, payload_sha256: &[u8; 32]) -> Result<Vec<u8>, ContainerError> {
+    let mut message = SIGNATURE_CONTEXT.to_vec();
+    message.extend_from_slice(prefix);
+    message.extend(signature_field(public_key, "")?);
+    message.extend_from_slice(payload_sha256);
+    Ok(message)
+}
+
+/// Signs the container at `src` with `key`, and writes the signed container
+/// to `dst`, which can be the same file. Any previous signature is replaced.
+pub fn sign_container(src: &str, dst: &str, key: &SigningKey) -> Result<(), Error> {
+    let mut file = File::open(src)
+        .map_err(|source| SignatureError::Io { path: src.into(), source })?;
+    let container = ContainerFileInfo::read_container(&mut file)?;
+    container.validate_payload(&mut file)?;
+    let raw = container.read_raw_header(&mut file)?;
+    let prefix = unsigned_prefix(&container, &raw)?;
+
+    let public_key = encode_public_key(&key.verifying_key());
+    let signature = key.sign(&signed_message(prefix, &public_key, &container.header.payload_sha256)?);
+
+    // written next to `dst` and renamed over it, so that a failed write
+    // leaves the original in place when signing in place
+    let io_error = |source| SignatureError::Io { path: dst.into(), source };
+    let mut signed = AtomicFile::create(dst).map_err(io_error)?;
+    signed.file().write_all(prefix).map_err(io_error)?;
+    signed.file().write_all(&signature_field(&public_key, &BASE64.encode(signature.to_bytes()))?).map_err(io_error)?;
+
+    file.seek(SeekFrom::Start(container.payload_offset))
+        .map_err(|source| SignatureError::Io { path: src.into(), source })?;
+    io::copy(&mut file.take(container.header.payload_length), signed.file())
+        .map_err(io_error)?;
+    signed.commit().map_err(io_error)?;
+    Ok(())
+}
+
+/// Checks that the container at `path` is intact and signed by one of
+/// `trusted`, and returns the signer.
+pub fn verify_container(path: &str, trusted: &TrustedKeys) -> Result<TrustedKey, Error> {
+    let mut file = File::open(path)
+        .map_err(ContainerError::Io)?;
+    let container = ContainerFileInfo::read_container(&mut file)?;
+
+    let signer = verify_header(&container, &mut file, trusted)?;
+    container.validate_payload(file)?;
+    Ok(signer.clone())
+}
+
+/// Checks the signature of a container that is already open against the
+/// process wide trusted keys. The caller checks the payload.
+pub(crate) fn verify_opened_header(container: &ContainerFileInfo, file: &mut File) -> Result<TrustedKey, Error> {
+    let verification = VERIFICATION.read().unwrap_or_else(|e| e.into_inner());
+    verify_header(container, file, &verification.trusted).cloned()
+}
+
+fn verify_header<'a>(container: &ContainerFileInfo, file: &mut File, trusted: &'a TrustedKeys) -> Result<&'a TrustedKey, Error> {
+    let signed = container.header.signature.as_ref()
+        .ok_or(SignatureError::Unsigned)?;
+    let key = decode_public_key(&signed.public_key)?;
+    let signer = trusted.find(&key)
+        .ok_or_else(|| SignatureError::UntrustedKey(signed.public_key.clone()))?;
+
+    let signature = BASE64.decode(&signed.signature).ok()
+        .and_then(|bytes| Signature::from_slice(&bytes).ok())
+        .ok_or(SignatureError::Invalid)?;
+    let raw = container.read_raw_header(file)?;
+    let message = signed_message(unsigned_prefix(container, &raw)?, &signed.public_key, &container.header.payload_sha256)?;
+    key.verify_strict(&message, &signature)
+        .map_err(|_| SignatureError::Invalid)?;
+
+    Ok(signer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::format::dictionary_convert::ConvertOptions;
+    use crate::format::test_util::{build_dictionary, scratch_dir, term};
+
+    fn trusting(key: &SigningKey) -> TrustedKeys {
+        TrustedKeys {
+            keys: vec![TrustedKey { name: "test".into(), public_key: encode_public_key(&key.verifying_key()) }],
+        }
+    }
+
+    #[test]
+    fn sign_and_verify() {
+        let dir = scratch_dir("signature");
+        let path = build_dictionary(&dir, "dict.tomo", "Dict", &[term("犬", "いぬ", "dog")], &[], &ConvertOptions::default());
+        let path = path.to_str().unwrap();
+        let key = generate_signing_key().unwrap();
+        let other = generate_signing_key().unwrap();
+
+        assert!(matches!(verify_container(path, &trusting(&key)), Err(Error::Signature(SignatureError::Unsigned))));
+
+        sign_container(path, path, &key).unwrap();
+        assert_eq!(verify_container(path, &trusting(&key)).unwrap().name, "test");
+        assert!(matches!(
+            verify_container(path, &trusting(&other)),
+            Err(Error::Signature(SignatureError::UntrustedKey(k))) if k == encode_public_key(&key.verifying_key())
+        ));
+
+        let signed = fs::read(path).unwrap();
+        let tampered = dir.join("tampered.tomo");
+        let tampered = tampered.to_str().unwrap();
+
+        // same length, so the header still parses
+        let title = signed.windows(6).position(|w| w == b"\"Dict\"").unwrap();
+        let mut header = signed.clone();
+        header[title + 4] = b'x';
+        fs::write(tampered, &header).unwrap();
+        assert!(matches!(verify_container(tampered, &trusting(&key)), Err(Error::Signature(SignatureError::Invalid))));
+
+        let mut payload = signed.clone();
+        *payload.last_mut().unwrap() ^= 1;
+        fs::write(tampered, &payload).unwrap();
+        assert!(matches!(verify_container(tampered, &trusting(&key)), Err(Error::Container(ContainerError::PayloadHashMismatch))));
+
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn signatures_cover_unknown_header_fields() {
+        let dir = scratch_dir("signature-raw-header");
+        let path = build_dictionary(&dir, "dict.tomo", "Dict", &[term("犬", "いぬ", "dog")], &[], &ConvertOptions::default());
+        let key = generate_signing_key().unwrap();
+        let other = generate_signing_key().unwrap();
+
+        // a field from a newer version, which this one can't serialize again
+        let original = fs::read(&path).unwrap();
+        let header_start = "TOMOCHAN:XXXX:{".len();
+        let mut extended = original[..header_start].to_vec();
+        extended.extend_from_slice(b"\"future\":1,");
+        extended.extend_from_slice(&original[header_start..]);
+        let unsigned = dir.join("extended.tomo");
+        fs::write(&unsigned, &extended).unwrap();
+
+        let signed = dir.join("signed.tomo");
+        let (unsigned, signed) = (unsigned.to_str().unwrap(), signed.to_str().unwrap());
+        sign_container(unsigned, signed, &other).unwrap();
+        // signing again replaces the signature
+        sign_container(signed, signed, &key).unwrap();
+        assert!(!dir.join("signed.tomo.tmp").exists());
+        assert_eq!(verify_container(signed, &trusting(&key)).unwrap().name, "test");
+        assert!(verify_container(signed, &trusting(&other)).is_err());
+
+        let data = fs::read(signed).unwrap();
+        assert!(data.ends_with(&original[original.len() - 100..]));
+        let field = data.windows(10).position(|w| w == b"\"future\":1").unwrap();
+        let mut tampered = data.clone();
+        tampered[field + 9] = b'2';
+        fs::write(signed, &tampered).unwrap();
+        assert!(matches!(verify_container(signed, &trusting(&key)), Err(Error::Signature(SignatureError::Invalid))));
+
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn key_files_are_private_and_not_overwritten() {
+        let dir = scratch_dir("signing-key");
+        let path = dir.join("key");
+        let path = path.to_str().unwrap();
+        let key = generate_signing_key().unwrap();
+
+        write_signing_key(path, &key).unwrap();
+        assert_eq!(read_signing_key(path).unwrap().to_bytes(), key.to_bytes());
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(fs::metadata(path).unwrap().permissions().mode() & 0o777, 0o600);
+        }
+
+        let other = generate_signing_key().unwrap();
+        assert!(matches!(write_signing_key(path, &other), Err(SignatureError::Io { .. })));
+        assert_eq!(read_signing_key(path).unwrap().to_bytes(), key.to_bytes());
+
+        let _ = fs::remove_dir_all(dir);
+    }
+}
@@ -1,10 +1,98 @@
 use crate::schema::BINCODE_CONFIG;
+use crate::error::StoreError;
+use super::frame_cache::{FrameCache, next_store_id};
+use bincode::{Encode, Decode};
+use memmap2::Mmap;
 use std::cell::RefCell;
-use std::io::Write;
+use std::io::{self, Read, Seek, SeekFrom, Write};
 use std::rc::Rc;
-use zeekstd::{Decoder, EncodeOptions, Seekable};
+use std::sync::{Arc, Mutex};
+use zeekstd::{DecodeOptions, Decoder, EncodeOptions, FrameSizePolicy, SeekTable, Seekable, SEEKABLE_MAX_FRAME_SIZE};
+use zstd_safe::{CCtx, CParameter, DCtx};
 
-const ZSTD_COMPRESSION_LEVEL: i32 = 19;
+// With content defined frames, frames end after entries whose hash has the
+// low bits of BOUNDARY_MASK clear, once they hold at least 1/MIN_FRAME_DIVISOR
+// of the frame size. Because boundaries depend on the entries rather than
+// their offsets, adding or removing an entry only changes the frames around
+// it, and the compressed bytes of the rest of the store stay the same between
+// revisions, which keeps patches small. The frame size bounds frames in
+// stores without any boundaries.
+const MIN_FRAME_DIVISOR: u32 = 16;
+const BOUNDARY_MASK: u64 = 0xff;
+
+// zstd suggests training dictionaries on about 100 times their size of
+// samples. The samples are the first items inserted in the store, and are
+// held in memory until training, so there are never more than
+// MAX_SAMPLE_BYTES of them.
+const SAMPLES_PER_DICTIONARY_BYTE: u64 = 100;
+const MAX_SAMPLE_BYTES: u64 = 128 << 20;
+
+/// How a store is compressed. Smaller frames make lookups faster, since a
+/// lookup decompresses the frame holding its entry up to the entry, and
+/// larger frames and higher levels make stores smaller. Options are recorded
+/// in dictionary headers, but readers don't need them.
+#[derive(Encode, Decode, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StoreOptions {
+    /// zstd compression level, from 1 to 22.
+    pub compression_level: i32,
+    /// Uncompressed size of a frame in bytes. Frames are this size except
+    /// for the last one, unless `content_defined_frames` is set.
+    pub frame_size: u32,
+    /// Ends frames at boundaries chosen by the entries themselves, so that
+    /// unchanged entries compress to the same bytes in the next revision,
+    /// and patches between revisions stay small.
+    /// Frames then end about 256 entries after reaching 1/16 of
+    /// `frame_size`, which for dictionary entries is usually well below it,
+    /// and `frame_size` only bounds them.
+    pub content_defined_frames: bool,
+    /// Lets zstd find matches further back than the level normally allows,
+    /// which only helps stores with frames of several MiB.
+    pub long_distance_matching: bool,
+    /// Threads compressing in the background, 0 to compress on the calling
+    /// thread.
+    pub threads: u32,
+    /// Size of a zstd dictionary trained on the first items of the store, 0
+    /// for no dictionary. Items share a lot of structure that each frame
+    /// would otherwise have to learn again, so a dictionary keeps small
+    /// frames almost as compact as large ones. A newly trained dictionary
+    /// changes every frame, so patches between revisions are only small if
+    /// the new revision reuses the dictionary of the old one (see
+    /// [`StoreBuilder::with_dictionary`]).
+    pub dictionary_size: u32,
+}
+
+impl Default for StoreOptions {
+    fn default() -> Self {
+        Self {
+            compression_level: 19,
+            frame_size: 0x200_000,
+            content_defined_frames: false,
+            long_distance_matching: false,
+            threads: 0,
+            dictionary_size: 0,
+        }
+    }
+}
+
+impl StoreOptions {
+    /// Checks the options, and returns them as a store would use them. Frame
+    /// sizes are clamped to what the seekable format allows.
+    pub fn validate(&self) -> Result<StoreOptions, StoreError> {
+        if !(1..=zstd_safe::max_c_level()).contains(&self.compression_level) {
+            return Err(StoreError::InvalidCompressionLevel(self.compression_level));
+        }
+
+        Ok(StoreOptions {
+            frame_size: self.frame_size.clamp(1, SEEKABLE_MAX_FRAME_SIZE as u32),
+            ..*self
+        })
+    }
+}
+
+/// FNV-1a, only used to pick frame boundaries.
+fn boundary_hash(data: &[u8]) -> u64 {
+    data.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &b| (hash ^ b as u64).wrapping_mul(0x100_0000_01b3))
+}
 
 // i cannot believe i need to do this :(
 struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
@@ -20,77 +108,578 @@ impl Write for SharedBuffer {
     }
 }
 
+/// Splits the data written to an encoder into frames (see BOUNDARY_MASK).
+struct FrameSplitter {
+    written: u64,
+    frame_start: u64,
+    frame_size: u64,
+    /// Unset if frames are only ended by the encoder once full.
+    min_frame_size: Option<u64>,
+}
+
+impl FrameSplitter {
+    fn write(&mut self, encoder: &mut zeekstd::Encoder<'static, SharedBuffer>, data: &[u8]) -> Result<(), StoreError> {
+        encoder
+            .write_all(data)
+            .map_err(StoreError::Io)?;
+
+        self.written += data.len() as u64;
+
+        // the encoder ends full frames by itself
+        while self.written - self.frame_start >= self.frame_size {
+            self.frame_start += self.frame_size;
+        }
+
+        if self.min_frame_size.is_some_and(|min| self.written - self.frame_start >= min)
+            && boundary_hash(data) & BOUNDARY_MASK == 0
+        {
+            encoder.end_frame()?;
+            self.frame_start = self.written;
+        }
+
+        Ok(())
+    }
+}
+
+/// Compressed data of a store, along with the dictionary it was compressed
+/// with, which is needed to read it back.
+pub struct StoreData {
+    pub data: Vec<u8>,
+    pub dictionary: Option<Vec<u8>>,
+}
+
 pub struct StoreBuilder {
-    encoder: zeekstd::Encoder<'static, SharedBuffer>,
+    /// Not created until a dictionary has been trained, if the options ask
+    /// for one.
+    encoder: Option<zeekstd::Encoder<'static, SharedBuffer>>,
+    options: StoreOptions,
     buffer: Rc<RefCell<Vec<u8>>>,
     current_offset: u64,
+    frames: FrameSplitter,
+    /// Items inserted before the encoder was created, back to back.
+    samples: Vec<u8>,
+    sample_sizes: Vec<usize>,
+    dictionary: Option<Vec<u8>>,
 }
 
 impl StoreBuilder {
-    pub fn new() -> Result<Self, String> {
-        let buffer = Rc::new(RefCell::new(Vec::new()));
-        let writer = SharedBuffer(Rc::clone(&buffer));
+    pub fn new() -> Result<Self, StoreError> {
+        Self::with_options(&StoreOptions::default())
+    }
 
-        let encoder = EncodeOptions::new()
-            .compression_level(ZSTD_COMPRESSION_LEVEL)
-            // .frame_size_policy(FrameSizePolicy::Uncompressed(8192))
-            .into_encoder(writer)
-            .map_err(|e| format!("Failed to create Encoder: {}", e))?;
+    pub fn with_options(options: &StoreOptions) -> Result<Self, StoreError> {
+        let options = options.validate()?;
+        let frame_size = options.frame_size;
 
-        Ok(Self {
-            encoder,
-            buffer,
+        let mut builder = Self {
+            encoder: None,
+            options,
+            buffer: Rc::new(RefCell::new(Vec::new())),
             current_offset: 0,
-        })
+            frames: FrameSplitter {
+                written: 0,
+                frame_start: 0,
+                frame_size: frame_size as u64,
+                min_frame_size: options.content_defined_frames.then_some((frame_size / MIN_FRAME_DIVISOR) as u64),
+            },
+            samples: Vec::new(),
+            sample_sizes: Vec::new(),
+            dictionary: None,
+        };
+
+        if options.dictionary_size == 0 {
+            builder.start_encoder()?;
+        }
+
+        Ok(builder)
     }
 
-    pub fn insert<T: bincode::Encode>(&mut self, item: &T) -> Result<u64, String> {
-        let serialized = bincode::encode_to_vec(item, BINCODE_CONFIG)
-            .map_err(|e| format!("bincode serialization failed: {}", e))?;
+    /// Store compressed with an existing dictionary instead of training one,
+    /// usually the one of the previous revision, so frames that did not
+    /// change compress to the same bytes.
+    pub fn with_dictionary(options: &StoreOptions, dictionary: Vec<u8>) -> Result<Self, StoreError> {
+        let options = StoreOptions { dictionary_size: dictionary.len() as u32, ..*options };
+        let mut builder = Self::with_options(&options)?;
+        if !dictionary.is_empty() {
+            builder.dictionary = Some(dictionary);
+            builder.start_encoder()?;
+        }
+        Ok(builder)
+    }
+
+    /// Options the store is built with, see [`StoreOptions::validate`].
+    pub fn options(&self) -> &StoreOptions {
+        &self.options
+    }
+
+    pub fn insert<T: bincode::Encode>(&mut self, item: &T) -> Result<u64, StoreError> {
+        let serialized = bincode::encode_to_vec(item, BINCODE_CONFIG)?;
 
         let offset = self.current_offset;
-        let len = serialized.len() as u64;
+        self.current_offset += serialized.len() as u64;
 
-        self.encoder
-            .write_all(&serialized)
-            .map_err(|e| format!("Failed to write data: {}", e))?;
+        match &mut self.encoder {
+            Some(encoder) => self.frames.write(encoder, &serialized)?,
+            None => {
+                self.samples.extend_from_slice(&serialized);
+                self.sample_sizes.push(serialized.len());
+
+                let wanted = (self.options.dictionary_size as u64 * SAMPLES_PER_DICTIONARY_BYTE).min(MAX_SAMPLE_BYTES);
+                if self.samples.len() as u64 >= wanted {
+                    self.start_encoder()?;
+                }
+            }
+        }
 
-        self.current_offset += len;
         Ok(offset)
     }
 
-    pub fn finalize(self) -> Result<Vec<u8>, String> {
-        self.encoder
-            .finish()
-            .map_err(|e| format!("Failed to finalize compressed data: {}", e))?;
+    /// Trains the dictionary on the samples if there should be one, and
+    /// compresses the samples.
+    fn start_encoder(&mut self) -> Result<(), StoreError> {
+        if self.dictionary.is_none() && self.options.dictionary_size > 0 {
+            let mut dictionary = Vec::with_capacity(self.options.dictionary_size as usize);
+            // training fails when there are too few samples to learn from,
+            // and such small stores are fine without a dictionary
+            if zstd_safe::train_from_buffer(&mut dictionary, &self.samples, &self.sample_sizes).is_ok() {
+                self.dictionary = Some(dictionary);
+            }
+        }
+
+        let mut cctx = CCtx::create();
+        cctx.set_parameter(CParameter::EnableLongDistanceMatching(self.options.long_distance_matching))
+            .map_err(zeekstd::Error::from)?;
+        cctx.set_parameter(CParameter::NbWorkers(self.options.threads))
+            .map_err(zeekstd::Error::from)?;
+        if let Some(dictionary) = &self.dictionary {
+            cctx.load_dictionary(dictionary)
+                .map_err(zeekstd::Error::from)?;
+        }
 
-        Rc::try_unwrap(self.buffer)
-            .map_err(|_| "Failed to extract buffer".to_string())
-            .map(|refcell| refcell.into_inner())
+        let mut encoder = EncodeOptions::with_cctx(cctx)
+            .compression_level(self.options.compression_level)
+            .frame_size_policy(FrameSizePolicy::Uncompressed(self.options.frame_size))
+            .into_encoder(SharedBuffer(Rc::clone(&self.buffer)))?;
+
+        let samples = std::mem::take(&mut self.samples);
+        let mut start = 0;
+        for size in std::mem::take(&mut self.sample_sizes) {
+            self.frames.write(&mut encoder, &samples[start..start + size])?;
+            start += size;
+        }
+
+        self.encoder = Some(encoder);
+        Ok(())
     }
+
+    pub fn finalize(mut self) -> Result<StoreData, StoreError> {
+        if self.encoder.is_none() {
+            self.start_encoder()?;
+        }
+
+        // unwrap is ok b/c the encoder was just started
+        self.encoder.take().unwrap().finish()?;
+
+        // unwrap is ok b/c the encoder holding the other reference was consumed by finish
+        Ok(StoreData {
+            data: Rc::try_unwrap(self.buffer).unwrap().into_inner(),
+            dictionary: self.dictionary,
+        })
+    }
+}
+
+/// Items are stored raw instead of in the zstd store when compressing them
+/// saves less than 1/RAW_MIN_SAVING of their size. Checking with a fast
+/// level is enough to tell images, audio and other compressed formats apart.
+const RAW_MIN_SAVING: usize = 8;
+const RAW_CHECK_LEVEL: i32 = 1;
+
+/// Offsets of items in the raw region have this bit set, so a single index
+/// can point into either region.
+pub const RAW_OFFSET_FLAG: u64 = 1 << 63;
+
+/// Whether zstd makes `data` smaller by enough to be worth decompressing it.
+pub fn is_compressible(data: &[u8]) -> bool {
+    let mut compressed = Vec::with_capacity(zstd_safe::compress_bound(data.len()));
+    zstd_safe::compress(&mut compressed, data, RAW_CHECK_LEVEL)
+        .is_ok_and(|len| len < data.len() - data.len() / RAW_MIN_SAVING)
 }
 
-pub struct Store<S: Seekable> {
-    decoder: Decoder<'static, S>,
+/// Builds a region of uncompressed items, for data that is compressed
+/// already. Each item is its length as a little endian u64, followed by its
+/// bytes, so it can be read straight out of a memory map.
+#[derive(Default)]
+pub struct RawBuilder {
+    data: Vec<u8>,
 }
 
-impl<S: Seekable> Store<S> {
-    pub fn new(src: S) -> Result<Self, String> {
-        let decoder = Decoder::new(src)
-            .map_err(|e| format!("Failed to create Decoder: {}", e))?;
+impl RawBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
 
-        Ok(Self { decoder })
+    /// Returns the offset of the item, with [`RAW_OFFSET_FLAG`] set.
+    pub fn insert(&mut self, item: &[u8]) -> u64 {
+        let offset = self.data.len() as u64;
+        self.data.extend_from_slice(&(item.len() as u64).to_le_bytes());
+        self.data.extend_from_slice(item);
+        offset | RAW_OFFSET_FLAG
     }
 
-    pub fn get<T: bincode::Decode<()>>(&mut self, offset: u64) -> Result<T, String> {
-        self.decoder
-            .set_offset(offset)
-            .map_err(|e| format!("Failed to seek to offset: {}", e))?;
+    pub fn finalize(self) -> Vec<u8> {
+        self.data
+    }
+}
 
-        let (item, _): (T, usize) =
-            bincode::decode_from_std_read(&mut self.decoder, BINCODE_CONFIG)
-                .map_err(|e| format!("bincode deserialization failed: {}", e))?;
+/// Region of uncompressed items written by a [`RawBuilder`]. Items are
+/// borrowed from the memory map without copying.
+pub struct RawRegion {
+    region: MappedRegion,
+}
 
-        Ok(item)
+impl RawRegion {
+    pub fn new(region: MappedRegion) -> Self {
+        Self { region }
     }
-}
\ No newline at end of file
+
+    /// `offset` is as returned by [`RawBuilder::insert`].
+    pub fn get(&self, offset: u64) -> Result<&[u8], StoreError> {
+        let data = self.region.data();
+        let start = usize::try_from(offset & !RAW_OFFSET_FLAG).ok();
+
+        let item = start
+            .and_then(|start| Some((start, data.get(start..start.checked_add(8)?)?)))
+            .and_then(|(start, len)| {
+                // unwrap is ok b/c the slice is 8 bytes long
+                let len = usize::try_from(u64::from_le_bytes(len.try_into().unwrap())).ok()?;
+                data.get(start + 8..(start + 8).checked_add(len)?)
+            });
+
+        item.ok_or(StoreError::InvalidRawOffset(offset & !RAW_OFFSET_FLAG))
+    }
+}
+
+/// A section of a memory mapped file, with offsets relative to the start of
+/// the section. The zstd seek table stores frame offsets relative to the start
+/// of the compressed data, so the store needs to be isolated from the rest of
+/// the container. Regions only read from the map, so any number of them can
+/// read the same file at once.
+#[derive(Clone)]
+pub struct MappedRegion {
+    mmap: Arc<Mmap>,
+    start: usize,
+    len: usize,
+    pos: u64,
+}
+
+impl MappedRegion {
+    /// The region is cut short if it extends past the end of the map.
+    pub fn new(mmap: Arc<Mmap>, start: u64, len: u64) -> Self {
+        let start = usize::try_from(start).unwrap_or(usize::MAX).min(mmap.len());
+        let len = usize::try_from(len).unwrap_or(usize::MAX).min(mmap.len() - start);
+        Self { mmap, start, len, pos: 0 }
+    }
+
+    fn data(&self) -> &[u8] {
+        &self.mmap[self.start..self.start + self.len]
+    }
+}
+
+impl Read for MappedRegion {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let data = self.data();
+        let pos = usize::try_from(self.pos).unwrap_or(usize::MAX).min(data.len());
+        let read = buf.len().min(data.len() - pos);
+        buf[..read].copy_from_slice(&data[pos..pos + read]);
+        self.pos += read as u64;
+        Ok(read)
+    }
+}
+
+impl Seek for MappedRegion {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let new_pos = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(delta) => (self.len as u64).checked_add_signed(delta),
+            SeekFrom::Current(delta) => self.pos.checked_add_signed(delta),
+        }.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "seek out of range"))?;
+
+        self.pos = new_pos;
+        Ok(new_pos)
+    }
+}
+
+/// Compressed store of encoded items, read by offset. Lookups take `&self`
+/// and can run concurrently: each one borrows an idle decoder, or creates one
+/// if all of them are busy, and returns it afterwards so its decompression
+/// buffers are reused. With a [`FrameCache`], whole frames are decompressed
+/// and kept in the cache instead.
+pub struct Store<S: Seekable + Clone> {
+    id: u64,
+    src: S,
+    seek_table: SeekTable,
+    dictionary: Option<Vec<u8>>,
+    decoders: Mutex<Vec<Decoder<'static, S>>>,
+    cache: Option<Arc<FrameCache>>,
+}
+
+impl<S: Seekable + Clone> Store<S> {
+    pub fn new(src: S) -> Result<Self, StoreError> {
+        Self::with_dictionary(src, None)
+    }
+
+    /// Store compressed with `dictionary` (see [`StoreData`]).
+    pub fn with_dictionary(src: S, dictionary: Option<Vec<u8>>) -> Result<Self, StoreError> {
+        let seek_table = SeekTable::from_seekable(&mut src.clone())?;
+
+        Ok(Self {
+            id: next_store_id(),
+            src,
+            seek_table,
+            dictionary,
+            decoders: Mutex::new(Vec::new()),
+            cache: None,
+        })
+    }
+
+    pub fn dictionary(&self) -> Option<&[u8]> {
+        self.dictionary.as_deref()
+    }
+
+    pub fn cache(&self) -> Option<&Arc<FrameCache>> {
+        self.cache.as_ref()
+    }
+
+    pub fn set_cache(&mut self, cache: Option<Arc<FrameCache>>) {
+        self.cache = cache;
+    }
+
+    pub fn get<T: bincode::Decode<()>>(&self, offset: u64) -> Result<T, StoreError> {
+        if let Some(cache) = &self.cache {
+            let index = self.seek_table.frame_index_decomp(offset);
+            let start = self.seek_table.frame_start_decomp(index)?;
+            let mut reader = CachedFrameReader {
+                store: self,
+                cache,
+                index,
+                frame: self.frame(cache, index)?,
+                pos: usize::try_from(offset - start).unwrap_or(usize::MAX),
+            };
+
+            return Ok(bincode::decode_from_std_read(&mut reader, BINCODE_CONFIG)?);
+        }
+
+        self.with_decoder(|decoder| {
+            decoder.set_offset(offset)?;
+
+            let item: T = bincode::decode_from_std_read(decoder, BINCODE_CONFIG)?;
+
+            Ok(item)
+        })
+    }
+
+    /// Decompresses the whole store, which checks every frame.
+    pub fn verify(&self) -> Result<(), StoreError> {
+        self.with_decoder(|decoder| {
+            decoder.set_offset(0)?;
+            io::copy(decoder, &mut io::sink()).map_err(StoreError::Io)?;
+            Ok(())
+        })
+    }
+
+    /// Reads the decompressed store from `offset` on, for reading items one
+    /// after another in the order they were inserted.
+    pub fn reader(&self, offset: u64) -> Result<Decoder<'static, S>, StoreError> {
+        let mut decoder = self.new_decoder()?;
+        decoder.set_offset(offset)?;
+        Ok(decoder)
+    }
+
+    /// Checks that an item could start at `offset`.
+    pub fn check_offset(&self, offset: u64) -> Result<(), StoreError> {
+        if offset >= self.seek_table.size_decomp() {
+            return Err(StoreError::InvalidOffset(offset));
+        }
+        Ok(())
+    }
+
+    fn with_decoder<R>(&self, f: impl FnOnce(&mut Decoder<'static, S>) -> Result<R, StoreError>) -> Result<R, StoreError> {
+        // the lock only guards pushing and popping, so a poisoned pool is still usable
+        let idle = self.decoders.lock().unwrap_or_else(|e| e.into_inner()).pop();
+        let mut decoder = match idle {
+            Some(decoder) => decoder,
+            None => self.new_decoder()?,
+        };
+
+        let result = f(&mut decoder);
+        self.decoders.lock().unwrap_or_else(|e| e.into_inner()).push(decoder);
+        result
+    }
+
+    fn new_decoder(&self) -> Result<Decoder<'static, S>, StoreError> {
+        let mut dctx = DCtx::create();
+        if let Some(dictionary) = &self.dictionary {
+            dctx.load_dictionary(dictionary)
+                .map_err(zeekstd::Error::from)?;
+        }
+
+        Ok(DecodeOptions::with_dctx(self.src.clone(), dctx)
+            .seek_table(self.seek_table.clone())
+            .into_decoder()?)
+    }
+
+    fn frame(&self, cache: &FrameCache, index: u32) -> Result<Arc<[u8]>, StoreError> {
+        cache.get_or_load(self.id, index, || {
+            let start = self.seek_table.frame_start_decomp(index)?;
+            let end = self.seek_table.frame_end_decomp(index)?;
+
+            self.with_decoder(|decoder| {
+                decoder.set_offset(start)?;
+                decoder.set_offset_limit(end)?;
+
+                let mut data = Vec::with_capacity(usize::try_from(end - start).unwrap_or(0));
+                let read = decoder.read_to_end(&mut data);
+                decoder.set_offset_limit(self.seek_table.size_decomp())?;
+
+                read.map_err(StoreError::Io)?;
+                Ok(data)
+            })
+        })
+    }
+}
+
+/// Reads items out of cached frames, moving on to the next frame for items
+/// that continue past the end of one.
+struct CachedFrameReader<'a, S: Seekable + Clone> {
+    store: &'a Store<S>,
+    cache: &'a FrameCache,
+    index: u32,
+    frame: Arc<[u8]>,
+    pos: usize,
+}
+
+impl<S: Seekable + Clone> Read for CachedFrameReader<'_, S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pos >= self.frame.len() {
+            if self.index + 1 >= self.store.seek_table.num_frames() {
+                return Ok(0);
+            }
+
+            self.index += 1;
+            self.frame = self.store.frame(self.cache, self.index)
+                .map_err(io::Error::other)?;
+            self.pos = 0;
+        }
+
+        let read = buf.len().min(self.frame.len() - self.pos);
+        buf[..read].copy_from_slice(&self.frame[self.pos..self.pos + read]);
+        self.pos += read;
+        Ok(read)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::format::test_util::scratch_dir;
+    use std::fs;
+
+    #[test]
+    fn options_are_validated() {
+        let options = StoreOptions { frame_size: u32::MAX, ..StoreOptions::default() };
+        let effective = options.validate().unwrap();
+        assert_eq!(effective.frame_size, SEEKABLE_MAX_FRAME_SIZE as u32);
+        assert_eq!(StoreBuilder::with_options(&options).unwrap().options(), &effective);
+
+        let options = StoreOptions { frame_size: 0, ..StoreOptions::default() };
+        assert_eq!(options.validate().unwrap().frame_size, 1);
+
+        for level in [0, -1, zstd_safe::max_c_level() + 1] {
+            let options = StoreOptions { compression_level: level, ..StoreOptions::default() };
+            assert!(matches!(options.validate(), Err(StoreError::InvalidCompressionLevel(l)) if l == level));
+            assert!(StoreBuilder::with_options(&options).is_err());
+        }
+    }
+
+    #[test]
+    fn content_defined_frames_are_opt_in() {
+        let items: Vec<String> = (0..20_000).map(|i| format!("item number {}", i)).collect();
+        let frame_sizes = |content_defined_frames: bool| {
+            let options = StoreOptions { frame_size: 64 << 10, content_defined_frames, ..StoreOptions::default() };
+            let mut builder = StoreBuilder::with_options(&options).unwrap();
+            for item in &items {
+                builder.insert(item).unwrap();
+            }
+            let data = builder.finalize().unwrap().data;
+            let table = SeekTable::from_seekable(&mut io::Cursor::new(data)).unwrap();
+            (0..table.num_frames())
+                .map(|i| table.frame_end_decomp(i).unwrap() - table.frame_start_decomp(i).unwrap())
+                .collect::<Vec<_>>()
+        };
+
+        let fixed = frame_sizes(false);
+        let (last, full) = fixed.split_last().unwrap();
+        assert!(full.iter().all(|&size| size == 64 << 10));
+        assert!(*last <= 64 << 10);
+
+        let content_defined = frame_sizes(true);
+        assert!(content_defined.len() > fixed.len());
+        assert!(content_defined.iter().all(|&size| size <= 64 << 10));
+    }
+
+    #[test]
+    fn reused_dictionary_reproduces_store() {
+        let items: Vec<String> = (0..4000)
+            .map(|i| format!("{{\"term\":\"entry {}\",\"reading\":\"reading {}\",\"definition\":\"definition number {}\"}}", i, i % 97, i * 7))
+            .collect();
+        let build = |mut builder: StoreBuilder| {
+            for item in &items {
+                builder.insert(item).unwrap();
+            }
+            builder.finalize().unwrap()
+        };
+
+        let options = StoreOptions { dictionary_size: 4 << 10, ..StoreOptions::default() };
+        let trained = build(StoreBuilder::with_options(&options).unwrap());
+        let dictionary = trained.dictionary.clone().expect("dictionary is trained");
+
+        let reused = build(StoreBuilder::with_dictionary(&options, dictionary.clone()).unwrap());
+        assert_eq!(reused.dictionary, Some(dictionary));
+        assert_eq!(reused.data, trained.data);
+    }
+
+    #[test]
+    fn raw_region_round_trip() {
+        let mut builder = RawBuilder::new();
+        let first = builder.insert(b"first item");
+        let empty = builder.insert(b"");
+        let last = builder.insert(&[0xff; 100]);
+        assert!([first, empty, last].iter().all(|offset| offset & RAW_OFFSET_FLAG != 0));
+
+        // the region starts after other data, like in a container
+        let mut file = b"unrelated".to_vec();
+        let raw = builder.finalize();
+        file.extend_from_slice(&raw);
+        let dir = scratch_dir("raw-region");
+        let path = dir.join("raw");
+        fs::write(&path, &file).unwrap();
+        let mmap = Arc::new(unsafe { Mmap::map(&fs::File::open(&path).unwrap()).unwrap() });
+        let region = RawRegion::new(MappedRegion::new(mmap, 9, raw.len() as u64));
+
+        assert_eq!(region.get(first).unwrap(), b"first item");
+        assert_eq!(region.get(empty).unwrap(), b"");
+        assert_eq!(region.get(last).unwrap(), &[0xff; 100]);
+        // the flag is optional
+        assert_eq!(region.get(first & !RAW_OFFSET_FLAG).unwrap(), b"first item");
+
+        let end = raw.len() as u64;
+        for offset in [end, end - 1, last + 1, RAW_OFFSET_FLAG | (u64::MAX >> 1), u64::MAX] {
+            assert!(
+                matches!(region.get(offset), Err(StoreError::InvalidRawOffset(o)) if o == offset & !RAW_OFFSET_FLAG),
+                "offset {:#x}", offset,
+            );
+        }
+
+        let _ = fs::remove_dir_all(dir);
+    }
+}
@@ -0,0 +1,103 @@
+//! Helpers for tests that need dictionaries on disk.
+
+use super::container::allow_dev_version;
+use super::container::ContainerMeta;
+use super::deinflector_convert::convert_deinflector;
+use super::dictionary_convert::{ConvertOptions, convert_yomitan_dictionary};
+use serde_json::{Value, json};
+use std::fs::{self, OpenOptions};
+use std::io::{Seek, SeekFrom, Write};
+use zip::ZipWriter;
+use zip::write::SimpleFileOptions;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+static NEXT_DIR: AtomicUsize = AtomicUsize::new(0);
+
+/// Empty directory that is only used by one test.
+pub fn scratch_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!(
+        "tomochan-{}-{}-{}",
+        name,
+        std::process::id(),
+        NEXT_DIR.fetch_add(1, Ordering::Relaxed),
+    ));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Term bank row with a single text definition.
+pub fn term(term: &str, reading: &str, definition: &str) -> Value {
+    json!([term, reading, "", "", 0, [definition], 0, ""])
+}
+
+/// Term bank row with a single text definition and deinflection rules
+/// (ex. `v1`).
+pub fn term_with_rules(term: &str, reading: &str, rules: &str, definition: &str) -> Value {
+    json!([term, reading, "", rules, 0, [definition], 0, ""])
+}
+
+/// Writes the given transform set to `dir/<file_name>.json`, and converts it
+/// to a deinflector at `dir/<file_name>`.
+pub fn build_deinflector(dir: &Path, file_name: &str, transforms: Value) -> PathBuf {
+    allow_dev_version(true);
+
+    let src = dir.join(format!("{}.json", file_name));
+    fs::write(&src, transforms.to_string()).unwrap();
+
+    let dst = dir.join(file_name);
+    let meta = ContainerMeta { name: "Deinflector".into(), revision_name: "dev".into(), revision: 0 };
+    convert_deinflector(src.to_str().unwrap(), dst.to_str().unwrap(), meta).unwrap();
+    dst
+}
+
+/// Writes a yomitan dictionary with the given terms and media files to
+/// `dir/src`, and converts it to `dir/<file_name>`.
+pub fn build_dictionary(
+    dir: &Path,
+    file_name: &str,
+    title: &str,
+    terms: &[Value],
+    files: &[(&str, &[u8])],
+    options: &ConvertOptions,
+) -> PathBuf {
+    allow_dev_version(true);
+
+    let src = dir.join(format!("{}.src", file_name));
+    let _ = fs::remove_dir_all(&src);
+    fs::create_dir_all(&src).unwrap();
+
+    let index = json!({ "title": title, "revision": "1", "format": 3 });
+    fs::write(src.join("index.json"), index.to_string()).unwrap();
+    fs::write(src.join("term_bank_1.json"), Value::from(terms.to_vec()).to_string()).unwrap();
+    for (path, data) in files {
+        let path = src.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, data).unwrap();
+    }
+
+    let dst = dir.join(file_name);
+    convert_yomitan_dictionary(src.to_str().unwrap(), dst.to_str().unwrap(), options).unwrap();
+    dst
+}
+
+/// Overwrites part of a file without changing its size or modification time,
+/// like corruption that file metadata does not show.
+pub fn overwrite_in_place(path: impl AsRef<Path>, pos: SeekFrom, data: &[u8]) {
+    let modified = fs::metadata(&path).unwrap().modified().unwrap();
+    let mut file = OpenOptions::new().write(true).open(&path).unwrap();
+    file.seek(pos).unwrap();
+    file.write_all(data).unwrap();
+    file.set_modified(modified).unwrap();
+}
+
+/// Writes a zip archive holding the given files, with names as given.
+pub fn write_zip(path: &Path, files: &[(&str, &[u8])]) {
+    let mut zip = ZipWriter::new(fs::File::create(path).unwrap());
+    for (name, data) in files {
+        zip.start_file(*name, SimpleFileOptions::default()).unwrap();
+        zip.write_all(data).unwrap();
+    }
+    zip.finish().unwrap();
+}
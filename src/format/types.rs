@@ -1,4 +1,5 @@
 use crate::schema::{Term, Kanji, Tag, TermMeta, KanjiMeta};
+use crate::error::IndexError;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Ord, PartialOrd)]
 #[repr(u8)]
@@ -9,14 +10,52 @@ pub enum QueryKindKey {
     TermMeta = 0x03,
     KanjiMeta = 0x04,
     File = 0x05,
+    /// Secondary key for terms by their reading, pointing to the same store
+    /// entries as [`QueryKindKey::Term`].
+    TermReading = 0x06,
+}
+
+/// Which keys to search when looking up terms.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LookupMode {
+    #[default]
+    Term,
+    Reading,
+    TermOrReading,
+}
+
+impl LookupMode {
+    pub fn kinds(self) -> &'static [QueryKindKey] {
+        match self {
+            LookupMode::Term => &[QueryKindKey::Term],
+            LookupMode::Reading => &[QueryKindKey::TermReading],
+            LookupMode::TermOrReading => &[QueryKindKey::Term, QueryKindKey::TermReading],
+        }
+    }
 }
 
 impl QueryKindKey {
+    pub const ALL: [QueryKindKey; 7] = [
+        QueryKindKey::Term,
+        QueryKindKey::Kanji,
+        QueryKindKey::Tag,
+        QueryKindKey::TermMeta,
+        QueryKindKey::KanjiMeta,
+        QueryKindKey::File,
+        QueryKindKey::TermReading,
+    ];
+
     pub fn as_byte(self) -> u8 {
         self as u8
     }
     
-    pub fn from_byte(byte: u8) -> Result<Self, String> {
+    /// Whether keys of this kind are width folded in the index, see
+    /// [`index_key`](super::normalize::index_key).
+    pub fn is_normalized(self) -> bool {
+        matches!(self, QueryKindKey::Term | QueryKindKey::TermMeta | QueryKindKey::TermReading)
+    }
+
+    pub fn from_byte(byte: u8) -> Result<Self, IndexError> {
         match byte {
             0x00 => Ok(QueryKindKey::Term),
             0x01 => Ok(QueryKindKey::Kanji),
@@ -24,7 +63,8 @@ impl QueryKindKey {
             0x03 => Ok(QueryKindKey::TermMeta),
             0x04 => Ok(QueryKindKey::KanjiMeta),
             0x05 => Ok(QueryKindKey::File),
-            _ => Err("invalid key byte".to_string()),
+            0x06 => Ok(QueryKindKey::TermReading),
+            _ => Err(IndexError::InvalidKind(byte)),
         }
     }
 }
@@ -33,6 +73,11 @@ pub trait Queryable {
     const KIND: QueryKindKey;
 
     fn key(&self) -> String;
+
+    /// Secondary keys that should point to the same store entry.
+    fn additional_keys(&self) -> Vec<(QueryKindKey, String)> {
+        Vec::new()
+    }
 }
 
 impl Queryable for Term {
@@ -41,6 +86,14 @@ impl Queryable for Term {
     fn key(&self) -> String {
         self.term.clone()
     }
+
+    /// Every term is indexed by its effective reading, which is the term
+    /// itself for kana-only headwords where yomitan leaves `reading` empty or
+    /// repeats the term.
+    fn additional_keys(&self) -> Vec<(QueryKindKey, String)> {
+        let reading = if self.reading.is_empty() { &self.term } else { &self.reading };
+        vec![(QueryKindKey::TermReading, reading.clone())]
+    }
 }
 
 impl Queryable for Kanji {
@@ -0,0 +1,342 @@
+//! Updating installed dictionaries to newer revisions.
+//!
+//! Yomitan dictionaries marked `isUpdatable` link to the `index.json` of
+//! their latest revision (`indexUrl`), which in turn links to the archive to
+//! download (`downloadUrl`). An [`Updater`] fetches that index through a
+//! [`Transport`] and compares it to the installed revision. When it is newer,
+//! the archive is downloaded and converted next to the installed file, synced
+//! to disk, and then renamed over it. The rename is atomic, so readers (and a
+//! crash) see either the old or the new dictionary, and a failed update
+//! leaves the old one in place.
+
+use super::collection::DictionaryCollection;
+use super::container::{ContainerFileInfo, ContainerMeta, Role};
+use super::dictionary::Dictionary;
+use super::signature::{SignaturePolicy, signature_policy};
+use super::store::StoreOptions;
+use super::dictionary_convert::{ConversionReport, ConvertOptions, convert_uncommitted, revision_number, revision_parts};
+use super::atomic_file::AtomicFile;
+use crate::schema::DictionaryIndex as IndexInfo;
+use crate::error::{ContainerError, SchemaError, UpdateError};
+use std::fs;
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+/// Fetches remote resources for an [`Updater`].
+pub trait Transport {
+    /// Writes the resource at `url` to `out`.
+    fn download(&mut self, url: &str, out: &mut dyn Write) -> Result<(), UpdateError>;
+
+    fn fetch(&mut self, url: &str) -> Result<Vec<u8>, UpdateError> {
+        let mut data = Vec::new();
+        self.download(url, &mut data)?;
+        Ok(data)
+    }
+}
+
+/// Reads `file://` URLs and plain paths from the local filesystem.
+pub struct FileTransport;
+
+impl Transport for FileTransport {
+    fn download(&mut self, url: &str, out: &mut dyn Write) -> Result<(), UpdateError> {
+        let path = url.strip_prefix("file://").unwrap_or(url);
+        let mut file = File::open(path)
+            .map_err(|source| UpdateError::Io { path: path.into(), source })?;
+
+        io::copy(&mut file, out)
+            .map_err(|source| UpdateError::Io { path: path.into(), source })?;
+        Ok(())
+    }
+}
+
+/// Downloads with the `curl` command line tool, for applications that do
+/// not bring their own HTTP client.
+pub struct CurlTransport;
+
+impl Transport for CurlTransport {
+    fn download(&mut self, url: &str, out: &mut dyn Write) -> Result<(), UpdateError> {
+        let transport_error = |message: String| UpdateError::Transport { url: url.to_string(), message };
+
+        let mut child = Command::new("curl")
+            // redirects must not lead to file:// or other protocols curl
+            // supports, or a remote index could read local files
+            .args([
+                "--fail", "--silent", "--show-error", "--location",
+                "--proto", "=http,https", "--proto-redir", "=http,https",
+                "--", url,
+            ])
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| transport_error(format!("failed to run curl: {}", e)))?;
+
+        // unwrap is ok b/c stdout is piped
+        let copied = io::copy(&mut child.stdout.take().unwrap(), out);
+        let output = child.wait_with_output()
+            .map_err(|e| transport_error(format!("failed to run curl: {}", e)))?;
+
+        if !output.status.success() {
+            return Err(transport_error(String::from_utf8_lossy(&output.stderr).trim().to_string()));
+        }
+        copied.map_err(|e| transport_error(e.to_string()))?;
+
+        Ok(())
+    }
+}
+
+/// Newer revision of an installed dictionary.
+#[derive(Debug, Clone)]
+pub struct AvailableUpdate {
+    /// `index.json` of the new revision.
+    pub info: IndexInfo,
+    pub download_url: String,
+    /// Options the installed dictionary was compressed with, which the
+    /// update is compressed with as well.
+    pub store_options: StoreOptions,
+    /// zstd dictionary of the installed store, which the update reuses so
+    /// a patch to it stays small.
+    pub store_dictionary: Option<Vec<u8>>,
+}
+
+#[derive(Debug, Clone)]
+pub enum UpdateStatus {
+    /// The dictionary is not updatable, or does not say where to update from.
+    NotUpdatable,
+    UpToDate,
+    Available(Box<AvailableUpdate>),
+}
+
+pub struct Updater<T: Transport> {
+    transport: T,
+}
+
+impl<T: Transport> Updater<T> {
+    pub fn new(transport: T) -> Self {
+        Self { transport }
+    }
+
+    /// Fetches the latest `index.json` of a dictionary and compares it to
+    /// the installed revision.
+    pub fn check(&mut self, dictionary: &Dictionary) -> Result<UpdateStatus, UpdateError> {
+        let Some(info) = dictionary.info.as_ref().filter(|info| info.is_updatable) else {
+            return Ok(UpdateStatus::NotUpdatable);
+        };
+        let Some(index_url) = &info.index_url else {
+            return Ok(UpdateStatus::NotUpdatable);
+        };
+
+        let data = self.transport.fetch(index_url)?;
+        let remote = serde_json::from_slice::<serde_json::Value>(&data)
+            .map_err(|source| UpdateError::Json { url: index_url.clone(), source })?;
+        let remote = remote.as_object()
+            .ok_or(SchemaError::ExpectedObject("index"))
+            .and_then(IndexInfo::from_json)
+            .map_err(|source| UpdateError::Index { url: index_url.clone(), source })?;
+
+        if !is_newer(info, &remote) {
+            return Ok(UpdateStatus::UpToDate);
+        }
+
+        let Some(download_url) = remote.download_url.clone().or_else(|| info.download_url.clone()) else {
+            return Ok(UpdateStatus::NotUpdatable);
+        };
+
+        Ok(UpdateStatus::Available(Box::new(AvailableUpdate {
+            info: remote,
+            download_url,
+            store_options: dictionary.store_options,
+            store_dictionary: dictionary.store.dictionary().map(<[u8]>::to_vec),
+        })))
+    }
+
+    /// Checks every dictionary in a collection. Results are paired with the
+    /// path of the dictionary they are for.
+    pub fn check_collection(&mut self, collection: &DictionaryCollection) -> Vec<(String, Result<UpdateStatus, UpdateError>)> {
+        collection.entries()
+            .iter()
+            .map(|entry| (entry.path.clone(), self.check(&entry.dictionary)))
+            .collect()
+    }
+
+    /// Downloads and converts an update, and replaces the dictionary at
+    /// `path` with it. The dictionary keeps its name. Dictionaries that are
+    /// open keep reading the old revision until they are reopened (see
+    /// [`DictionaryCollection::reload`]). The converted dictionary replaces
+    /// the installed one only if it has its name and a newer revision.
+    ///
+    /// Converted dictionaries are not signed, so under
+    /// [`SignaturePolicy::Require`] updates are rejected before anything is
+    /// downloaded, rather than replacing a signed dictionary with one that
+    /// can't be opened.
+    pub fn apply(&mut self, path: &str, meta: &ContainerMeta, update: &AvailableUpdate) -> Result<ConversionReport, UpdateError> {
+        if signature_policy() == SignaturePolicy::Require {
+            return Err(UpdateError::SignatureRequired);
+        }
+
+        let download_path = format!("{}.download", path);
+
+        let result = self.download_and_convert(&download_path, path, meta, update)
+            .and_then(|(report, converted)| {
+                check_update(converted.tmp_path(), meta)?;
+                converted.commit()
+                    .map_err(|source| UpdateError::Io { path: path.into(), source })?;
+                Ok(report)
+            });
+
+        let _ = fs::remove_file(&download_path);
+        result
+    }
+
+    fn download_and_convert(
+        &mut self,
+        download_path: &str,
+        path: &str,
+        meta: &ContainerMeta,
+        update: &AvailableUpdate,
+    ) -> Result<(ConversionReport, AtomicFile), UpdateError> {
+        let mut file = File::create(download_path)
+            .map_err(|source| UpdateError::Io { path: download_path.into(), source })?;
+        self.transport.download(&update.download_url, &mut file)?;
+        file.sync_all()
+            .map_err(|source| UpdateError::Io { path: download_path.into(), source })?;
+
+        // revisions that can't be packed into a number larger than the
+        // installed one are numbered after it
+        let revision = revision_number(&update.info.revision)
+            .filter(|&revision| revision > meta.revision)
+            .unwrap_or(meta.revision + 1);
+        let options = ConvertOptions {
+            name: Some(meta.name.clone()),
+            revision_name: None,
+            revision: Some(revision),
+            store: update.store_options,
+            store_dictionary: update.store_dictionary.clone(),
+        };
+
+        Ok(convert_uncommitted(download_path, path, &options)?)
+    }
+}
+
+/// Checks that the converted update at `path` is a dictionary with the
+/// installed name and a newer revision, before it replaces the installed one.
+fn check_update(path: &Path, installed: &ContainerMeta) -> Result<(), UpdateError> {
+    let file = File::open(path)
+        .map_err(|source| UpdateError::Io { path: path.into(), source })?;
+    let header = ContainerFileInfo::read_container(file)?.header;
+
+    if header.role != Role::Dictionary {
+        return Err(ContainerError::RoleMismatch { expected: Role::Dictionary, found: header.role }.into());
+    }
+    if header.meta.name != installed.name || header.meta.revision <= installed.revision {
+        return Err(UpdateError::UnexpectedUpdate { name: header.meta.name, revision: header.meta.revision });
+    }
+
+    Ok(())
+}
+
+/// Revisions are compared part by part when both are numeric (see
+/// [`revision_parts`]), and otherwise any different revision is assumed to
+/// be newer.
+fn is_newer(installed: &IndexInfo, remote: &IndexInfo) -> bool {
+    match (revision_parts(&installed.revision), revision_parts(&remote.revision)) {
+        (Some(installed), Some(remote)) => remote > installed,
+        _ => remote.revision != installed.revision,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::format::container::open_container_with_header;
+    use crate::format::dictionary_convert::convert_yomitan_dictionary;
+    use crate::schema::Definition;
+    use crate::format::test_util::{build_dictionary, scratch_dir, term, write_zip};
+    use serde_json::{Value, json};
+
+    #[test]
+    fn updates_must_be_newer_revisions_of_the_same_dictionary() {
+        let dir = scratch_dir("check-update");
+        let options = ConvertOptions { revision: Some(3), ..ConvertOptions::default() };
+        let path = build_dictionary(&dir, "update.tomo", "Dict", &[term("犬", "いぬ", "dog")], &[], &options);
+        let path = path.as_path();
+
+        let installed = |name: &str, revision| ContainerMeta { name: name.into(), revision_name: "1".into(), revision };
+        assert!(check_update(path, &installed("Dict", 2)).is_ok());
+        assert!(matches!(check_update(path, &installed("Dict", 3)), Err(UpdateError::UnexpectedUpdate { revision: 3, .. })));
+        assert!(matches!(check_update(path, &installed("Other", 2)), Err(UpdateError::UnexpectedUpdate { .. })));
+
+        fs::write(path, b"not a container").unwrap();
+        assert!(matches!(check_update(path, &installed("Dict", 2)), Err(UpdateError::Container(_))));
+
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    /// Writes a yomitan archive of revision `revision` that updates from
+    /// `remote_index`.
+    fn archive(path: &Path, revision: &str, remote_index: &Path, definition: &str) -> Value {
+        let index = json!({
+            "title": "Dict",
+            "revision": revision,
+            "format": 3,
+            "isUpdatable": true,
+            "indexUrl": remote_index.to_str().unwrap(),
+            "downloadUrl": format!("file://{}", path.to_str().unwrap()),
+        });
+        let terms = json!([term("犬", "いぬ", definition)]);
+        write_zip(path, &[
+            ("index.json", index.to_string().as_bytes()),
+            ("term_bank_1.json", terms.to_string().as_bytes()),
+        ]);
+        index
+    }
+
+    fn open(path: &str) -> (ContainerMeta, Dictionary) {
+        let (header, dictionary) = open_container_with_header::<Dictionary>(path, true).unwrap();
+        (header.meta, dictionary)
+    }
+
+    #[test]
+    fn updates_replace_installed_dictionaries() {
+        let dir = scratch_dir("apply-update");
+        let remote_index = dir.join("remote-index.json");
+        let installed = dir.join("dict.tomo");
+        let installed = installed.to_str().unwrap();
+        let leftovers = || ["dict.tomo.download", "dict.tomo.tmp"].iter().any(|name| dir.join(name).exists());
+
+        archive(&dir.join("installed.zip"), "1", &remote_index, "dog");
+        convert_yomitan_dictionary(dir.join("installed.zip").to_str().unwrap(), installed, &ConvertOptions::default()).unwrap();
+        let index = archive(&dir.join("update.zip"), "2", &remote_index, "a dog");
+        fs::write(&remote_index, index.to_string()).unwrap();
+
+        let mut updater = Updater::new(FileTransport);
+        let (meta, dictionary) = open(installed);
+        let Ok(UpdateStatus::Available(update)) = updater.check(&dictionary) else {
+            panic!("update not found");
+        };
+        assert_eq!(update.info.revision, "2");
+        drop(dictionary);
+
+        // a broken download leaves the installed dictionary as it was
+        let original = fs::read(installed).unwrap();
+        let archive_data = fs::read(dir.join("update.zip")).unwrap();
+        fs::write(dir.join("update.zip"), b"not an archive").unwrap();
+        assert!(matches!(updater.apply(installed, &meta, &update), Err(UpdateError::Convert(_))));
+        assert_eq!(fs::read(installed).unwrap(), original);
+        assert!(!leftovers());
+
+        fs::write(dir.join("update.zip"), archive_data).unwrap();
+        updater.apply(installed, &meta, &update).unwrap();
+        assert!(!leftovers());
+
+        let (updated_meta, dictionary) = open(installed);
+        assert_eq!(updated_meta.name, "Dict");
+        assert!(updated_meta.revision > meta.revision);
+        assert_eq!(dictionary.info.as_ref().unwrap().revision, "2");
+        assert!(matches!(&dictionary.find_terms("犬").unwrap()[0].definitions[..], [Definition::Text(text)] if text == "a dog"));
+        assert!(matches!(updater.check(&dictionary), Ok(UpdateStatus::UpToDate)));
+
+        let _ = fs::remove_dir_all(dir);
+    }
+}
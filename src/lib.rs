@@ -0,0 +1,34 @@
+//! Implementation of the tomochan dictionary format.
+//!
+//! The [`schema`] module contains the yomitan data types stored inside
+//! dictionaries, the [`format`] module contains the on disk formats and
+//! the tools for converting into them, and the [`render`] module turns
+//! definitions into displayable output.
+//!
+//! # Example
+//! ```no_run
+//! use tomochan_dict::{Dictionary, open_container};
+//!
+//! let dict = open_container::<Dictionary>("jitendex.tomo", true).unwrap();
+//! for term in dict.find_terms("食べる").unwrap() {
+//!     println!("{} [{}]", term.term, term.reading);
+//! }
+//! ```
+
+pub mod error;
+pub mod schema;
+pub mod format;
+pub mod render;
+
+pub use error::Error;
+
+pub use format::{
+    Dictionary, DictionaryCollection, Deinflector, DeinflectionResult, Scanner, ScanMatch, Normalizer, LookupMode,
+    convert_yomitan_dictionary, convert_deinflector, ConvertOptions, ConversionReport, export_yomitan_dictionary,
+    create_patch, apply_patch, StoreOptions,
+};
+pub use render::{Renderer, Html, PlainText, Markdown};
+pub use format::container::{
+    ContainerMeta, ContainerHeader, ContainerFileInfo, Role,
+    open_container, open_container_with_header, open_container_logged, allow_dev_version, dev_version_allowed,
+};
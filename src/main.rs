@@ -1,29 +1,124 @@
-use clap::{Parser, Subcommand, ValueEnum};
+use clap::{Args, Parser, Subcommand, ValueEnum};
 use std::fs::File;
-use std::time::Instant;
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, Instant};
 
-mod schema;
-mod format;
-
-use format::{Dictionary, Deinflector, convert_yomitan_dictionary, convert_deinflector};
-use format::types::QueryKindKey;
-use format::container::{ContainerMeta, ContainerFileInfo, Role, open_container, allow_dev_version};
+use tomochan_dict::{
+    Dictionary, DictionaryCollection, Deinflector, Scanner, Normalizer, LookupMode, convert_yomitan_dictionary,
+    convert_deinflector, export_yomitan_dictionary, ConvertOptions, StoreOptions, create_patch, apply_patch,
+};
+use tomochan_dict::format::{CollectionConfig, FrameCache};
+use tomochan_dict::format::types::QueryKindKey;
+use tomochan_dict::format::index::KeyQuery;
+use tomochan_dict::format::update::{Updater, Transport, FileTransport, CurlTransport, UpdateStatus};
+use tomochan_dict::format::signature::{
+    SignaturePolicy, TrustedKeys, set_signature_policy, set_signature_warning_handler, sign_container, verify_container, generate_signing_key,
+    read_signing_key, write_signing_key, encode_public_key,
+};
+use tomochan_dict::format::{HashLogFile, HashLogPolicy};
+use tomochan_dict::format::container::ContainerFormat;
+use tomochan_dict::error::UpdateError;
+use tomochan_dict::render::{DataUri, ImageResolver, NoImages, UrlScheme};
+use tomochan_dict::{Renderer, Html, PlainText, Markdown};
+use tomochan_dict::schema::Definition;
+use tomochan_dict::error::StoreError;
+use tomochan_dict::{ContainerMeta, ContainerHeader, ContainerFileInfo, Role, open_container, open_container_logged, allow_dev_version};
 
 type CliResult = Result<(), Box<dyn std::error::Error>>;
 
-// TODO: API that is simple "jp string to HTML" interface
-
 #[derive(Parser)]
 struct Cli {
     #[command(subcommand)]
     command: Commands,
+
+    /// Keys whose container signatures are accepted
+    #[arg(long, global = true)]
+    trusted_keys: Option<String>,
+
+    /// What to do with containers that are not signed by a trusted key
+    #[arg(long, global = true, value_enum, default_value = "ignore")]
+    signatures: SignaturePolicyArg,
+
+    /// File to remember verified containers in, so they are only hashed again
+    /// when they change
+    #[arg(long, global = true)]
+    hash_log: Option<String>,
+
+    /// When containers in the hash log are verified again
+    #[arg(long, global = true, value_enum, default_value = "metadata")]
+    hash_log_policy: HashLogPolicyArg,
+
+    /// Age in days after which the periodic policy verifies containers again
+    #[arg(long, global = true, default_value_t = 30)]
+    reverify_days: u64,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum HashLogPolicyArg {
+    /// Only when their size, modification time or inode changes
+    Metadata,
+    /// Always hash them, but skip the other checks if the file is unchanged
+    Rehash,
+    /// When they change, or after --reverify-days
+    Periodic,
+}
+
+impl Cli {
+    fn hash_log_policy(&self) -> HashLogPolicy {
+        match self.hash_log_policy {
+            HashLogPolicyArg::Metadata => HashLogPolicy::Metadata,
+            HashLogPolicyArg::Rehash => HashLogPolicy::Rehash,
+            HashLogPolicyArg::Periodic => HashLogPolicy::MaxAge(Duration::from_secs(self.reverify_days.saturating_mul(24 * 60 * 60))),
+        }
+    }
+}
+
+/// Set once in `main` from `--hash-log`.
+static HASH_LOG: OnceLock<Option<Arc<HashLogFile>>> = OnceLock::new();
+
+fn hash_log() -> Option<Arc<HashLogFile>> {
+    HASH_LOG.get().cloned().flatten()
+}
+
+/// Opens and verifies a container, unless the hash log has it as verified.
+fn open_verified<T: ContainerFormat>(path: &str) -> Result<(ContainerHeader, T), tomochan_dict::Error> {
+    open_container_logged(path, true, hash_log().as_deref())
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum SignaturePolicyArg {
+    Ignore,
+    Warn,
+    Require,
+}
+
+impl From<SignaturePolicyArg> for SignaturePolicy {
+    fn from(policy: SignaturePolicyArg) -> Self {
+        match policy {
+            SignaturePolicyArg::Ignore => SignaturePolicy::Ignore,
+            SignaturePolicyArg::Warn => SignaturePolicy::Warn,
+            SignaturePolicyArg::Require => SignaturePolicy::Require,
+        }
+    }
 }
 
 #[derive(Subcommand)]
 enum Commands {
     Convert(ConvertCommand),
+    Export(ExportCommand),
     Lookup(LookupCommand),
+    Scan(ScanCommand),
+    Search(SearchCommand),
+    Update(UpdateCommand),
+    Diff(DiffCommand),
+    Patch(PatchCommand),
+    Keygen(KeygenCommand),
+    Sign(SignCommand),
+    Verify(VerifyCommand),
     Probe(ProbeCommand),
+    Bench(BenchCommand),
+    #[command(subcommand)]
+    Collection(CollectionCommand),
 }
 
 trait Execute {
@@ -36,7 +131,6 @@ enum ConvertKind {
     Deinflector
 }
 
-// TODO: autofill revision and revision_name with dev if not specified
 // TODO: option to use current utc timestamp as revision?
 #[derive(Parser)]
 struct ConvertCommand {
@@ -46,38 +140,198 @@ struct ConvertCommand {
     #[arg(long)]
     output: String,
 
+    /// Defaults to the title in the dictionary's index.json
     #[arg(long)]
-    name: String,
+    name: Option<String>,
 
     #[arg(long)]
     kind: ConvertKind,
 
+    /// Defaults to the revision in the dictionary's index.json, or "dev"
+    /// for deinflectors
+    #[arg(long)]
+    revision_name: Option<String>,
+
+    /// Defaults to the numeric parts of the revision in the dictionary's
+    /// index.json, or 0 if it has none or for deinflectors
+    #[arg(long)]
+    revision: Option<u64>,
+
+    #[command(flatten)]
+    store: StoreArgs,
+}
+
+/// How dictionary stores are compressed
+#[derive(Args)]
+struct StoreArgs {
+    /// zstd compression level, from 1 to 22
+    #[arg(long, default_value_t = StoreOptions::default().compression_level)]
+    level: i32,
+
+    /// Uncompressed frame size in KiB, smaller frames make lookups faster
+    #[arg(long, default_value_t = StoreOptions::default().frame_size >> 10)]
+    frame_size: u32,
+
+    /// End frames at boundaries chosen by content, which keeps patches
+    /// between revisions small. Frames are then usually much smaller than
+    /// --frame-size, which only bounds them
     #[arg(long)]
-    revision_name: String,
+    content_defined_frames: bool,
 
+    /// Enable zstd long distance matching, for frames of several MiB
     #[arg(long)]
-    revision: u64,
+    long_distance: bool,
+
+    /// Compression worker threads, 0 to compress on the main thread
+    #[arg(long, default_value_t = 0)]
+    threads: u32,
+
+    /// Train a zstd dictionary of this many KiB on the first entries, which
+    /// keeps small frames compact, 0 for none. A new dictionary changes
+    /// every frame, so patches from older revisions need --dictionary-from
+    #[arg(long, default_value_t = 0)]
+    dictionary_size: u32,
+
+    /// Compress with the zstd dictionary of this older revision instead of
+    /// training one, which keeps patches between the revisions small
+    #[arg(long, conflicts_with = "dictionary_size")]
+    dictionary_from: Option<String>,
+}
+
+impl StoreArgs {
+    fn options(&self) -> StoreOptions {
+        StoreOptions {
+            compression_level: self.level,
+            frame_size: self.frame_size.saturating_mul(1 << 10),
+            content_defined_frames: self.content_defined_frames,
+            long_distance_matching: self.long_distance,
+            threads: self.threads,
+            dictionary_size: self.dictionary_size.saturating_mul(1 << 10),
+        }
+    }
+
+    /// Store dictionary of the --dictionary-from revision. Problems that
+    /// don't stop the conversion are added to `warnings`, for the command to
+    /// report with its results.
+    fn dictionary(&self, warnings: &mut Vec<String>) -> Result<Option<Vec<u8>>, tomochan_dict::Error> {
+        let Some(path) = &self.dictionary_from else {
+            return Ok(None);
+        };
+        let (_, dict) = open_verified::<Dictionary>(path)?;
+        if dict.store.dictionary().is_none() {
+            warnings.push(format!("{} has no store dictionary, so none was used", path));
+        }
+        Ok(dict.store.dictionary().map(<[u8]>::to_vec))
+    }
 }
 
 impl Execute for ConvertCommand {
     fn execute(&self) -> CliResult {
-        let meta = ContainerMeta {
-            name: self.name.clone(),
-            revision_name: self.revision_name.clone(),
-            revision: self.revision,
+        match self.kind {
+            ConvertKind::Dict => {
+                let mut warnings = Vec::new();
+                let options = ConvertOptions {
+                    name: self.name.clone(),
+                    revision_name: self.revision_name.clone(),
+                    revision: self.revision,
+                    store: self.store.options(),
+                    store_dictionary: self.store.dictionary(&mut warnings)?,
+                };
+
+                let report = convert_yomitan_dictionary(&self.input, &self.output, &options)?;
+                if !report.is_empty() {
+                    println!("Kept structured content that was not understood:");
+                    for (tag, count) in &report.unknown_tags {
+                        println!("  tag {}: {}", tag, count);
+                    }
+                    for (attribute, count) in &report.unknown_attributes {
+                        println!("  attribute {}: {}", attribute, count);
+                    }
+                }
+                for warning in &warnings {
+                    println!("Warning: {}", warning);
+                }
+                Ok(())
+            }
+            ConvertKind::Deinflector => {
+                let meta = ContainerMeta {
+                    name: self.name.clone().ok_or("--name is required for deinflectors")?,
+                    revision_name: self.revision_name.clone().unwrap_or_else(|| "dev".to_string()),
+                    revision: self.revision.unwrap_or(0),
+                };
+
+                Ok(convert_deinflector(&self.input, &self.output, meta)?)
+            }
+        }
+    }
+}
+
+/// Writes a dictionary back out as a yomitan dictionary zip
+#[derive(Parser)]
+struct ExportCommand {
+    #[arg(long)]
+    input: String,
+
+    #[arg(long)]
+    output: String,
+}
+
+impl Execute for ExportCommand {
+    fn execute(&self) -> CliResult {
+        let (header, dict) = open_verified::<Dictionary>(&self.input)?;
+        let file = File::create(&self.output)?;
+        export_yomitan_dictionary(&dict, &header.meta, file)?;
+        Ok(())
+    }
+}
+
+#[derive(Args)]
+struct DictionaryArgs {
+    /// Dictionaries to search, in priority order
+    #[arg(required_unless_present = "collection")]
+    dictionaries: Vec<String>,
+
+    /// Collection configuration file to search instead of listing dictionaries
+    #[arg(long)]
+    collection: Option<String>,
+
+    /// Cache up to this many MiB of decompressed entries, shared by all dictionaries
+    #[arg(long)]
+    frame_cache: Option<usize>,
+}
+
+impl DictionaryArgs {
+    fn open(&self) -> Result<DictionaryCollection, tomochan_dict::Error> {
+        let config = match &self.collection {
+            Some(path) => CollectionConfig::load(path)?,
+            None => CollectionConfig::default(),
         };
 
-        match self.kind {
-            ConvertKind::Dict => Ok(convert_yomitan_dictionary(
-                &self.input,
-                &self.output,
-                meta,
-            )?),
-            ConvertKind::Deinflector => Ok(convert_deinflector(
-                &self.input,
-                &self.output,
-                meta,
-            )?),
+        let mut collection = DictionaryCollection::open_with_hash_log(&config, true, hash_log())?;
+        if let Some(mib) = self.frame_cache {
+            collection.set_frame_cache(Some(Arc::new(FrameCache::new(mib << 20))));
+        }
+        for (priority, path) in self.dictionaries.iter().enumerate() {
+            collection.add(path, priority as i32, true, true)?;
+        }
+
+        Ok(collection)
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum ModeArg {
+    Term,
+    Reading,
+    Either,
+}
+
+impl From<ModeArg> for LookupMode {
+    fn from(mode: ModeArg) -> Self {
+        match mode {
+            ModeArg::Term => LookupMode::Term,
+            ModeArg::Reading => LookupMode::Reading,
+            ModeArg::Either => LookupMode::TermOrReading,
         }
     }
 }
@@ -86,57 +340,560 @@ impl Execute for ConvertCommand {
 pub struct LookupCommand {
     pub word: String,
 
-    #[arg(required = true)]
-    pub dictionaries: Vec<String>,
+    #[command(flatten)]
+    dictionaries: DictionaryArgs,
 
     #[arg(long)]
     pub deinflector: Option<String>,
+
+    /// Only look up the word as written (apart from width folding)
+    #[arg(long)]
+    pub no_normalize: bool,
+
+    /// Search by headword, by reading, or by either
+    #[arg(long, value_enum, default_value = "term")]
+    mode: ModeArg,
+
+    /// Maximum edit distance of suggestions shown when nothing is found
+    #[arg(long, default_value_t = 1)]
+    max_distance: u32,
+
+    /// Format definitions are printed in
+    #[arg(long, value_enum, default_value = "text")]
+    format: RenderFormat,
+
+    /// Link images to this URL prefix instead of embedding them
+    #[arg(long)]
+    image_url: Option<String>,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum RenderFormat {
+    Text,
+    Markdown,
+    Html,
+}
+
+fn print_frame_cache_stats(collection: &DictionaryCollection) {
+    if let Some(cache) = collection.frame_cache() {
+        let stats = cache.stats();
+        println!(
+            "frame cache: {} hits, {} misses ({:.0}%), {} frames, {}/{} bytes",
+            stats.hits, stats.misses, stats.hit_rate() * 100.0, stats.frames, stats.bytes, stats.budget,
+        );
+    }
+}
+
+fn render_definitions<R: ImageResolver>(
+    format: RenderFormat,
+    images: R,
+    definitions: &[Definition],
+) -> Result<String, StoreError> {
+    match format {
+        RenderFormat::Text => Renderer::new(PlainText::new(), images).render_definitions(definitions),
+        RenderFormat::Markdown => Renderer::new(Markdown::new(), images).render_definitions(definitions),
+        RenderFormat::Html => Renderer::new(Html::new(), images).render_definitions(definitions),
+    }
+}
+
+impl LookupCommand {
+    fn render(&self, collection: &DictionaryCollection, path: &str, definitions: &[Definition]) -> Result<String, Box<dyn std::error::Error>> {
+        let output = match (&self.image_url, self.format) {
+            (_, RenderFormat::Text) => render_definitions(self.format, NoImages, definitions)?,
+            (Some(prefix), _) => render_definitions(self.format, UrlScheme::new(prefix), definitions)?,
+            (None, _) => {
+                let dictionary = collection.dictionary(path)
+                    .ok_or("result from unknown dictionary")?;
+                render_definitions(self.format, DataUri::new(dictionary), definitions)?
+            }
+        };
+
+        Ok(output)
+    }
 }
 
 impl Execute for LookupCommand {
     fn execute(&self) -> CliResult {
-        // TODO: just .map, more concise, need to incl store though
-        let dicts: Vec<Dictionary> = self.dictionaries
-            .iter()
-            .map(|path| open_container::<Dictionary>(path, true))
-            .collect::<Result<_, _>>()?;
+        let collection = self.dictionaries.open()?;
 
+        let normalizer = if self.no_normalize {
+            Normalizer::disabled()
+        } else {
+            Normalizer::default()
+        };
+        let variants = normalizer.variants(&self.word);
+        println!("{} normalized variants", variants.len());
+
+        let deinflector = self.deinflector
+            .as_ref()
+            .map(|path| open_verified::<Deinflector>(path).map(|(_, deinflector)| deinflector))
+            .transpose()?;
         let mut terms = Vec::new();
 
-        if let Some(path) = &self.deinflector {
-            let deinflector = open_container::<Deinflector>(path, true)?;
+        if let Some(deinflector) = &deinflector {
 
             let start = Instant::now();
-            let results = deinflector.deinflect(&self.word);
-            for result in &results {
-                terms.push(result.term.clone());
+            let mut result_count = 0;
+            for variant in &variants {
+                let results = deinflector.deinflect(&variant.text);
+                result_count += results.len();
+                for result in results {
+                    terms.push((result.term.clone(), &variant.transformations, Some((deinflector, result))));
+                }
             }
             let elapsed = start.elapsed();
 
             println!("{:?} deinflection", elapsed);
-            println!("{} terms from deinflection, {} post-filtering", results.len(), terms.len());
+            println!("{} terms from deinflection, {} post-filtering", result_count, terms.len());
     
         } else {
-            terms.push(self.word.clone());
+            terms.extend(variants.iter().map(|variant| (variant.text.clone(), &variant.transformations, None)));
         }
 
         let start = Instant::now();
-        let mut result_count = 0;
-        for dict in &dicts {
-            for term in &terms {
-                result_count += dict.index.lookup(QueryKindKey::Term, term).len();
+        let mut results = Vec::new();
+        for (term, transformations, deinflection) in &terms {
+            for result in collection.find_terms_by(term, self.mode.into())? {
+                if deinflection.as_ref().is_none_or(|(deinflector, deinflection)| deinflector.accepts(deinflection, &result.entry.rules)) {
+                    results.push((result, transformations));
+                }
             }
         }
         let elapsed = start.elapsed();
 
         println!("filtered term count {}", terms.len());
         println!("{:?} lookup", elapsed);
-        println!("{} results", result_count);
+        println!("{} results", results.len());
+        print_frame_cache_stats(&collection);
+
+        for (result, transformations) in &results {
+            println!("  [{}] {} ({}) {:?}", result.dictionary, result.entry.term, result.entry.reading, transformations);
+
+            let rendered = self.render(&collection, &result.path, &result.entry.definitions)?;
+            for line in rendered.lines() {
+                println!("      {}", line);
+            }
+        }
+
+        if results.is_empty() {
+            let suggestions = collection.fuzzy_search(QueryKindKey::Term, &self.word, self.max_distance, 5)?;
+            if !suggestions.is_empty() {
+                let keys: Vec<&str> = suggestions.iter().map(|m| m.key.as_str()).collect();
+                println!("did you mean: {}", keys.join(", "));
+            }
+        }
 
         Ok(())
     }
 }
 
+/// Find the longest dictionary words starting at a position in a sentence
+#[derive(Parser)]
+struct ScanCommand {
+    text: String,
+
+    /// Character offset to start scanning from
+    #[arg(long, default_value_t = 0)]
+    cursor: usize,
+
+    #[command(flatten)]
+    dictionaries: DictionaryArgs,
+
+    #[arg(long)]
+    deinflector: Option<String>,
+
+    /// Search by headword, by reading, or by either
+    #[arg(long, value_enum, default_value = "term")]
+    mode: ModeArg,
+}
+
+impl Execute for ScanCommand {
+    fn execute(&self) -> CliResult {
+        let collection = self.dictionaries.open()?;
+        let deinflector = self.deinflector
+            .as_ref()
+            .map(|path| open_verified::<Deinflector>(path).map(|(_, deinflector)| deinflector))
+            .transpose()?;
+
+        let mut scanner = Scanner::new().lookup_mode(self.mode.into());
+        if let Some(deinflector) = &deinflector {
+            scanner = scanner.deinflector(deinflector);
+        }
+
+        let start = Instant::now();
+        let matches = scanner.scan(&collection, &self.text, self.cursor)?;
+        let elapsed = start.elapsed();
+
+        println!("{:?} scan", elapsed);
+        print_frame_cache_stats(&collection);
+        for m in &matches {
+            let rules: Vec<&str> = m.rule_chain.iter()
+                .filter_map(|&idx| deinflector.as_ref().map(|d| d.transform_meta[idx].name.as_str()))
+                .collect();
+            println!("{} -> {} ({} chars) {:?} {:?}", m.source, m.term, m.source_len, m.transformations, rules);
+            for result in &m.entries {
+                println!("  [{}] {} ({})", result.dictionary, result.entry.term, result.entry.reading);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// List dictionary keys by prefix, wildcard pattern or range
+#[derive(Parser)]
+struct SearchCommand {
+    /// Prefix, pattern if it contains `*` or `?`, or `start..end` with --range
+    pattern: String,
+
+    #[command(flatten)]
+    dictionaries: DictionaryArgs,
+
+    /// Search readings instead of headwords
+    #[arg(long)]
+    reading: bool,
+
+    /// Treat the pattern as a `start..end` key range, either side may be empty
+    #[arg(long)]
+    range: bool,
+
+    /// Find keys within this edit distance of the pattern instead
+    #[arg(long, conflicts_with = "range")]
+    fuzzy: Option<u32>,
+
+    /// Only list keys after this one
+    #[arg(long)]
+    after: Option<String>,
+
+    #[arg(long, default_value_t = 20)]
+    limit: usize,
+}
+
+impl Execute for SearchCommand {
+    fn execute(&self) -> CliResult {
+        let collection = self.dictionaries.open()?;
+
+        let kind = if self.reading {
+            QueryKindKey::TermReading
+        } else {
+            QueryKindKey::Term
+        };
+
+        if let Some(max_distance) = self.fuzzy {
+            let start = Instant::now();
+            let matches = collection.fuzzy_search(kind, &self.pattern, max_distance, self.limit)?;
+            let elapsed = start.elapsed();
+
+            println!("{:?} fuzzy search", elapsed);
+            for m in &matches {
+                println!("  {} ({})", m.key, m.distance);
+            }
+            return Ok(());
+        }
+
+        let query = if self.range {
+            let (start, end) = self.pattern
+                .split_once("..")
+                .ok_or("range must be written as start..end")?;
+            let bound = |s: &str| (!s.is_empty()).then(|| s.to_string());
+            KeyQuery::Range { start: bound(start), end: bound(end) }
+        } else {
+            KeyQuery::from_pattern(&self.pattern)
+        };
+
+        let start = Instant::now();
+        let page = collection.search(kind, &query, self.after.as_deref(), self.limit)?;
+        let elapsed = start.elapsed();
+
+        println!("{:?} search", elapsed);
+        for key in &page.keys {
+            println!("  {}", key);
+        }
+        if let Some(next) = &page.next {
+            println!("more results, continue with --after {}", next);
+        }
+
+        Ok(())
+    }
+}
+
+/// Manage a collection configuration file
+#[derive(Subcommand)]
+enum CollectionCommand {
+    /// List dictionaries in priority order
+    List {
+        config: String,
+    },
+    /// Add a dictionary, creating the configuration if needed
+    Add {
+        config: String,
+        dictionary: String,
+        #[arg(long, default_value_t = 0)]
+        priority: i32,
+    },
+    Remove {
+        config: String,
+        dictionary: String,
+    },
+    Enable {
+        config: String,
+        dictionary: String,
+    },
+    Disable {
+        config: String,
+        dictionary: String,
+    },
+    Priority {
+        config: String,
+        dictionary: String,
+        priority: i32,
+    },
+}
+
+impl Execute for CollectionCommand {
+    fn execute(&self) -> CliResult {
+        let config_path = match self {
+            CollectionCommand::List { config }
+            | CollectionCommand::Add { config, .. }
+            | CollectionCommand::Remove { config, .. }
+            | CollectionCommand::Enable { config, .. }
+            | CollectionCommand::Disable { config, .. }
+            | CollectionCommand::Priority { config, .. } => config,
+        };
+
+        let config = if std::path::Path::new(config_path).exists() {
+            CollectionConfig::load(config_path)?
+        } else {
+            CollectionConfig::default()
+        };
+        let mut collection = DictionaryCollection::open_with_hash_log(&config, false, hash_log())?;
+
+        let found = match self {
+            CollectionCommand::List { .. } => {
+                for entry in collection.entries() {
+                    println!(
+                        "{:>4} {} {} ({}) {}",
+                        entry.priority,
+                        if entry.enabled { "+" } else { "-" },
+                        entry.meta.name,
+                        entry.meta.revision_name,
+                        entry.path,
+                    );
+                }
+                println!("{} unique terms", collection.unique_terms()?);
+                return Ok(());
+            }
+            CollectionCommand::Add { dictionary, priority, .. } => {
+                collection.add(dictionary, *priority, true, true)?;
+                true
+            }
+            CollectionCommand::Remove { dictionary, .. } => collection.remove(dictionary).is_some(),
+            CollectionCommand::Enable { dictionary, .. } => collection.set_enabled(dictionary, true),
+            CollectionCommand::Disable { dictionary, .. } => collection.set_enabled(dictionary, false),
+            CollectionCommand::Priority { dictionary, priority, .. } => collection.set_priority(dictionary, *priority),
+        };
+
+        if !found {
+            return Err(format!("dictionary is not in collection {}", config_path).into());
+        }
+
+        collection.config().save(config_path)?;
+        Ok(())
+    }
+}
+
+/// Fetches `http(s)` URLs with curl, and reads anything else from disk.
+struct CliTransport;
+
+impl Transport for CliTransport {
+    fn download(&mut self, url: &str, out: &mut dyn std::io::Write) -> Result<(), UpdateError> {
+        if url.starts_with("http://") || url.starts_with("https://") {
+            CurlTransport.download(url, out)
+        } else {
+            FileTransport.download(url, out)
+        }
+    }
+}
+
+/// Check dictionaries for newer revisions and install them
+#[derive(Parser)]
+struct UpdateCommand {
+    #[command(flatten)]
+    dictionaries: DictionaryArgs,
+
+    /// Only report available updates
+    #[arg(long)]
+    check: bool,
+}
+
+impl Execute for UpdateCommand {
+    fn execute(&self) -> CliResult {
+        let mut collection = self.dictionaries.open()?;
+        let mut updater = Updater::new(CliTransport);
+
+        for (path, status) in updater.check_collection(&collection) {
+            let Some(entry) = collection.entries().iter().find(|entry| entry.path == path) else {
+                continue;
+            };
+            let meta = entry.meta.clone();
+
+            let update = match status {
+                Ok(UpdateStatus::NotUpdatable) => {
+                    println!("{}: not updatable", meta.name);
+                    continue;
+                }
+                Ok(UpdateStatus::UpToDate) => {
+                    println!("{}: up to date ({})", meta.name, meta.revision_name);
+                    continue;
+                }
+                Ok(UpdateStatus::Available(update)) => update,
+                Err(e) => {
+                    println!("{}: {}", meta.name, e);
+                    continue;
+                }
+            };
+
+            println!("{}: {} -> {}", meta.name, meta.revision_name, update.info.revision);
+            if self.check {
+                continue;
+            }
+
+            match updater.apply(&path, &meta, &update) {
+                Ok(_) => {
+                    collection.reload(&path, true)?;
+                    println!("  updated {}", path);
+                }
+                Err(e) => println!("  update failed: {}", e),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Creates a patch from one revision of a container to another
+#[derive(Parser)]
+struct DiffCommand {
+    #[arg(long)]
+    base: String,
+
+    #[arg(long)]
+    target: String,
+
+    #[arg(long)]
+    output: String,
+}
+
+impl Execute for DiffCommand {
+    fn execute(&self) -> CliResult {
+        create_patch(&self.base, &self.target, &self.output)?;
+
+        let target_len = std::fs::metadata(&self.target)?.len();
+        let patch_len = std::fs::metadata(&self.output)?.len();
+        println!("patch is {} KB, target is {} KB", patch_len / 1024, target_len / 1024);
+        Ok(())
+    }
+}
+
+/// Rebuilds a revision of a container from a patch and the revision it was
+/// made from
+#[derive(Parser)]
+struct PatchCommand {
+    #[arg(long)]
+    base: String,
+
+    #[arg(long)]
+    patch: String,
+
+    #[arg(long)]
+    output: String,
+}
+
+impl Execute for PatchCommand {
+    fn execute(&self) -> CliResult {
+        Ok(apply_patch(&self.base, &self.patch, &self.output)?)
+    }
+}
+
+/// Generates a key for signing containers
+#[derive(Parser)]
+struct KeygenCommand {
+    /// File the secret key is written to, which must not exist yet
+    #[arg(long)]
+    output: String,
+}
+
+impl Execute for KeygenCommand {
+    fn execute(&self) -> CliResult {
+        let key = generate_signing_key()?;
+        write_signing_key(&self.output, &key)?;
+        println!("public key: {}", encode_public_key(&key.verifying_key()));
+        Ok(())
+    }
+}
+
+/// Signs a container
+#[derive(Parser)]
+struct SignCommand {
+    #[arg(long)]
+    input: String,
+
+    /// Secret key file made by keygen
+    #[arg(long)]
+    key: String,
+
+    /// Defaults to signing the input in place
+    #[arg(long)]
+    output: Option<String>,
+}
+
+impl Execute for SignCommand {
+    fn execute(&self) -> CliResult {
+        let key = read_signing_key(&self.key)?;
+        sign_container(&self.input, self.output.as_ref().unwrap_or(&self.input), &key)?;
+        println!("signed with {}", encode_public_key(&key.verifying_key()));
+        Ok(())
+    }
+}
+
+/// Checks that a container is intact, and optionally signed by a trusted key
+#[derive(Parser)]
+struct VerifyCommand {
+    path: String,
+
+    /// Trusted keys file, to also check the signature
+    #[arg(long)]
+    keys: Option<String>,
+
+    /// Also decompress every entry of a dictionary, which takes about as long
+    /// as converting it
+    #[arg(long)]
+    deep: bool,
+}
+
+impl Execute for VerifyCommand {
+    fn execute(&self) -> CliResult {
+        if let Some(keys) = &self.keys {
+            let trusted_keys = TrustedKeys::load(keys)?;
+            let signer = verify_container(&self.path, &trusted_keys)?;
+            println!("signed by {} ({})", signer.name, signer.public_key);
+        }
+
+        // never skipped through the hash log, checking the file is the point
+        let container = ContainerFileInfo::read_container(File::open(&self.path)?)?;
+        if container.header.role == Role::Dictionary {
+            let dict = open_container::<Dictionary>(&self.path, true)?;
+            if self.deep {
+                dict.verify_deep()?;
+            }
+        } else {
+            container.validate_payload(File::open(&self.path)?)?;
+        }
+        println!("{} is intact", self.path);
+        Ok(())
+    }
+}
+
 #[derive(Parser)]
 struct ProbeCommand {
     #[arg(long)]
@@ -149,48 +906,191 @@ impl Execute for ProbeCommand {
         let container = ContainerFileInfo::read_container(&file)?;
         println!("{:#?}", container.header);
 
-        match container.header.role {
-            Role::Dictionary => {
-                let dict = open_container::<Dictionary>(&self.path, true)?;
-
-                // TODO: reimpl size stats
-                // println!("\nFST size: {} KB", dict.index.len()/1024);
-                // println!("\nStore size: {} KB", dict.index.data.len()/1024);
-
-                let key_kinds = [
-                    (QueryKindKey::Term, "term"),
-                    (QueryKindKey::Kanji, "kanji"),
-                    (QueryKindKey::Tag, "tag"),
-                    (QueryKindKey::TermMeta, "term meta"),
-                    (QueryKindKey::KanjiMeta, "kanji meta"),
-                    (QueryKindKey::File, "file"),
-                ];
-
-                for key_kind in &key_kinds {
-                    println!("  {} {} entries", dict.index.keys(key_kind.0).len(), key_kind.1);
-                }
+        if container.header.role == Role::Dictionary {
+            let (_, dict) = open_verified::<Dictionary>(&self.path)?;
+            if let Some(info) = &dict.info {
+                println!("{:#?}", info);
+            }
+            println!("{:#?}", dict.store_options);
+            if let Some(dictionary) = dict.store.dictionary() {
+                println!("store dictionary: {} bytes", dictionary.len());
+            }
+
+            // TODO: reimpl size stats
+            // println!("\nFST size: {} KB", dict.index.len()/1024);
+            // println!("\nStore size: {} KB", dict.index.data.len()/1024);
+
+            let key_kinds = [
+                (QueryKindKey::Term, "term"),
+                (QueryKindKey::Kanji, "kanji"),
+                (QueryKindKey::Tag, "tag"),
+                (QueryKindKey::TermMeta, "term meta"),
+                (QueryKindKey::KanjiMeta, "kanji meta"),
+                (QueryKindKey::File, "file"),
+                (QueryKindKey::TermReading, "term reading"),
+            ];
 
-                // TODO: per key information about store using compressed size
-                // need to get all keys, sort by offset, then extract size between keys
+            for key_kind in &key_kinds {
+                println!("  {} {} entries", dict.index.keys(key_kind.0)?.len(), key_kind.1);
             }
-            _ => {}
+
+            // TODO: per key information about store using compressed size
+            // need to get all keys, sort by offset, then extract size between keys
         }
 
         Ok(())
     }
 }
 
+/// Compare file size and lookup latency of a dictionary converted with
+/// different compression levels and frame sizes
+#[derive(Parser)]
+struct BenchCommand {
+    /// Yomitan dictionary to convert
+    #[arg(long)]
+    input: String,
+
+    /// Compression levels to try
+    #[arg(long, value_delimiter = ',', default_values_t = [3, 9, 19])]
+    levels: Vec<i32>,
+
+    /// Frame sizes to try, in KiB
+    #[arg(long, value_delimiter = ',', default_values_t = [64, 256, 2048])]
+    frame_sizes: Vec<u32>,
+
+    /// Enable zstd long distance matching
+    #[arg(long)]
+    long_distance: bool,
+
+    /// Compression worker threads
+    #[arg(long, default_value_t = 0)]
+    threads: u32,
+
+    /// Trained dictionary sizes to try, in KiB, 0 for no dictionary
+    #[arg(long, value_delimiter = ',', default_values_t = [0])]
+    dictionary_sizes: Vec<u32>,
+
+    /// Number of terms looked up in each conversion
+    #[arg(long, default_value_t = 1000)]
+    samples: usize,
+
+    /// Directory converted dictionaries are written to
+    #[arg(long)]
+    output_dir: Option<String>,
+}
+
+impl Execute for BenchCommand {
+    fn execute(&self) -> CliResult {
+        let output_dir = self.output_dir.clone()
+            .map(Into::into)
+            .unwrap_or_else(std::env::temp_dir);
+
+        println!(
+            "{:>5} {:>9} {:>8} {:>10} {:>12} {:>12} {:>12}",
+            "level", "frame KiB", "dict KiB", "size KiB", "convert", "lookup mean", "lookup max",
+        );
+
+        let configurations = self.levels.iter()
+            .flat_map(|&level| self.frame_sizes.iter().map(move |&frame_size| (level, frame_size)))
+            .flat_map(|(level, frame_size)| self.dictionary_sizes.iter().map(move |&dictionary_size| (level, frame_size, dictionary_size)));
+
+        for (level, frame_size, dictionary_size) in configurations {
+            let store = StoreArgs {
+                level,
+                frame_size,
+                content_defined_frames: false,
+                long_distance: self.long_distance,
+                threads: self.threads,
+                dictionary_size,
+                dictionary_from: None,
+            };
+            let output = output_dir.join(format!("bench-{}-{}-{}.tomo", level, frame_size, dictionary_size));
+            let output = output.to_str().ok_or("output directory is not valid UTF-8")?;
+            let options = ConvertOptions { store: store.options(), ..ConvertOptions::default() };
+
+            let start = Instant::now();
+            let result = convert_yomitan_dictionary(&self.input, output, &options);
+            let convert_time = start.elapsed();
+            let measured = result.map_err(Into::into)
+                .and_then(|_| self.measure_lookups(output));
+            let size = std::fs::metadata(output).map(|meta| meta.len());
+            let _ = std::fs::remove_file(output);
+            let (mean, max) = measured?;
+
+            println!(
+                "{:>5} {:>9} {:>8} {:>10} {:>12} {:>12} {:>12}",
+                level, frame_size, dictionary_size, size? >> 10, format!("{:.2?}", convert_time), format!("{:.2?}", mean), format!("{:.2?}", max),
+            );
+        }
+
+        Ok(())
+    }
+}
+
+impl BenchCommand {
+    /// Mean and worst time to look up terms spread evenly over the index.
+    fn measure_lookups(&self, path: &str) -> Result<(Duration, Duration), Box<dyn std::error::Error>> {
+        let dict = open_container::<Dictionary>(path, false)?;
+        let keys = dict.index.keys(QueryKindKey::Term)?;
+        let step = (keys.len() / self.samples.max(1)).max(1);
+
+        let mut total = Duration::ZERO;
+        let mut max = Duration::ZERO;
+        let mut count = 0;
+        for key in keys.iter().step_by(step).take(self.samples) {
+            let start = Instant::now();
+            dict.find_terms(key)?;
+            let elapsed = start.elapsed();
+
+            total += elapsed;
+            max = max.max(elapsed);
+            count += 1;
+        }
+
+        Ok((total / count.max(1), max))
+    }
+}
+
 fn main() {
     allow_dev_version(std::env::var("TOMOCHAN_DEV")
-        .map(|v| v.len() > 0)
+        .map(|v| !v.is_empty())
         .unwrap_or(false));
 
     let cli = Cli::parse();
 
+    let trusted_keys = match &cli.trusted_keys {
+        Some(path) => TrustedKeys::load(path),
+        None => Ok(TrustedKeys::default()),
+    };
+    set_signature_warning_handler(|path, e| eprintln!("warning: {}: {}", path, e));
+    let configured = trusted_keys
+        .map(|keys| set_signature_policy(cli.signatures.into(), keys));
+    if let Err(err) = configured {
+        eprintln!("error: {}", err);
+        std::process::exit(1);
+    }
+
+    let hash_log = cli.hash_log.as_ref().map(|path| HashLogFile::open(path, cli.hash_log_policy()));
+    if let Some(err) = hash_log.as_ref().and_then(HashLogFile::discarded) {
+        eprintln!("warning: {}, starting a new hash log", err);
+    }
+    HASH_LOG.set(hash_log.map(Arc::new)).expect("hash log is only set once");
+
     let result = match cli.command {
         Commands::Convert(args) => args.execute(),
+        Commands::Export(args) => args.execute(),
         Commands::Lookup(args) => args.execute(),
+        Commands::Scan(args) => args.execute(),
+        Commands::Search(args) => args.execute(),
+        Commands::Update(args) => args.execute(),
+        Commands::Diff(args) => args.execute(),
+        Commands::Patch(args) => args.execute(),
+        Commands::Keygen(args) => args.execute(),
+        Commands::Sign(args) => args.execute(),
+        Commands::Verify(args) => args.execute(),
         Commands::Probe(args) => args.execute(),
+        Commands::Bench(args) => args.execute(),
+        Commands::Collection(args) => args.execute(),
     };
 
     if let Err(err) = result {
@@ -0,0 +1,314 @@
+//! HTML output mirroring the markup yomitan generates for glossaries.
+//!
+//! Every structured content element gets a `gloss-sc-<tag>` class and its
+//! `data` attributes as `data-sc-<key>`, so stylesheets written for yomitan
+//! dictionaries keep working. All text and attribute values are escaped,
+//! and links are only kept if they are `http(s)` or internal `?query` links.
+
+use super::{Image, Target, is_allowed_link};
+use crate::schema::{Attributes, Definition, HtmlTag};
+use std::fmt::Write;
+
+#[derive(Default)]
+pub struct Html {
+    out: String,
+    in_list: bool,
+}
+
+impl Html {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Target for Html {
+    fn begin_list(&mut self, _count: usize) {
+        self.out.push_str("<ul class=\"gloss-list\">");
+        self.in_list = true;
+    }
+
+    fn end_list(&mut self) {
+        self.out.push_str("</ul>");
+        self.in_list = false;
+    }
+
+    fn begin_definition(&mut self, definition: &Definition) {
+        if self.in_list {
+            self.out.push_str("<li class=\"gloss-item\">");
+        }
+
+        match definition {
+            Definition::StructuredContent(_) => self.out.push_str("<div class=\"gloss-structured-content\">"),
+            Definition::Image { pixelated, monochrome, background, .. } => {
+                self.out.push_str("<span class=\"gloss-image\"");
+                write_attr(&mut self.out, "data-pixelated", if *pixelated { "true" } else { "false" });
+                write_attr(&mut self.out, "data-appearance", if *monochrome { "monochrome" } else { "auto" });
+                write_attr(&mut self.out, "data-background", if *background { "true" } else { "false" });
+                self.out.push('>');
+            }
+            _ => {}
+        }
+    }
+
+    fn end_definition(&mut self, definition: &Definition) {
+        match definition {
+            Definition::StructuredContent(_) => self.out.push_str("</div>"),
+            Definition::Image { .. } => self.out.push_str("</span>"),
+            _ => {}
+        }
+
+        if self.in_list {
+            self.out.push_str("</li>");
+        }
+    }
+
+    fn text(&mut self, text: &str) {
+        write_text(text, &mut self.out);
+    }
+
+    fn begin_element(&mut self, tag: HtmlTag, attrs: &Attributes) -> bool {
+        let out = &mut self.out;
+
+        if tag == HtmlTag::Table {
+            out.push_str("<div class=\"gloss-sc-table-container\">");
+        }
+
+        let _ = write!(out, "<{}", tag.name());
+        write_common_attrs(tag, attrs, out);
+        match tag {
+            HtmlTag::Td | HtmlTag::Th => {
+                if let Some(col_span) = attrs.col_span {
+                    write_attr(out, "colspan", &col_span.to_string());
+                }
+                if let Some(row_span) = attrs.row_span {
+                    write_attr(out, "rowspan", &row_span.to_string());
+                }
+            }
+            HtmlTag::Details if attrs.open == Some(true) => out.push_str(" open"),
+            HtmlTag::A => {
+                if let Some(href) = attrs.href.as_deref().filter(|href| is_allowed_link(href)) {
+                    write_attr(out, "href", href);
+                    if !href.starts_with('?') {
+                        write_attr(out, "rel", "noopener noreferrer");
+                    }
+                }
+            }
+            _ => {}
+        }
+        out.push('>');
+
+        // void element, no content or closing tag
+        tag != HtmlTag::Br
+    }
+
+    fn end_element(&mut self, tag: HtmlTag, _attrs: &Attributes) {
+        let _ = write!(self.out, "</{}>", tag.name());
+        if tag == HtmlTag::Table {
+            self.out.push_str("</div>");
+        }
+    }
+
+    fn image(&mut self, image: &Image<'_>) {
+        let Some(src) = image.src else {
+            return;
+        };
+
+        let out = &mut self.out;
+        out.push_str("<img");
+        if let Some(attrs) = image.attrs {
+            write_common_attrs(HtmlTag::Img, attrs, out);
+        } else if let Some(title) = image.title {
+            write_attr(out, "title", title);
+        }
+        write_attr(out, "src", src);
+        if let Some(width) = image.width {
+            write_attr(out, "width", &width.to_string());
+        }
+        if let Some(height) = image.height {
+            write_attr(out, "height", &height.to_string());
+        }
+        write_attr(out, "alt", image.alt.unwrap_or_default());
+        out.push('>');
+
+        if image.attrs.is_none() && let Some(description) = image.description {
+            out.push_str("<span class=\"gloss-image-description\">");
+            write_text(description, out);
+            out.push_str("</span>");
+        }
+    }
+
+    fn deinflection(&mut self, uninflected: &str, rules: &[String]) {
+        let out = &mut self.out;
+        out.push_str("<span class=\"gloss-deinflection\">");
+        write_text(uninflected, out);
+        out.push_str("</span>");
+        if !rules.is_empty() {
+            out.push_str(" <span class=\"gloss-deinflection-rules\">");
+            write_text(&rules.join(", "), out);
+            out.push_str("</span>");
+        }
+    }
+
+    fn finish(&mut self) -> String {
+        self.in_list = false;
+        std::mem::take(&mut self.out)
+    }
+}
+
+fn write_common_attrs(tag: HtmlTag, attrs: &Attributes, out: &mut String) {
+    write_attr(out, "class", &format!("gloss-sc-{}", tag.name()));
+
+    if let Some(lang) = &attrs.lang {
+        write_attr(out, "lang", lang);
+    }
+    if let Some(title) = &attrs.title {
+        write_attr(out, "title", title);
+    }
+    for (key, value) in attrs.data.iter().flatten() {
+        write_attr(out, &format!("data-sc-{}", data_key(key)), value);
+    }
+
+    let style = style(attrs);
+    if !style.is_empty() {
+        write_attr(out, "style", &style);
+    }
+}
+
+fn style(attrs: &Attributes) -> String {
+    let Some(style) = &attrs.style else {
+        return String::new();
+    };
+
+    style.declarations()
+        .into_iter()
+        .filter(|(_, value)| is_safe_css(value))
+        .map(|(property, value)| format!("{}:{}", property, value))
+        .collect::<Vec<_>>()
+        .join(";")
+}
+
+/// CSS values are copied from the dictionary, so only allow characters that
+/// can't end the declaration, and no functions that pull in external
+/// resources or run script (`expression()` in old IE).
+fn is_safe_css(value: &str) -> bool {
+    let lower = value.to_ascii_lowercase();
+    !value.is_empty()
+        && !lower.contains("url(")
+        && !lower.contains("expression(")
+        && value.chars().all(|c| c.is_alphanumeric() || " #.,%-+()".contains(c))
+}
+
+/// `fooBar` to `foo-bar`, the same conversion the DOM does for `dataset`.
+fn data_key(key: &str) -> String {
+    let mut result = String::with_capacity(key.len());
+    for c in key.chars() {
+        if c.is_ascii_uppercase() {
+            result.push('-');
+            result.push(c.to_ascii_lowercase());
+        } else if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+            result.push(c);
+        }
+    }
+    result
+}
+
+fn write_attr(out: &mut String, name: &str, value: &str) {
+    let _ = write!(out, " {}=\"", name);
+    escape(value, out);
+    out.push('"');
+}
+
+/// Writes escaped text, with line breaks as `<br>` like yomitan does.
+fn write_text(text: &str, out: &mut String) {
+    for (i, line) in text.split('\n').enumerate() {
+        if i > 0 {
+            out.push_str("<br>");
+        }
+        escape(line, out);
+    }
+}
+
+fn escape(text: &str, out: &mut String) {
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            _ => out.push(c),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::render::{NoImages, Renderer};
+    use crate::schema::StructuredContent;
+    use serde_json::json;
+
+    fn render(content: serde_json::Value) -> String {
+        let content = StructuredContent::parse(&content).unwrap();
+        Renderer::new(Html::new(), NoImages).render_content(&content).unwrap()
+    }
+
+    #[test]
+    fn escapes_text_and_attributes() {
+        assert_eq!(
+            render(json!(["<script>alert('x')</script>", "a & \"b\""])),
+            "&lt;script&gt;alert(&#39;x&#39;)&lt;/script&gt;a &amp; &quot;b&quot;",
+        );
+        assert_eq!(
+            render(json!({ "tag": "span", "title": "\"><script>", "lang": "ja' onload='x", "content": "t" })),
+            "<span class=\"gloss-sc-span\" lang=\"ja&#39; onload=&#39;x\" title=\"&quot;&gt;&lt;script&gt;\">t</span>",
+        );
+    }
+
+    #[test]
+    fn drops_unsafe_links() {
+        for href in ["javascript:alert(1)", "JavaScript:alert(1)", "data:text/html,<b>", "vbscript:x", "//evil.example"] {
+            assert_eq!(render(json!({ "tag": "a", "href": href, "content": "x" })), "<a class=\"gloss-sc-a\">x</a>", "{}", href);
+        }
+
+        assert_eq!(
+            render(json!({ "tag": "a", "href": "https://example.com/?a=1&b=2", "content": "x" })),
+            "<a class=\"gloss-sc-a\" href=\"https://example.com/?a=1&amp;b=2\" rel=\"noopener noreferrer\">x</a>",
+        );
+        assert_eq!(
+            render(json!({ "tag": "a", "href": "?query=犬", "content": "x" })),
+            "<a class=\"gloss-sc-a\" href=\"?query=犬\">x</a>",
+        );
+    }
+
+    #[test]
+    fn rejects_unsafe_css() {
+        assert!(is_safe_css("red"));
+        assert!(is_safe_css("rgb(10, 20, 30)"));
+        assert!(!is_safe_css("expression(alert(1))"));
+        assert!(!is_safe_css("EXPRESSION(alert(1))"));
+        assert!(!is_safe_css("url(https://example.com/x.png)"));
+        assert!(!is_safe_css("red;background:blue"));
+        assert!(!is_safe_css("\"red\""));
+        assert!(!is_safe_css(""));
+
+        let style = json!({ "color": "red", "background": "url(https://example.com/x.png)", "fontSize": "expression(1)" });
+        assert_eq!(
+            render(json!({ "tag": "span", "style": style, "content": "x" })),
+            "<span class=\"gloss-sc-span\" style=\"color:red\">x</span>",
+        );
+    }
+
+    #[test]
+    fn writes_spans_and_data_attributes() {
+        let cell = json!({ "tag": "td", "colSpan": 2, "rowSpan": 3, "data": { "fooBar": "a\"b" }, "content": "c" });
+        assert_eq!(
+            render(cell),
+            "<td class=\"gloss-sc-td\" data-sc-foo-bar=\"a&quot;b\" colspan=\"2\" rowspan=\"3\">c</td>",
+        );
+
+        // data keys can't break out of the attribute name
+        let span = json!({ "tag": "span", "data": { "x\" onclick=\"y": "z" }, "content": "c" });
+        assert_eq!(render(span), "<span class=\"gloss-sc-span\" data-sc-xonclicky=\"z\">c</span>");
+    }
+}
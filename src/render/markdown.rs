@@ -0,0 +1,319 @@
+//! Markdown output. Follows the same layout as [`PlainText`], with
+//! emphasis, links, images and pipe tables written as Markdown.
+//!
+//! [`PlainText`]: super::PlainText
+
+use super::text::{Lines, List};
+use super::{Image, Target, is_allowed_link};
+use crate::schema::{Attributes, Definition, FontStyle, FontWeight, HtmlTag, TextDecorationLine};
+
+pub struct Markdown {
+    lines: Lines,
+    lists: Vec<List>,
+    definitions: Option<List>,
+    /// Number of columns written in the current table row.
+    cells: usize,
+    /// Number of rows written in the current table.
+    rows: usize,
+    in_cell: bool,
+}
+
+impl Default for Markdown {
+    fn default() -> Self {
+        Self {
+            lines: Lines::new("    "),
+            lists: Vec::new(),
+            definitions: None,
+            cells: 0,
+            rows: 0,
+            in_cell: false,
+        }
+    }
+}
+
+impl Markdown {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn list_depth(&self) -> usize {
+        self.lists.len() + usize::from(self.definitions.is_some())
+    }
+
+    /// Line breaks can't appear inside table cells, so they become spaces.
+    fn newline(&mut self) {
+        if !self.in_cell {
+            self.lines.newline();
+        } else if !self.lines.ends_with(' ') {
+            self.lines.push(" ");
+        }
+    }
+
+    fn push_escaped(&mut self, text: &str) {
+        let mut escaped = String::with_capacity(text.len());
+        for c in text.chars() {
+            match c {
+                '\n' if self.in_cell => escaped.push(' '),
+                '\\' | '`' | '*' | '_' | '[' | ']' | '<' | '>' | '#' | '|' => {
+                    escaped.push('\\');
+                    escaped.push(c);
+                }
+                _ => escaped.push(c),
+            }
+        }
+        self.lines.push(&escaped);
+    }
+}
+
+/// Markers for the emphasis Markdown can express. They are symmetric, so
+/// the same string opens and closes the element.
+fn emphasis(attrs: &Attributes) -> &'static str {
+    let Some(style) = &attrs.style else {
+        return "";
+    };
+
+    let strikethrough = style.text_decoration_line
+        .as_ref()
+        .is_some_and(|lines| lines.contains(&TextDecorationLine::LineThrough));
+
+    match (style.font_weight, style.font_style, strikethrough) {
+        (_, _, true) => "~~",
+        (Some(FontWeight::Bold), Some(FontStyle::Italic), _) => "***",
+        (Some(FontWeight::Bold), _, _) => "**",
+        (_, Some(FontStyle::Italic), _) => "*",
+        _ => "",
+    }
+}
+
+impl Target for Markdown {
+    fn begin_list(&mut self, count: usize) {
+        if count > 1 {
+            self.definitions = Some(List { ordered: true, next: 0 });
+        }
+    }
+
+    fn end_list(&mut self) {
+        self.definitions = None;
+    }
+
+    fn begin_definition(&mut self, _definition: &Definition) {
+        self.lines.newline();
+        if let Some(list) = &mut self.definitions {
+            self.lines.set_depth(0);
+            let marker = list.marker("");
+            self.lines.push(&marker);
+            self.lines.set_depth(1);
+        }
+    }
+
+    fn end_definition(&mut self, _definition: &Definition) {
+        self.lines.newline();
+    }
+
+    fn text(&mut self, text: &str) {
+        self.push_escaped(text);
+    }
+
+    fn begin_element(&mut self, tag: HtmlTag, attrs: &Attributes) -> bool {
+        match tag {
+            HtmlTag::Rp => return false,
+            HtmlTag::Br => {
+                if self.in_cell {
+                    self.newline();
+                } else {
+                    self.lines.push("  \n");
+                }
+                return false;
+            }
+            HtmlTag::Rt => self.lines.push("["),
+            HtmlTag::A if attrs.href.as_deref().is_some_and(is_allowed_link) => self.lines.push("["),
+            HtmlTag::Ul | HtmlTag::Ol => {
+                self.newline();
+                self.lists.push(List { ordered: tag == HtmlTag::Ol, next: 0 });
+            }
+            HtmlTag::Li => {
+                self.newline();
+                self.lines.set_depth(self.list_depth().saturating_sub(1));
+                let marker = self.lists.last_mut()
+                    .map(|list| list.marker("- "))
+                    .unwrap_or_default();
+                self.lines.push(&marker);
+                self.lines.set_depth(self.list_depth());
+            }
+            HtmlTag::Table => {
+                self.lines.newline();
+                self.rows = 0;
+            }
+            HtmlTag::Tr => {
+                self.lines.newline();
+                self.lines.push("|");
+                self.cells = 0;
+            }
+            HtmlTag::Td | HtmlTag::Th => {
+                self.lines.push(" ");
+                self.in_cell = true;
+            }
+            HtmlTag::Summary => {
+                self.newline();
+                self.lines.push("**");
+            }
+            HtmlTag::Div | HtmlTag::Details => self.newline(),
+            _ => {}
+        }
+
+        self.lines.push(emphasis(attrs));
+        true
+    }
+
+    fn end_element(&mut self, tag: HtmlTag, attrs: &Attributes) {
+        self.lines.push(emphasis(attrs));
+
+        match tag {
+            HtmlTag::Rt => self.lines.push("]"),
+            HtmlTag::A => {
+                if let Some(href) = attrs.href.as_deref().filter(|href| is_allowed_link(href)) {
+                    self.lines.push(&format!("]({})", href.replace(' ', "%20").replace(')', "%29")));
+                }
+            }
+            HtmlTag::Ul | HtmlTag::Ol => {
+                self.lists.pop();
+                self.newline();
+                self.lines.set_depth(self.list_depth());
+            }
+            HtmlTag::Td | HtmlTag::Th => {
+                let span = attrs.col_span.unwrap_or(1).max(1) as usize;
+                self.lines.push(&" |".repeat(span));
+                self.cells += span;
+                self.in_cell = false;
+            }
+            HtmlTag::Tr => {
+                self.lines.newline();
+                if self.rows == 0 {
+                    self.lines.push(&format!("|{}", " --- |".repeat(self.cells.max(1))));
+                    self.lines.newline();
+                }
+                self.rows += 1;
+            }
+            HtmlTag::Summary => {
+                self.lines.push("**");
+                self.newline();
+            }
+            HtmlTag::Li | HtmlTag::Div | HtmlTag::Table | HtmlTag::Details => self.newline(),
+            _ => {}
+        }
+    }
+
+    fn image(&mut self, image: &Image<'_>) {
+        let alt = image.alt
+            .or(image.description)
+            .or(image.title)
+            .unwrap_or_default();
+
+        match image.src {
+            Some(src) => {
+                self.lines.push("![");
+                self.push_escaped(alt);
+                self.lines.push(&format!("]({})", src.replace(' ', "%20").replace(')', "%29")));
+            }
+            None => {
+                self.lines.push("*");
+                self.push_escaped(&format!("[image: {}]", if alt.is_empty() { image.path } else { alt }));
+                self.lines.push("*");
+            }
+        }
+    }
+
+    fn deinflection(&mut self, uninflected: &str, rules: &[String]) {
+        self.push_escaped(uninflected);
+        if !rules.is_empty() {
+            self.push_escaped(&format!(" ({})", rules.join(", ")));
+        }
+    }
+
+    fn finish(&mut self) -> String {
+        self.lists.clear();
+        self.definitions = None;
+        self.cells = 0;
+        self.rows = 0;
+        self.in_cell = false;
+        self.lines.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::format::Dictionary;
+    use crate::format::container::open_container;
+    use crate::format::ConvertOptions;
+    use crate::format::test_util::{build_dictionary, scratch_dir, term};
+    use crate::render::{DataUri, ImageResolver, NoImages, Renderer, UrlScheme};
+    use crate::schema::StructuredContent;
+    use serde_json::{Value, json};
+    use std::fs;
+
+    fn render_with(images: impl ImageResolver, content: &Value) -> String {
+        let content = StructuredContent::parse(content).unwrap();
+        Renderer::new(Markdown::new(), images).render_content(&content).unwrap()
+    }
+
+    fn render(content: Value) -> String {
+        render_with(NoImages, &content)
+    }
+
+    #[test]
+    fn escapes_markdown_in_text() {
+        assert_eq!(render(json!("*not* _emphasis_ [or] `code` # | <b>")), r"\*not\* \_emphasis\_ \[or\] \`code\` \# \| \<b\>");
+        assert_eq!(render(json!({ "tag": "span", "style": { "fontWeight": "bold" }, "content": "a*b" })), r"**a\*b**");
+    }
+
+    #[test]
+    fn inlines_ruby_and_numbers_lists() {
+        let ruby = json!({ "tag": "ruby", "content": [
+            "犬", { "tag": "rp", "content": "(" }, { "tag": "rt", "content": "いぬ" }, { "tag": "rp", "content": ")" },
+        ] });
+        assert_eq!(render(ruby), "犬[いぬ]");
+
+        let list = json!({ "tag": "ol", "content": [
+            { "tag": "li", "content": "one" },
+            { "tag": "li", "content": ["two", { "tag": "ul", "content": [{ "tag": "li", "content": "nested" }] }] },
+        ] });
+        assert_eq!(render(list), "1. one\n2. two\n    - nested");
+    }
+
+    #[test]
+    fn writes_pipe_tables() {
+        let table = json!({ "tag": "table", "content": [
+            { "tag": "tr", "content": [{ "tag": "th", "content": "kana" }, { "tag": "th", "content": "a|b" }] },
+            { "tag": "tr", "content": [{ "tag": "td", "content": ["k", { "tag": "br" }, "a"] }, { "tag": "td", "content": "か" }] },
+            { "tag": "tr", "content": [{ "tag": "td", "colSpan": 2, "content": "both" }] },
+        ] });
+        assert_eq!(render(table), "| kana | a\\|b |\n| --- | --- |\n| k a | か |\n| both | |");
+    }
+
+    #[test]
+    fn links_and_images_follow_the_resolver() {
+        let links = json!([
+            { "tag": "a", "href": "https://example.com/a b", "content": "web" },
+            " ",
+            { "tag": "a", "href": "javascript:alert(1)", "content": "script" },
+        ]);
+        assert_eq!(render(links), "[web](https://example.com/a%20b) script");
+
+        let dir = scratch_dir("render-markdown");
+        let files: [(&str, &[u8]); 1] = [("img/dog.png", b"png")];
+        let path = build_dictionary(&dir, "dict.tomo", "Dict", &[term("犬", "いぬ", "dog")], &files, &ConvertOptions::default());
+        let dictionary: Dictionary = open_container(path.to_str().unwrap(), true).unwrap();
+
+        let image = json!({ "tag": "img", "path": "img/dog.png", "alt": "a [dog]" });
+        assert_eq!(render_with(NoImages, &image), r"*\[image: a \[dog\]\]*");
+        assert_eq!(render_with(UrlScheme::new("tomochan://dict/"), &image), r"![a \[dog\]](tomochan://dict/img/dog.png)");
+        assert_eq!(render_with(DataUri::new(&dictionary), &image), r"![a \[dog\]](data:image/png;base64,cG5n)");
+
+        let missing = json!({ "tag": "img", "path": "img/cat.png" });
+        assert_eq!(render_with(DataUri::new(&dictionary), &missing), r"*\[image: img/cat.png\]*");
+
+        drop(dictionary);
+        let _ = fs::remove_dir_all(dir);
+    }
+}
@@ -0,0 +1,253 @@
+//! Rendering of dictionary definitions for display.
+//!
+//! A [`Renderer`] walks definitions and their structured content, and
+//! reports what it finds to a [`Target`], which produces the actual output.
+//! [`Html`], [`PlainText`] and [`Markdown`] targets are provided.
+//!
+//! Definitions can reference media files stored in the dictionary (see
+//! [`Dictionary::find_file`]). Renderers do not read those files themselves,
+//! instead an [`ImageResolver`] decides what URL an image is displayed from.
+
+mod html;
+mod text;
+mod markdown;
+
+pub use html::Html;
+pub use text::PlainText;
+pub use markdown::Markdown;
+
+use crate::format::Dictionary;
+use crate::schema::{Attributes, Definition, HtmlTag, StructuredContent};
+use crate::error::StoreError;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+
+/// An image to display, either a whole definition or an `img` element in
+/// structured content.
+pub struct Image<'a> {
+    /// URL returned by the [`ImageResolver`], `None` if it left the image out.
+    pub src: Option<&'a str>,
+    pub path: &'a str,
+    pub width: Option<f32>,
+    pub height: Option<f32>,
+    pub title: Option<&'a str>,
+    pub alt: Option<&'a str>,
+    pub description: Option<&'a str>,
+    /// Attributes of the `img` element, `None` for image definitions.
+    pub attrs: Option<&'a Attributes>,
+}
+
+/// Output format driven by a [`Renderer`].
+pub trait Target {
+    /// Called before the definitions of a term when rendering all of them.
+    fn begin_list(&mut self, _count: usize) {}
+    fn end_list(&mut self) {}
+
+    fn begin_definition(&mut self, _definition: &Definition) {}
+    fn end_definition(&mut self, _definition: &Definition) {}
+
+    fn text(&mut self, text: &str);
+
+    /// Called before the content of an element. If this returns false the
+    /// content is skipped, and [`Target::end_element`] is not called.
+    fn begin_element(&mut self, tag: HtmlTag, attrs: &Attributes) -> bool;
+    fn end_element(&mut self, tag: HtmlTag, attrs: &Attributes);
+
+    fn image(&mut self, image: &Image<'_>);
+
+    fn deinflection(&mut self, uninflected: &str, rules: &[String]);
+
+    /// Returns the output produced so far, and resets the target.
+    fn finish(&mut self) -> String;
+}
+
+pub struct Renderer<T: Target, R: ImageResolver> {
+    target: T,
+    images: R,
+}
+
+impl<T: Target, R: ImageResolver> Renderer<T, R> {
+    pub fn new(target: T, images: R) -> Self {
+        Self { target, images }
+    }
+
+    /// Renders all definitions of a term as a list.
+    pub fn render_definitions(&mut self, definitions: &[Definition]) -> Result<String, StoreError> {
+        self.target.begin_list(definitions.len());
+        for definition in definitions {
+            self.walk_definition(definition)?;
+        }
+        self.target.end_list();
+
+        Ok(self.target.finish())
+    }
+
+    pub fn render_definition(&mut self, definition: &Definition) -> Result<String, StoreError> {
+        self.walk_definition(definition)?;
+        Ok(self.target.finish())
+    }
+
+    pub fn render_content(&mut self, content: &StructuredContent) -> Result<String, StoreError> {
+        self.walk_content(content)?;
+        Ok(self.target.finish())
+    }
+
+    fn walk_definition(&mut self, definition: &Definition) -> Result<(), StoreError> {
+        self.target.begin_definition(definition);
+        match definition {
+            Definition::Text(text) => self.target.text(text),
+            Definition::StructuredContent(content) => self.walk_content(content)?,
+            Definition::Image { path, width, height, title, alt, description, .. } => {
+                let src = self.images.resolve(path)?;
+                self.target.image(&Image {
+                    src: src.as_deref(),
+                    path,
+                    width: width.map(f32::from),
+                    height: height.map(f32::from),
+                    title: title.as_deref(),
+                    alt: alt.as_deref(),
+                    description: description.as_deref(),
+                    attrs: None,
+                });
+            }
+            Definition::Deinflection { uninflected, rules } => self.target.deinflection(uninflected, rules),
+        }
+        self.target.end_definition(definition);
+
+        Ok(())
+    }
+
+    fn walk_content(&mut self, content: &StructuredContent) -> Result<(), StoreError> {
+        match content {
+            StructuredContent::Text(text) => self.target.text(text),
+            StructuredContent::Array(items) => {
+                for item in items {
+                    self.walk_content(item)?;
+                }
+            }
+            StructuredContent::Element { tag: HtmlTag::Img, attrs, .. } => {
+                let Some(path) = attrs.path.as_deref() else {
+                    return Ok(());
+                };
+
+                let src = self.images.resolve(path)?;
+                self.target.image(&Image {
+                    src: src.as_deref(),
+                    path,
+                    width: attrs.width,
+                    height: attrs.height,
+                    title: attrs.title.as_deref(),
+                    alt: attrs.alt.as_deref(),
+                    description: attrs.description.as_deref(),
+                    attrs: Some(attrs),
+                });
+            }
+            StructuredContent::Element { tag, content, attrs } => {
+                if !self.target.begin_element(*tag, attrs) {
+                    return Ok(());
+                }
+                if let Some(content) = content {
+                    self.walk_content(content)?;
+                }
+                self.target.end_element(*tag, attrs);
+            }
+            StructuredContent::UnknownElement { content, .. } => {
+                if let Some(content) = content {
+                    self.walk_content(content)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Turns the path of a media file inside a dictionary into a URL.
+pub trait ImageResolver {
+    /// Returns the URL to display the image at `path` from, or `None` if the
+    /// image should be left out.
+    fn resolve(&mut self, path: &str) -> Result<Option<String>, StoreError>;
+}
+
+/// Resolves images to `prefix` followed by the percent encoded path, for
+/// applications that serve dictionary media themselves (ex. through a custom
+/// URL scheme such as `tomochan://jitendex/`).
+pub struct UrlScheme {
+    prefix: String,
+}
+
+impl UrlScheme {
+    pub fn new(prefix: impl Into<String>) -> Self {
+        Self { prefix: prefix.into() }
+    }
+}
+
+impl ImageResolver for UrlScheme {
+    fn resolve(&mut self, path: &str) -> Result<Option<String>, StoreError> {
+        let mut url = self.prefix.clone();
+        for byte in path.bytes() {
+            match byte {
+                b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => url.push(byte as char),
+                _ => url.push_str(&format!("%{:02X}", byte)),
+            }
+        }
+
+        Ok(Some(url))
+    }
+}
+
+/// Embeds images as `data:` URIs, reading them from the dictionary the
+/// definitions came from. Images missing from the dictionary are left out.
+pub struct DataUri<'a> {
+    dictionary: &'a Dictionary,
+}
+
+impl<'a> DataUri<'a> {
+    pub fn new(dictionary: &'a Dictionary) -> Self {
+        Self { dictionary }
+    }
+}
+
+impl ImageResolver for DataUri<'_> {
+    fn resolve(&mut self, path: &str) -> Result<Option<String>, StoreError> {
+        let Some(data) = self.dictionary.find_file(path)? else {
+            return Ok(None);
+        };
+
+        Ok(Some(format!("data:{};base64,{}", mime_type(path), BASE64.encode(data))))
+    }
+}
+
+/// Leaves out every image.
+pub struct NoImages;
+
+impl ImageResolver for NoImages {
+    fn resolve(&mut self, _path: &str) -> Result<Option<String>, StoreError> {
+        Ok(None)
+    }
+}
+
+fn mime_type(path: &str) -> &'static str {
+    let extension = path.rsplit_once('.')
+        .map(|(_, ext)| ext.to_ascii_lowercase())
+        .unwrap_or_default();
+
+    match extension.as_str() {
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "svg" => "image/svg+xml",
+        "bmp" => "image/bmp",
+        "avif" => "image/avif",
+        "tif" | "tiff" => "image/tiff",
+        _ => "application/octet-stream",
+    }
+}
+
+/// Links in dictionaries are either internal `?query=` links, or external
+/// `http(s)` links. Anything else (ex. `javascript:`) is dropped.
+fn is_allowed_link(href: &str) -> bool {
+    let lower = href.to_ascii_lowercase();
+    href.starts_with('?') || lower.starts_with("http:") || lower.starts_with("https:")
+}
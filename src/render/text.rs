@@ -0,0 +1,291 @@
+//! Plain text output, for terminals, screen readers and flashcard fields.
+//!
+//! Ruby is inlined as `漢字[かんじ]` and `rp` fallbacks are dropped. Lists
+//! become indented bullet or numbered lines, and table cells are joined with
+//! `|` on one line per row.
+
+use super::{Image, Target};
+use crate::schema::{Attributes, Definition, HtmlTag};
+
+/// Line oriented output buffer shared by the text based targets.
+pub(super) struct Lines {
+    out: String,
+    indent: &'static str,
+    depth: usize,
+    at_line_start: bool,
+}
+
+impl Lines {
+    pub(super) fn new(indent: &'static str) -> Self {
+        Self {
+            out: String::new(),
+            indent,
+            depth: 0,
+            at_line_start: true,
+        }
+    }
+
+    pub(super) fn push(&mut self, text: &str) {
+        for c in text.chars() {
+            if c == '\n' {
+                self.out.push(c);
+                self.at_line_start = true;
+                continue;
+            }
+
+            if self.at_line_start {
+                for _ in 0..self.depth {
+                    self.out.push_str(self.indent);
+                }
+                self.at_line_start = false;
+            }
+            self.out.push(c);
+        }
+    }
+
+    /// Starts a new line, unless already at the start of one.
+    pub(super) fn newline(&mut self) {
+        if !self.at_line_start {
+            self.out.push('\n');
+            self.at_line_start = true;
+        }
+    }
+
+    pub(super) fn set_depth(&mut self, depth: usize) {
+        self.depth = depth;
+    }
+
+    pub(super) fn ends_with(&self, c: char) -> bool {
+        self.out.ends_with(c)
+    }
+
+    pub(super) fn finish(&mut self) -> String {
+        self.depth = 0;
+        self.at_line_start = true;
+        let out = std::mem::take(&mut self.out);
+        out.trim_end().to_string()
+    }
+}
+
+/// Open `ul` or `ol`, and the number of the next item for `ol`.
+pub(super) struct List {
+    pub(super) ordered: bool,
+    pub(super) next: usize,
+}
+
+impl List {
+    pub(super) fn marker(&mut self, bullet: &str) -> String {
+        if self.ordered {
+            self.next += 1;
+            format!("{}. ", self.next)
+        } else {
+            bullet.to_string()
+        }
+    }
+}
+
+pub struct PlainText {
+    lines: Lines,
+    lists: Vec<List>,
+    /// Set when rendering more than one definition, numbers them.
+    definitions: Option<List>,
+    /// Number of cells written in the current table row.
+    cells: usize,
+}
+
+impl Default for PlainText {
+    fn default() -> Self {
+        Self {
+            lines: Lines::new("  "),
+            lists: Vec::new(),
+            definitions: None,
+            cells: 0,
+        }
+    }
+}
+
+impl PlainText {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn list_depth(&self) -> usize {
+        self.lists.len() + usize::from(self.definitions.is_some())
+    }
+}
+
+impl Target for PlainText {
+    fn begin_list(&mut self, count: usize) {
+        if count > 1 {
+            self.definitions = Some(List { ordered: true, next: 0 });
+        }
+    }
+
+    fn end_list(&mut self) {
+        self.definitions = None;
+    }
+
+    fn begin_definition(&mut self, _definition: &Definition) {
+        self.lines.newline();
+        if let Some(list) = &mut self.definitions {
+            self.lines.set_depth(0);
+            let marker = list.marker("");
+            self.lines.push(&marker);
+            self.lines.set_depth(1);
+        }
+    }
+
+    fn end_definition(&mut self, _definition: &Definition) {
+        self.lines.newline();
+    }
+
+    fn text(&mut self, text: &str) {
+        self.lines.push(text);
+    }
+
+    fn begin_element(&mut self, tag: HtmlTag, _attrs: &Attributes) -> bool {
+        match tag {
+            HtmlTag::Rp => return false,
+            HtmlTag::Br => {
+                self.lines.push("\n");
+                return false;
+            }
+            HtmlTag::Rt => self.lines.push("["),
+            HtmlTag::Ul | HtmlTag::Ol => {
+                self.lines.newline();
+                self.lists.push(List { ordered: tag == HtmlTag::Ol, next: 0 });
+            }
+            HtmlTag::Li => {
+                self.lines.newline();
+                self.lines.set_depth(self.list_depth().saturating_sub(1));
+                let marker = self.lists.last_mut()
+                    .map(|list| list.marker("• "))
+                    .unwrap_or_default();
+                self.lines.push(&marker);
+                self.lines.set_depth(self.list_depth());
+            }
+            HtmlTag::Tr => {
+                self.lines.newline();
+                self.cells = 0;
+            }
+            HtmlTag::Td | HtmlTag::Th => {
+                if self.cells > 0 {
+                    self.lines.push(" | ");
+                }
+                self.cells += 1;
+            }
+            HtmlTag::Div | HtmlTag::Table | HtmlTag::Thead | HtmlTag::Tbody | HtmlTag::Tfoot
+            | HtmlTag::Details | HtmlTag::Summary => self.lines.newline(),
+            _ => {}
+        }
+
+        true
+    }
+
+    fn end_element(&mut self, tag: HtmlTag, _attrs: &Attributes) {
+        match tag {
+            HtmlTag::Rt => self.lines.push("]"),
+            HtmlTag::Ul | HtmlTag::Ol => {
+                self.lists.pop();
+                self.lines.newline();
+                self.lines.set_depth(self.list_depth());
+            }
+            HtmlTag::Li | HtmlTag::Tr | HtmlTag::Div | HtmlTag::Table | HtmlTag::Thead | HtmlTag::Tbody
+            | HtmlTag::Tfoot | HtmlTag::Details | HtmlTag::Summary => self.lines.newline(),
+            _ => {}
+        }
+    }
+
+    fn image(&mut self, image: &Image<'_>) {
+        let label = image.description
+            .or(image.alt)
+            .or(image.title)
+            .filter(|label| !label.is_empty())
+            .unwrap_or(image.path);
+
+        self.lines.push(&format!("[image: {}]", label));
+    }
+
+    fn deinflection(&mut self, uninflected: &str, rules: &[String]) {
+        self.lines.push(uninflected);
+        if !rules.is_empty() {
+            self.lines.push(&format!(" ({})", rules.join(", ")));
+        }
+    }
+
+    fn finish(&mut self) -> String {
+        self.lists.clear();
+        self.definitions = None;
+        self.cells = 0;
+        self.lines.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::render::{NoImages, Renderer, UrlScheme};
+    use crate::schema::StructuredContent;
+    use serde_json::{Value, json};
+
+    fn render(content: Value) -> String {
+        let content = StructuredContent::parse(&content).unwrap();
+        Renderer::new(PlainText::new(), NoImages).render_content(&content).unwrap()
+    }
+
+    #[test]
+    fn inlines_ruby_without_fallbacks() {
+        let ruby = json!({ "tag": "ruby", "content": [
+            "漢字",
+            { "tag": "rp", "content": "(" },
+            { "tag": "rt", "content": "かんじ" },
+            { "tag": "rp", "content": ")" },
+        ] });
+        assert_eq!(render(json!([ruby, "を書く"])), "漢字[かんじ]を書く");
+    }
+
+    #[test]
+    fn numbers_lists() {
+        let list = json!({ "tag": "ol", "content": [
+            { "tag": "li", "content": "one" },
+            { "tag": "li", "content": ["two", { "tag": "ul", "content": [
+                { "tag": "li", "content": "nested" },
+            ] }] },
+            { "tag": "li", "content": "three" },
+        ] });
+        assert_eq!(render(list), "1. one\n2. two\n  • nested\n3. three");
+
+        let definitions = [Definition::Text("dog".into()), Definition::Text("hound".into())];
+        assert_eq!(
+            Renderer::new(PlainText::new(), NoImages).render_definitions(&definitions).unwrap(),
+            "1. dog\n2. hound",
+        );
+    }
+
+    #[test]
+    fn flattens_tables() {
+        let table = json!({ "tag": "table", "content": [
+            { "tag": "tr", "content": [{ "tag": "th", "content": "kana" }, { "tag": "th", "content": "romaji" }] },
+            { "tag": "tr", "content": [{ "tag": "td", "content": "か" }, { "tag": "td", "content": ["k", { "tag": "br" }, "a"] }] },
+        ] });
+        assert_eq!(render(table), "kana | romaji\nか | k\na");
+    }
+
+    #[test]
+    fn labels_images_and_keeps_link_text() {
+        let content = json!([
+            { "tag": "a", "href": "https://example.com", "content": "link" },
+            " ",
+            { "tag": "img", "path": "img/dog.png", "alt": "a dog" },
+            " ",
+            { "tag": "img", "path": "img/cat.png" },
+        ]);
+        let expected = "link [image: a dog] [image: img/cat.png]";
+        assert_eq!(render(content.clone()), expected);
+
+        // text has nowhere to show an image, so resolved URLs are not used
+        let content = StructuredContent::parse(&content).unwrap();
+        let rendered = Renderer::new(PlainText::new(), UrlScheme::new("tomochan://dict/")).render_content(&content).unwrap();
+        assert_eq!(rendered, expected);
+    }
+}
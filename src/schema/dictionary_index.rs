@@ -1,6 +1,8 @@
 use bincode::{Encode, Decode};
 use std::collections::HashMap;
-use crate::schema::get_optional_str;
+use crate::schema::{get_optional_str, set_optional_str, f32_to_json};
+use serde_json::{Map, Value};
+use crate::error::SchemaError;
 
 #[derive(Encode, Decode, Debug, Clone)]
 pub struct DictionaryIndex {
@@ -38,24 +40,24 @@ pub struct TagMetaInfo {
 }
 
 impl DictionaryIndex {
-    pub fn from_json(obj: &serde_json::Map<String, serde_json::Value>) -> Result<Self, String> {
+    pub fn from_json(obj: &serde_json::Map<String, serde_json::Value>) -> Result<Self, SchemaError> {
         let title = obj.get("title")
             .and_then(|v| v.as_str())
-            .ok_or("Missing title")?
+            .ok_or(SchemaError::MissingField("title"))?
             .to_string();
         
         let revision = obj.get("revision")
             .and_then(|v| v.as_str())
-            .ok_or("Missing revision")?
+            .ok_or(SchemaError::MissingField("revision"))?
             .to_string();
         
         let format = obj.get("format")
             .or_else(|| obj.get("version"))
             .and_then(|v| v.as_u64())
-            .ok_or("Missing format/version")? as u8;
+            .ok_or(SchemaError::MissingField("format/version"))? as u8;
         
-        if format < 1 || format > 3 {
-            return Err(format!("Invalid format value: {}", format));
+        if !(1..=3).contains(&format) {
+            return Err(SchemaError::UnsupportedFormat(format));
         }
         
         Ok(DictionaryIndex {
@@ -78,7 +80,7 @@ impl DictionaryIndex {
                 .and_then(Self::parse_frequency_mode),
             tag_meta: obj.get("tagMeta")
                 .and_then(|v| v.as_object())
-                .map(|meta_obj| Self::parse_tag_meta(meta_obj))
+                .map(Self::parse_tag_meta)
                 .transpose()?,
         })
     }
@@ -91,7 +93,7 @@ impl DictionaryIndex {
         }
     }
     
-    fn parse_tag_meta(obj: &serde_json::Map<String, serde_json::Value>) -> Result<HashMap<String, TagMetaInfo>, String> {
+    fn parse_tag_meta(obj: &serde_json::Map<String, serde_json::Value>) -> Result<HashMap<String, TagMetaInfo>, SchemaError> {
         Ok(obj.iter()
             .filter_map(|(tag_name, value)| {
                 value.as_object().map(|tag_obj| {
@@ -105,4 +107,57 @@ impl DictionaryIndex {
             })
             .collect())
     }
-}
\ No newline at end of file
+}
+
+impl FrequencyMode {
+    pub fn name(self) -> &'static str {
+        match self {
+            FrequencyMode::OccurrenceBased => "occurrence-based",
+            FrequencyMode::RankBased => "rank-based",
+        }
+    }
+}
+
+impl DictionaryIndex {
+    /// Writes the metadata back as a yomitan `index.json` object.
+    pub fn to_json(&self) -> Map<String, Value> {
+        let mut obj = Map::new();
+        obj.insert("title".into(), Value::from(self.title.as_str()));
+        obj.insert("revision".into(), Value::from(self.revision.as_str()));
+        obj.insert("format".into(), Value::from(self.format));
+        obj.insert("sequenced".into(), Value::from(self.sequenced));
+        set_optional_str(&mut obj, "minimumYomitanVersion", &self.minimum_yomitan_version);
+        set_optional_str(&mut obj, "author", &self.author);
+        if self.is_updatable {
+            obj.insert("isUpdatable".into(), Value::from(true));
+        }
+        set_optional_str(&mut obj, "indexUrl", &self.index_url);
+        set_optional_str(&mut obj, "downloadUrl", &self.download_url);
+        set_optional_str(&mut obj, "url", &self.url);
+        set_optional_str(&mut obj, "description", &self.description);
+        set_optional_str(&mut obj, "attribution", &self.attribution);
+        set_optional_str(&mut obj, "sourceLanguage", &self.source_language);
+        set_optional_str(&mut obj, "targetLanguage", &self.target_language);
+        if let Some(mode) = self.frequency_mode {
+            obj.insert("frequencyMode".into(), Value::from(mode.name()));
+        }
+        if let Some(tag_meta) = &self.tag_meta {
+            let tags: Map<String, Value> = tag_meta.iter()
+                .map(|(name, info)| {
+                    let mut tag = Map::new();
+                    set_optional_str(&mut tag, "category", &info.category);
+                    if let Some(order) = info.order {
+                        tag.insert("order".into(), f32_to_json(order));
+                    }
+                    set_optional_str(&mut tag, "notes", &info.notes);
+                    if let Some(score) = info.score {
+                        tag.insert("score".into(), f32_to_json(score));
+                    }
+                    (name.clone(), Value::Object(tag))
+                })
+                .collect();
+            obj.insert("tagMeta".into(), Value::Object(tags));
+        }
+        obj
+    }
+}
@@ -1,8 +1,9 @@
 use serde_json::Value;
+use crate::error::SchemaError;
 
-pub fn get_str(val: &Value, field: &str) -> Result<String, String> {
+pub fn get_str(val: &Value, field: &'static str) -> Result<String, SchemaError> {
     val.as_str()
-        .ok_or_else(|| format!("Invalid {}", field))
+        .ok_or(SchemaError::InvalidField(field))
         .map(String::from)
 }
 
@@ -10,38 +11,18 @@ pub fn get_str_or_default(val: &Value) -> String {
     val.as_str().unwrap_or("").to_string()
 }
 
-pub fn get_f32(val: &Value, field: &str) -> Result<f32, String> {
+pub fn get_f32(val: &Value, field: &'static str) -> Result<f32, SchemaError> {
     val.as_f64()
-        .ok_or_else(|| format!("Invalid {}", field))
+        .ok_or(SchemaError::InvalidField(field))
         .map(|v| v as f32)
 }
 
-pub fn get_i32(val: &Value, field: &str) -> Result<i32, String> {
+pub fn get_i32(val: &Value, field: &'static str) -> Result<i32, SchemaError> {
     val.as_i64()
-        .ok_or_else(|| format!("Invalid {}", field))
+        .ok_or(SchemaError::InvalidField(field))
         .map(|v| v as i32)
 }
 
-pub fn get_i64(val: &Value, field: &str) -> Result<i64, String> {
-    val.as_i64()
-        .ok_or_else(|| format!("Invalid {}", field))
-}
-
-pub fn get_u64(val: &Value, field: &str) -> Result<u64, String> {
-    val.as_u64()
-        .ok_or_else(|| format!("Invalid {}", field))
-}
-
-pub fn get_array<'a>(val: &'a Value, field: &str) -> Result<&'a Vec<Value>, String> {
-    val.as_array()
-        .ok_or_else(|| format!("{} must be an array", field))
-}
-
-pub fn get_object<'a>(val: &'a Value, field: &str) -> Result<&'a serde_json::Map<String, Value>, String> {
-    val.as_object()
-        .ok_or_else(|| format!("{} must be an object", field))
-}
-
 pub fn get_optional_str(obj: &serde_json::Map<String, Value>, key: &str) -> Option<String> {
     obj.get(key).and_then(|v| v.as_str()).map(String::from)
 }
@@ -52,13 +33,7 @@ pub fn parse_string_array(val: &Value) -> Vec<String> {
         .unwrap_or_default()
 }
 
-pub fn parse_u32_array(val: &Value) -> Vec<u32> {
-    val.as_array()
-        .map(|arr| arr.iter().filter_map(|v| v.as_u64().map(|n| n as u32)).collect())
-        .unwrap_or_default()
-}
-
-pub fn parse_single_or_multiple<T, F>(value: &Value, single: F, multiple: fn(Vec<u32>) -> T) -> Result<T, String>
+pub fn parse_single_or_multiple<T, F>(value: &Value, single: F, multiple: fn(Vec<u32>) -> T) -> Result<T, SchemaError>
 where
     F: FnOnce(u32) -> T,
 {
@@ -71,5 +46,27 @@ where
         return Ok(multiple(positions));
     }
     
-    Err("Invalid position value".to_string())
+    Err(SchemaError::InvalidField("position"))
+}
+
+/// Writes an `f32` as the shortest JSON number that reads back as the same
+/// value, and as an integer if it is one (ex. `0.1` instead of
+/// `0.10000000149011612`, `5` instead of `5.0`).
+pub fn f32_to_json(value: f32) -> Value {
+    if value.fract() == 0.0 && value.abs() < 1e15 {
+        return Value::from(value as i64);
+    }
+
+    value.to_string()
+        .parse::<f64>()
+        .ok()
+        .and_then(serde_json::Number::from_f64)
+        .map(Value::Number)
+        .unwrap_or(Value::Null)
+}
+
+pub fn set_optional_str(obj: &mut serde_json::Map<String, Value>, key: &str, value: &Option<String>) {
+    if let Some(value) = value {
+        obj.insert(key.to_string(), Value::from(value.as_str()));
+    }
 }
@@ -1,6 +1,7 @@
 use bincode::{Encode, Decode};
 use std::collections::HashMap;
-use crate::schema::{JsonParseable, get_str};
+use crate::schema::{JsonParseable, JsonSerializable, get_str};
+use crate::error::SchemaError;
 
 #[derive(Encode, Decode, Debug, Clone)]
 pub struct Kanji {
@@ -13,17 +14,17 @@ pub struct Kanji {
 }
 
 impl Kanji {
-    fn parse_meanings(value: &serde_json::Value) -> Result<Vec<String>, String> {
+    fn parse_meanings(value: &serde_json::Value) -> Result<Vec<String>, SchemaError> {
         Ok(value.as_array()
-            .ok_or("Meanings must be an array")?
+            .ok_or(SchemaError::ExpectedArray("meanings"))?
             .iter()
             .filter_map(|v| v.as_str().map(String::from))
             .collect())
     }
     
-    fn parse_stats(value: &serde_json::Value) -> Result<HashMap<String, String>, String> {
+    fn parse_stats(value: &serde_json::Value) -> Result<HashMap<String, String>, SchemaError> {
         Ok(value.as_object()
-            .ok_or("Stats must be an object")?
+            .ok_or(SchemaError::ExpectedObject("stats"))?
             .iter()
             .filter_map(|(k, v)| v.as_str().map(|s| (k.clone(), s.to_string())))
             .collect())
@@ -31,9 +32,9 @@ impl Kanji {
 }
 
 impl JsonParseable for Kanji {
-    fn from_json_array(arr: &[serde_json::Value]) -> Result<Self, String> {
+    fn from_json_array(arr: &[serde_json::Value]) -> Result<Self, SchemaError> {
         if arr.len() != 6 {
-            return Err("Kanji array must have exactly 6 elements".to_string());
+            return Err(SchemaError::WrongLength { kind: "Kanji", expected: 6, found: arr.len() });
         }
         
         Ok(Kanji {
@@ -45,4 +46,21 @@ impl JsonParseable for Kanji {
             stats: Self::parse_stats(&arr[5])?,
         })
     }
-}
\ No newline at end of file
+}
+
+impl JsonSerializable for Kanji {
+    fn to_json_array(&self) -> serde_json::Value {
+        let stats: serde_json::Map<String, serde_json::Value> = self.stats.iter()
+            .map(|(k, v)| (k.clone(), serde_json::Value::from(v.as_str())))
+            .collect();
+
+        serde_json::json!([
+            self.character,
+            self.onyomi,
+            self.kunyomi,
+            self.tags,
+            self.meanings,
+            stats,
+        ])
+    }
+}
@@ -1,5 +1,6 @@
 use bincode::{Encode, Decode};
-use crate::schema::{JsonParseable, get_str, Frequency, FrequencyValue};
+use crate::schema::{JsonParseable, JsonSerializable, get_str, Frequency, FrequencyValue};
+use crate::error::SchemaError;
 
 #[derive(Encode, Decode, Debug, Clone)]
 pub struct KanjiMeta {
@@ -9,10 +10,10 @@ pub struct KanjiMeta {
 }
 
 impl KanjiMeta {
-    fn parse_frequency(value: &serde_json::Value) -> Result<Frequency, String> {
+    fn parse_frequency(value: &serde_json::Value) -> Result<Frequency, SchemaError> {
         if let Some(obj) = value.as_object() {
             return Ok(Frequency::Detailed {
-                value: obj.get("value").and_then(|v| v.as_f64()).ok_or("Missing frequency value")? as f32,
+                value: obj.get("value").and_then(|v| v.as_f64()).ok_or(SchemaError::MissingField("frequency value"))? as f32,
                 display_value: obj.get("displayValue").and_then(|v| v.as_str()).map(String::from),
             });
         }
@@ -25,14 +26,14 @@ impl KanjiMeta {
             return Ok(Frequency::Simple(FrequencyValue::String(s.to_string())));
         }
         
-        Err("Invalid frequency format".to_string())
+        Err(SchemaError::InvalidField("frequency"))
     }
 }
 
 impl JsonParseable for KanjiMeta {
-    fn from_json_array(arr: &[serde_json::Value]) -> Result<Self, String> {
+    fn from_json_array(arr: &[serde_json::Value]) -> Result<Self, SchemaError> {
         if arr.len() != 3 {
-            return Err("Kanji meta array must have exactly 3 elements".to_string());
+            return Err(SchemaError::WrongLength { kind: "Kanji meta", expected: 3, found: arr.len() });
         }
         
         Ok(KanjiMeta {
@@ -41,4 +42,10 @@ impl JsonParseable for KanjiMeta {
             data: Self::parse_frequency(&arr[2])?,
         })
     }
-}
\ No newline at end of file
+}
+
+impl JsonSerializable for KanjiMeta {
+    fn to_json_array(&self) -> serde_json::Value {
+        serde_json::json!([self.character, self.mode, self.data.to_json()])
+    }
+}
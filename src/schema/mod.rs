@@ -5,18 +5,36 @@ mod tag_bank;
 mod term_bank;
 mod term_meta_bank;
 mod structured_content;
+mod style;
 mod json_helpers;
 
+pub use dictionary_index::{DictionaryIndex, FrequencyMode, TagMetaInfo};
 pub use kanji_bank::Kanji;
 pub use kanji_meta_bank::KanjiMeta;
 pub use tag_bank::Tag;
-pub use term_bank::Term;
-pub use term_meta_bank::{TermMeta, Frequency, FrequencyValue};
+pub use term_bank::{Term, Definition};
+pub use term_meta_bank::{
+    TermMeta, TermMetaMode, TermMetaData, FrequencyData, Frequency, FrequencyValue,
+    PitchData, PitchAccent, PitchPosition, NasalPositions, DevoicePositions,
+    IpaData, IpaTranscription,
+};
+pub use structured_content::{StructuredContent, HtmlTag, Attributes};
+pub use style::{
+    Style, CssValue, FontStyle, FontWeight, VerticalAlign, TextAlign, TextDecorationLine, TextDecorationStyle,
+    WordBreak,
+};
 
 pub(crate) use json_helpers::*;
 
+use crate::error::SchemaError;
+
 pub(crate) trait JsonParseable: Sized {
-    fn from_json_array(arr: &[serde_json::Value]) -> Result<Self, String>;
+    fn from_json_array(arr: &[serde_json::Value]) -> Result<Self, SchemaError>;
+}
+
+/// Inverse of [`JsonParseable`], writes an entry back as a yomitan bank row.
+pub(crate) trait JsonSerializable {
+    fn to_json_array(&self) -> serde_json::Value;
 }
 
 pub(crate) const BINCODE_CONFIG: bincode::config::Configuration = bincode::config::standard();
@@ -1,4 +1,8 @@
 use bincode::{Encode, Decode};
+use super::style::Style;
+use super::{f32_to_json, set_optional_str};
+use crate::error::SchemaError;
+use serde_json::{Map, Value};
 
 #[derive(Encode, Decode, Debug, Clone)]
 pub enum StructuredContent {
@@ -7,7 +11,14 @@ pub enum StructuredContent {
     Element {
         tag: HtmlTag,
         content: Option<Box<StructuredContent>>,
-        attrs: Attributes,
+        attrs: Box<Attributes>,
+    },
+    /// Element with a tag this version does not know, kept so that it can
+    /// be written back out. Renderers only display its content.
+    UnknownElement {
+        tag: String,
+        content: Option<Box<StructuredContent>>,
+        attrs: Box<Attributes>,
     },
 }
 
@@ -22,6 +33,34 @@ pub enum HtmlTag {
     Img = 18, A = 19,
 }
 
+impl HtmlTag {
+    /// Name of the tag, as written in yomitan dictionaries.
+    pub fn name(self) -> &'static str {
+        match self {
+            HtmlTag::Br => "br",
+            HtmlTag::Ruby => "ruby",
+            HtmlTag::Rt => "rt",
+            HtmlTag::Rp => "rp",
+            HtmlTag::Table => "table",
+            HtmlTag::Thead => "thead",
+            HtmlTag::Tbody => "tbody",
+            HtmlTag::Tfoot => "tfoot",
+            HtmlTag::Tr => "tr",
+            HtmlTag::Td => "td",
+            HtmlTag::Th => "th",
+            HtmlTag::Span => "span",
+            HtmlTag::Div => "div",
+            HtmlTag::Ol => "ol",
+            HtmlTag::Ul => "ul",
+            HtmlTag::Li => "li",
+            HtmlTag::Details => "details",
+            HtmlTag::Summary => "summary",
+            HtmlTag::Img => "img",
+            HtmlTag::A => "a",
+        }
+    }
+}
+
 #[derive(Encode, Decode, Debug, Clone, Default)]
 pub struct Attributes {
     pub lang: Option<String>,
@@ -36,44 +75,17 @@ pub struct Attributes {
     pub description: Option<String>,
     pub data: Option<Vec<(String, String)>>,
     pub open: Option<bool>,
-
-    pub font_style: Option<FontStyle>,
-    pub font_weight: Option<FontWeight>,
-    pub font_size: Option<String>,
-    pub color: Option<String>,
-    pub background: Option<String>,
-    pub vertical_align: Option<VerticalAlign>,
-    pub text_align: Option<TextAlign>,
-}
-
-#[derive(Encode, Decode, Debug, Clone, Copy, PartialEq, Eq)]
-#[repr(u8)]
-pub enum FontStyle {
-    Normal = 0,
-    Italic = 1,
-}
-
-#[derive(Encode, Decode, Debug, Clone, Copy, PartialEq, Eq)]
-#[repr(u8)]
-pub enum FontWeight {
-    Normal = 0,
-    Bold = 1,
-}
-
-#[derive(Encode, Decode, Debug, Clone, Copy, PartialEq, Eq)]
-#[repr(u8)]
-pub enum VerticalAlign {
-    Baseline = 0, Sub = 1, Super = 2, TextTop = 3, TextBottom = 4, Middle = 5, Top = 6, Bottom = 7,
-}
-
-#[derive(Encode, Decode, Debug, Clone, Copy, PartialEq, Eq)]
-#[repr(u8)]
-pub enum TextAlign {
-    Start = 0, End = 1, Left = 2, Right = 3, Center = 4, Justify = 5, JustifyAll = 6, MatchParent = 7,
+    /// Boxed rather than inline fields, most elements have no style and
+    /// there are too many properties to give every element room for them.
+    pub style: Option<Box<Style>>,
+    /// Attributes this version does not know, as name and JSON encoded
+    /// value. Unknown style properties are kept as a `style` object holding
+    /// only those properties.
+    pub extra: Option<Vec<(String, String)>>,
 }
 
 impl StructuredContent {
-    pub fn parse(value: &serde_json::Value) -> Result<StructuredContent, String> {
+    pub fn parse(value: &serde_json::Value) -> Result<StructuredContent, SchemaError> {
         if let Some(text) = value.as_str() {
             return Ok(StructuredContent::Text(text.into()));
         }
@@ -84,72 +96,255 @@ impl StructuredContent {
             ));
         }
         
-        let obj = value.as_object().ok_or("Invalid structured content")?;
-        let tag = Self::parse_tag(obj.get("tag").and_then(|v| v.as_str()).ok_or("Missing tag")?)?;
+        let obj = value.as_object().ok_or(SchemaError::InvalidField("structured content"))?;
+        let tag = obj.get("tag").and_then(|v| v.as_str()).ok_or(SchemaError::MissingField("tag"))?;
         let content = obj.get("content")
             .map(|v| Self::parse(v).map(Box::new))
             .transpose()?;
-        let attrs = Self::parse_attributes(obj)?;
+        let attrs = Box::new(Self::parse_attributes(obj)?);
         
-        Ok(StructuredContent::Element { tag, content, attrs })
+        match Self::parse_tag(tag) {
+            Some(tag) => Ok(StructuredContent::Element { tag, content, attrs }),
+            None => Ok(StructuredContent::UnknownElement { tag: tag.to_string(), content, attrs }),
+        }
     }
     
-    fn parse_tag(tag: &str) -> Result<HtmlTag, String> {
+    fn parse_tag(tag: &str) -> Option<HtmlTag> {
         match tag {
-            "br" => Ok(HtmlTag::Br),
-            "ruby" => Ok(HtmlTag::Ruby),
-            "rt" => Ok(HtmlTag::Rt),
-            "rp" => Ok(HtmlTag::Rp),
-            "table" => Ok(HtmlTag::Table),
-            "thead" => Ok(HtmlTag::Thead),
-            "tbody" => Ok(HtmlTag::Tbody),
-            "tfoot" => Ok(HtmlTag::Tfoot),
-            "tr" => Ok(HtmlTag::Tr),
-            "td" => Ok(HtmlTag::Td),
-            "th" => Ok(HtmlTag::Th),
-            "span" => Ok(HtmlTag::Span),
-            "div" => Ok(HtmlTag::Div),
-            "ol" => Ok(HtmlTag::Ol),
-            "ul" => Ok(HtmlTag::Ul),
-            "li" => Ok(HtmlTag::Li),
-            "details" => Ok(HtmlTag::Details),
-            "summary" => Ok(HtmlTag::Summary),
-            "img" => Ok(HtmlTag::Img),
-            "a" => Ok(HtmlTag::A),
-            _ => Err(format!("Unknown tag: {}", tag))
+            "br" => Some(HtmlTag::Br),
+            "ruby" => Some(HtmlTag::Ruby),
+            "rt" => Some(HtmlTag::Rt),
+            "rp" => Some(HtmlTag::Rp),
+            "table" => Some(HtmlTag::Table),
+            "thead" => Some(HtmlTag::Thead),
+            "tbody" => Some(HtmlTag::Tbody),
+            "tfoot" => Some(HtmlTag::Tfoot),
+            "tr" => Some(HtmlTag::Tr),
+            "td" => Some(HtmlTag::Td),
+            "th" => Some(HtmlTag::Th),
+            "span" => Some(HtmlTag::Span),
+            "div" => Some(HtmlTag::Div),
+            "ol" => Some(HtmlTag::Ol),
+            "ul" => Some(HtmlTag::Ul),
+            "li" => Some(HtmlTag::Li),
+            "details" => Some(HtmlTag::Details),
+            "summary" => Some(HtmlTag::Summary),
+            "img" => Some(HtmlTag::Img),
+            "a" => Some(HtmlTag::A),
+            _ => None,
         }
     }
     
-    fn parse_attributes(obj: &serde_json::Map<String, serde_json::Value>) -> Result<Attributes, String> {
+    fn parse_attributes(obj: &serde_json::Map<String, serde_json::Value>) -> Result<Attributes, SchemaError> {
         let mut attrs = Attributes::default();
+        let mut extra = Vec::new();
         
         for (key, value) in obj.iter() {
             match key.as_str() {
                 "tag" | "content" => continue,
-                "lang" => attrs.lang = value.as_str().map(|s| s.into()),
-                "title" => attrs.title = value.as_str().map(String::from),
-                "href" => attrs.href = value.as_str().map(String::from),
-                "colSpan" => attrs.col_span = value.as_u64().map(|n| n as u16),
-                "rowSpan" => attrs.row_span = value.as_u64().map(|n| n as u16),
-                "open" => attrs.open = value.as_bool(),
-                "path" => attrs.path = value.as_str().map(String::from),
-                "width" => attrs.width = value.as_f64().map(|n| n as f32),
-                "height" => attrs.height = value.as_f64().map(|n| n as f32),
-                "alt" => attrs.alt = value.as_str().map(String::from),
-                "description" => attrs.description = value.as_str().map(String::from),
-                "data" => {
-                    if let Some(data_obj) = value.as_object() {
-                        attrs.data = Some(
-                            data_obj.iter()
-                                .filter_map(|(k, v)| v.as_str().map(|s| (String::from(k.as_str()), String::from(s))))
-                                .collect()
-                        );
+                "lang" => attrs.lang = keep_or_extra(value.as_str().map(String::from), key, value, &mut extra),
+                "title" => attrs.title = keep_or_extra(value.as_str().map(String::from), key, value, &mut extra),
+                "href" => attrs.href = keep_or_extra(value.as_str().map(String::from), key, value, &mut extra),
+                "colSpan" => attrs.col_span = keep_or_extra(as_u16(value), key, value, &mut extra),
+                "rowSpan" => attrs.row_span = keep_or_extra(as_u16(value), key, value, &mut extra),
+                "open" => attrs.open = keep_or_extra(value.as_bool(), key, value, &mut extra),
+                "path" => attrs.path = keep_or_extra(value.as_str().map(String::from), key, value, &mut extra),
+                "width" => attrs.width = keep_or_extra(value.as_f64().map(|n| n as f32), key, value, &mut extra),
+                "height" => attrs.height = keep_or_extra(value.as_f64().map(|n| n as f32), key, value, &mut extra),
+                "alt" => attrs.alt = keep_or_extra(value.as_str().map(String::from), key, value, &mut extra),
+                "description" => attrs.description = keep_or_extra(value.as_str().map(String::from), key, value, &mut extra),
+                "style" if let Some(style_obj) = value.as_object() => {
+                    let (style, unknown) = Style::parse(style_obj);
+                    attrs.style = style;
+                    if !unknown.is_empty() {
+                        extra.push((key.clone(), serde_json::Value::Object(unknown).to_string()));
+                    }
+                }
+                "data" if let Some(data_obj) = value.as_object() => {
+                    let mut data = Vec::new();
+                    let mut unknown = Map::new();
+                    for (k, v) in data_obj {
+                        match v.as_str() {
+                            Some(s) => data.push((k.clone(), String::from(s))),
+                            None => {
+                                unknown.insert(k.clone(), v.clone());
+                            }
+                        }
+                    }
+                    attrs.data = Some(data);
+                    if !unknown.is_empty() {
+                        extra.push((key.clone(), serde_json::Value::Object(unknown).to_string()));
                     }
                 }
-                _ => {}
+                _ => extra.push((key.clone(), value.to_string())),
             }
         }
         
+        if !extra.is_empty() {
+            attrs.extra = Some(extra);
+        }
         Ok(attrs)
     }
-}
\ No newline at end of file
+}
+
+/// Returns `parsed`, or keeps the attribute as is in `extra` when it does not
+/// have the expected type.
+fn keep_or_extra<T>(parsed: Option<T>, key: &str, value: &Value, extra: &mut Vec<(String, String)>) -> Option<T> {
+    if parsed.is_none() {
+        extra.push((key.to_string(), value.to_string()));
+    }
+    parsed
+}
+
+fn as_u16(value: &Value) -> Option<u16> {
+    value.as_u64().and_then(|n| u16::try_from(n).ok())
+}
+
+impl StructuredContent {
+    /// Writes the content back as yomitan structured content. Unknown tags
+    /// and attributes are written as they were read.
+    pub fn to_json(&self) -> Value {
+        let (tag, content, attrs) = match self {
+            StructuredContent::Text(text) => return Value::from(text.as_str()),
+            StructuredContent::Array(items) => return Value::Array(items.iter().map(Self::to_json).collect()),
+            StructuredContent::Element { tag, content, attrs } => (tag.name(), content, attrs),
+            StructuredContent::UnknownElement { tag, content, attrs } => (tag.as_str(), content, attrs),
+        };
+
+        let mut obj = Map::new();
+        obj.insert("tag".into(), Value::from(tag));
+        if let Some(content) = content {
+            obj.insert("content".into(), content.to_json());
+        }
+        attrs.write_json(&mut obj);
+        Value::Object(obj)
+    }
+}
+
+impl Attributes {
+    fn write_json(&self, obj: &mut Map<String, Value>) {
+        set_optional_str(obj, "lang", &self.lang);
+        set_optional_str(obj, "title", &self.title);
+        set_optional_str(obj, "href", &self.href);
+        if let Some(col_span) = self.col_span {
+            obj.insert("colSpan".into(), Value::from(col_span));
+        }
+        if let Some(row_span) = self.row_span {
+            obj.insert("rowSpan".into(), Value::from(row_span));
+        }
+        if let Some(open) = self.open {
+            obj.insert("open".into(), Value::from(open));
+        }
+        set_optional_str(obj, "path", &self.path);
+        if let Some(width) = self.width {
+            obj.insert("width".into(), f32_to_json(width));
+        }
+        if let Some(height) = self.height {
+            obj.insert("height".into(), f32_to_json(height));
+        }
+        set_optional_str(obj, "alt", &self.alt);
+        set_optional_str(obj, "description", &self.description);
+        let mut data: Option<Map<String, Value>> = self.data.as_ref().map(|data| {
+            data.iter()
+                .map(|(k, v)| (k.clone(), Value::from(v.as_str())))
+                .collect()
+        });
+
+        let mut style = self.style.as_ref().map(|style| style.to_json()).unwrap_or_default();
+        for (key, value) in self.extra.iter().flatten() {
+            let Ok(value) = serde_json::from_str::<Value>(value) else {
+                continue;
+            };
+
+            match value {
+                Value::Object(unknown) if key == "style" => style.extend(unknown),
+                Value::Object(unknown) if key == "data" => data.get_or_insert_default().extend(unknown),
+                value => {
+                    obj.insert(key.clone(), value);
+                }
+            }
+        }
+        if let Some(data) = data {
+            obj.insert("data".into(), Value::Object(data));
+        }
+        if !style.is_empty() {
+            obj.insert("style".into(), Value::Object(style));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schema::BINCODE_CONFIG;
+    use serde_json::json;
+
+    /// Parses, stores and writes back `value`, which must come out unchanged.
+    fn assert_round_trips(value: Value) -> StructuredContent {
+        let content = StructuredContent::parse(&value).unwrap();
+        assert_eq!(content.to_json(), value);
+
+        let encoded = bincode::encode_to_vec(&content, BINCODE_CONFIG).unwrap();
+        let (decoded, _): (StructuredContent, _) = bincode::decode_from_slice(&encoded, BINCODE_CONFIG).unwrap();
+        assert_eq!(decoded.to_json(), value);
+        content
+    }
+
+    #[test]
+    fn keeps_unknown_tags_with_their_children() {
+        let content = assert_round_trips(json!({
+            "tag": "marquee",
+            "behavior": "scroll",
+            "content": ["moving ", { "tag": "span", "lang": "ja", "content": "文字" }],
+        }));
+
+        let StructuredContent::UnknownElement { tag, content: Some(children), attrs } = content else {
+            panic!("not kept as an unknown element");
+        };
+        assert_eq!(tag, "marquee");
+        assert!(matches!(*children, StructuredContent::Array(ref items) if items.len() == 2));
+        assert_eq!(attrs.extra, Some(vec![("behavior".into(), "\"scroll\"".into())]));
+    }
+
+    #[test]
+    fn keeps_unknown_and_mistyped_attributes() {
+        let content = assert_round_trips(json!({
+            "tag": "td",
+            "colSpan": "2",
+            "rowSpan": 3,
+            "onclick": "x()",
+            "content": "c",
+        }));
+
+        let StructuredContent::Element { attrs, .. } = content else {
+            panic!("not parsed as an element");
+        };
+        assert_eq!(attrs.col_span, None);
+        assert_eq!(attrs.row_span, Some(3));
+        let mut extra = attrs.extra.unwrap();
+        extra.sort();
+        assert_eq!(extra, [("colSpan".into(), "\"2\"".into()), ("onclick".into(), "\"x()\"".into())]);
+    }
+
+    #[test]
+    fn keeps_unknown_style_and_data_keys() {
+        let content = assert_round_trips(json!({
+            "tag": "span",
+            "style": { "fontWeight": "bold", "textGlow": "1px red" },
+            "data": { "code": "n", "count": 2 },
+            "content": "x",
+        }));
+
+        let StructuredContent::Element { attrs, .. } = content else {
+            panic!("not parsed as an element");
+        };
+        assert!(attrs.style.is_some());
+        assert_eq!(attrs.data, Some(vec![("code".into(), "n".into())]));
+        let mut extra = attrs.extra.unwrap();
+        extra.sort();
+        assert_eq!(extra, [
+            ("data".into(), json!({ "count": 2 }).to_string()),
+            ("style".into(), json!({ "textGlow": "1px red" }).to_string()),
+        ]);
+    }
+}
@@ -0,0 +1,430 @@
+//! The `style` object of structured content elements.
+
+use bincode::{Encode, Decode};
+use bincode::enc::Encoder;
+use bincode::de::Decoder;
+use bincode::error::{EncodeError, DecodeError};
+use std::borrow::Cow;
+
+/// Value of a CSS property, as written in a style attribute.
+pub trait CssValue {
+    fn css_value(&self) -> Cow<'_, str>;
+}
+
+impl CssValue for String {
+    fn css_value(&self) -> Cow<'_, str> {
+        Cow::Borrowed(self)
+    }
+}
+
+macro_rules! css_keywords {
+    ($ty:ident { $($variant:ident => $css:literal,)* }) => {
+        impl $ty {
+            pub fn from_css(value: &str) -> Option<Self> {
+                match value {
+                    $($css => Some($ty::$variant),)*
+                    _ => None,
+                }
+            }
+
+            pub fn as_css(self) -> &'static str {
+                match self {
+                    $($ty::$variant => $css,)*
+                }
+            }
+        }
+
+        impl CssValue for $ty {
+            fn css_value(&self) -> Cow<'_, str> {
+                Cow::Borrowed(self.as_css())
+            }
+        }
+    };
+}
+
+#[derive(Encode, Decode, Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum FontStyle {
+    Normal = 0,
+    Italic = 1,
+}
+
+css_keywords!(FontStyle {
+    Normal => "normal",
+    Italic => "italic",
+});
+
+#[derive(Encode, Decode, Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum FontWeight {
+    Normal = 0,
+    Bold = 1,
+}
+
+css_keywords!(FontWeight {
+    Normal => "normal",
+    Bold => "bold",
+});
+
+#[derive(Encode, Decode, Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum VerticalAlign {
+    Baseline = 0,
+    Sub = 1,
+    Super = 2,
+    TextTop = 3,
+    TextBottom = 4,
+    Middle = 5,
+    Top = 6,
+    Bottom = 7,
+}
+
+css_keywords!(VerticalAlign {
+    Baseline => "baseline",
+    Sub => "sub",
+    Super => "super",
+    TextTop => "text-top",
+    TextBottom => "text-bottom",
+    Middle => "middle",
+    Top => "top",
+    Bottom => "bottom",
+});
+
+#[derive(Encode, Decode, Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum TextAlign {
+    Start = 0,
+    End = 1,
+    Left = 2,
+    Right = 3,
+    Center = 4,
+    Justify = 5,
+    JustifyAll = 6,
+    MatchParent = 7,
+}
+
+css_keywords!(TextAlign {
+    Start => "start",
+    End => "end",
+    Left => "left",
+    Right => "right",
+    Center => "center",
+    Justify => "justify",
+    JustifyAll => "justify-all",
+    MatchParent => "match-parent",
+});
+
+#[derive(Encode, Decode, Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum TextDecorationLine {
+    None = 0,
+    Underline = 1,
+    Overline = 2,
+    LineThrough = 3,
+}
+
+css_keywords!(TextDecorationLine {
+    None => "none",
+    Underline => "underline",
+    Overline => "overline",
+    LineThrough => "line-through",
+});
+
+#[derive(Encode, Decode, Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum TextDecorationStyle {
+    Solid = 0,
+    Double = 1,
+    Dotted = 2,
+    Dashed = 3,
+    Wavy = 4,
+}
+
+css_keywords!(TextDecorationStyle {
+    Solid => "solid",
+    Double => "double",
+    Dotted => "dotted",
+    Dashed => "dashed",
+    Wavy => "wavy",
+});
+
+#[derive(Encode, Decode, Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum WordBreak {
+    Normal = 0,
+    BreakAll = 1,
+    KeepAll = 2,
+}
+
+css_keywords!(WordBreak {
+    Normal => "normal",
+    BreakAll => "break-all",
+    KeepAll => "keep-all",
+});
+
+impl CssValue for Vec<TextDecorationLine> {
+    fn css_value(&self) -> Cow<'_, str> {
+        let values: Vec<&str> = self.iter().map(|line| line.as_css()).collect();
+        Cow::Owned(values.join(" "))
+    }
+}
+
+macro_rules! style_properties {
+    ($($field:ident: $ty:ty => $css:literal,)*) => {
+        /// CSS properties of a structured content element.
+        ///
+        /// Most elements only set one or two properties, so only the ones
+        /// that are set are encoded, after a bit mask of which ones they are.
+        #[derive(Debug, Clone, Default, PartialEq)]
+        pub struct Style {
+            $(pub $field: Option<$ty>,)*
+        }
+
+        impl Style {
+            /// Every property that is set, as CSS property name and value.
+            pub fn declarations(&self) -> Vec<(&'static str, Cow<'_, str>)> {
+                let mut declarations = Vec::new();
+                $(
+                    if let Some(value) = &self.$field {
+                        declarations.push(($css, value.css_value()));
+                    }
+                )*
+                declarations
+            }
+        }
+
+        impl Encode for Style {
+            #[allow(unused_assignments)]
+            fn encode<E: Encoder>(&self, encoder: &mut E) -> Result<(), EncodeError> {
+                let mut mask: u64 = 0;
+                let mut bit: u64 = 1;
+                $(
+                    if self.$field.is_some() {
+                        mask |= bit;
+                    }
+                    bit <<= 1;
+                )*
+
+                mask.encode(encoder)?;
+                $(
+                    if let Some(value) = &self.$field {
+                        value.encode(encoder)?;
+                    }
+                )*
+                Ok(())
+            }
+        }
+
+        impl<Context> Decode<Context> for Style {
+            #[allow(unused_assignments)]
+            fn decode<D: Decoder<Context = Context>>(decoder: &mut D) -> Result<Self, DecodeError> {
+                let mask = u64::decode(decoder)?;
+                let mut style = Style::default();
+                let mut bit: u64 = 1;
+                $(
+                    if mask & bit != 0 {
+                        style.$field = Some(<$ty>::decode(decoder)?);
+                    }
+                    bit <<= 1;
+                )*
+                Ok(style)
+            }
+        }
+
+        bincode::impl_borrow_decode!(Style);
+    };
+}
+
+style_properties! {
+    font_style: FontStyle => "font-style",
+    font_weight: FontWeight => "font-weight",
+    font_size: String => "font-size",
+    color: String => "color",
+    background: String => "background",
+    background_color: String => "background-color",
+    text_decoration_line: Vec<TextDecorationLine> => "text-decoration-line",
+    text_decoration_style: TextDecorationStyle => "text-decoration-style",
+    text_decoration_color: String => "text-decoration-color",
+    border_color: String => "border-color",
+    border_style: String => "border-style",
+    border_radius: String => "border-radius",
+    border_width: String => "border-width",
+    clip_path: String => "clip-path",
+    vertical_align: VerticalAlign => "vertical-align",
+    text_align: TextAlign => "text-align",
+    text_emphasis: String => "text-emphasis",
+    text_shadow: String => "text-shadow",
+    margin: String => "margin",
+    margin_top: String => "margin-top",
+    margin_left: String => "margin-left",
+    margin_right: String => "margin-right",
+    margin_bottom: String => "margin-bottom",
+    padding: String => "padding",
+    padding_top: String => "padding-top",
+    padding_left: String => "padding-left",
+    padding_right: String => "padding-right",
+    padding_bottom: String => "padding-bottom",
+    word_break: WordBreak => "word-break",
+    white_space: String => "white-space",
+    cursor: String => "cursor",
+    list_style_type: String => "list-style-type",
+}
+
+impl Style {
+    /// Parses a yomitan style object. Returns `None` for the style if nothing
+    /// was set, along with the properties that were not understood (unknown
+    /// names or invalid values), so they can be kept as they were.
+    pub fn parse(obj: &serde_json::Map<String, serde_json::Value>) -> (Option<Box<Style>>, serde_json::Map<String, serde_json::Value>) {
+        let mut style = Style::default();
+        let mut unknown = serde_json::Map::new();
+
+        for (key, value) in obj {
+            let text = || value.as_str().map(String::from);
+            let known = match key.as_str() {
+                "fontStyle" => set(&mut style.font_style, value.as_str().and_then(FontStyle::from_css)),
+                "fontWeight" => set(&mut style.font_weight, value.as_str().and_then(FontWeight::from_css)),
+                "fontSize" => set(&mut style.font_size, text()),
+                "color" => set(&mut style.color, text()),
+                "background" => set(&mut style.background, text()),
+                "backgroundColor" => set(&mut style.background_color, text()),
+                "textDecorationLine" => set(&mut style.text_decoration_line, parse_decoration_lines(value)),
+                "textDecorationStyle" => {
+                    set(&mut style.text_decoration_style, value.as_str().and_then(TextDecorationStyle::from_css))
+                }
+                "textDecorationColor" => set(&mut style.text_decoration_color, text()),
+                "borderColor" => set(&mut style.border_color, text()),
+                "borderStyle" => set(&mut style.border_style, text()),
+                "borderRadius" => set(&mut style.border_radius, text()),
+                "borderWidth" => set(&mut style.border_width, text()),
+                "clipPath" => set(&mut style.clip_path, text()),
+                "verticalAlign" => set(&mut style.vertical_align, value.as_str().and_then(VerticalAlign::from_css)),
+                "textAlign" => set(&mut style.text_align, value.as_str().and_then(TextAlign::from_css)),
+                "textEmphasis" => set(&mut style.text_emphasis, text()),
+                "textShadow" => set(&mut style.text_shadow, text()),
+                "margin" => set(&mut style.margin, text()),
+                "marginTop" => set(&mut style.margin_top, parse_margin(value)),
+                "marginLeft" => set(&mut style.margin_left, parse_margin(value)),
+                "marginRight" => set(&mut style.margin_right, parse_margin(value)),
+                "marginBottom" => set(&mut style.margin_bottom, parse_margin(value)),
+                "padding" => set(&mut style.padding, text()),
+                "paddingTop" => set(&mut style.padding_top, text()),
+                "paddingLeft" => set(&mut style.padding_left, text()),
+                "paddingRight" => set(&mut style.padding_right, text()),
+                "paddingBottom" => set(&mut style.padding_bottom, text()),
+                "wordBreak" => set(&mut style.word_break, value.as_str().and_then(WordBreak::from_css)),
+                "whiteSpace" => set(&mut style.white_space, text()),
+                "cursor" => set(&mut style.cursor, text()),
+                "listStyleType" => set(&mut style.list_style_type, text()),
+                _ => false,
+            };
+
+            if !known {
+                unknown.insert(key.clone(), value.clone());
+            }
+        }
+
+        let style = (style != Style::default()).then(|| Box::new(style));
+        (style, unknown)
+    }
+
+    /// Writes the style back as a yomitan style object.
+    pub fn to_json(&self) -> serde_json::Map<String, serde_json::Value> {
+        self.declarations()
+            .into_iter()
+            .map(|(property, value)| (camel_case(property), serde_json::Value::from(value.into_owned())))
+            .collect()
+    }
+}
+
+/// `font-weight` to `fontWeight`.
+fn camel_case(property: &str) -> String {
+    let mut result = String::with_capacity(property.len());
+    let mut upper = false;
+    for c in property.chars() {
+        if c == '-' {
+            upper = true;
+        } else if upper {
+            result.push(c.to_ascii_uppercase());
+            upper = false;
+        } else {
+            result.push(c);
+        }
+    }
+    result
+}
+
+/// Stores a parsed property, returns false if the value was not valid.
+fn set<T>(field: &mut Option<T>, value: Option<T>) -> bool {
+    let valid = value.is_some();
+    *field = value;
+    valid
+}
+
+/// Margins can be CSS lengths, or plain numbers which yomitan treats as `em`.
+fn parse_margin(value: &serde_json::Value) -> Option<String> {
+    match value {
+        serde_json::Value::String(s) => Some(s.clone()),
+        serde_json::Value::Number(n) => Some(format!("{}em", n)),
+        _ => None,
+    }
+}
+
+fn parse_decoration_lines(value: &serde_json::Value) -> Option<Vec<TextDecorationLine>> {
+    let lines: Vec<TextDecorationLine> = match value {
+        serde_json::Value::String(s) => s.split_whitespace()
+            .filter_map(TextDecorationLine::from_css)
+            .collect(),
+        serde_json::Value::Array(values) => values.iter()
+            .filter_map(|v| v.as_str().and_then(TextDecorationLine::from_css))
+            .collect(),
+        _ => Vec::new(),
+    };
+
+    (!lines.is_empty()).then_some(lines)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schema::BINCODE_CONFIG;
+    use serde_json::json;
+
+    fn round_trip(style: &Style) -> Style {
+        let encoded = bincode::encode_to_vec(style, BINCODE_CONFIG).unwrap();
+        let (decoded, read) = bincode::decode_from_slice::<Style, _>(&encoded, BINCODE_CONFIG).unwrap();
+        assert_eq!(read, encoded.len());
+        decoded
+    }
+
+    #[test]
+    fn encodes_only_set_properties() {
+        assert_eq!(round_trip(&Style::default()), Style::default());
+
+        let first_and_last = Style {
+            font_style: Some(FontStyle::Italic),
+            list_style_type: Some("circle".into()),
+            ..Style::default()
+        };
+        assert_eq!(round_trip(&first_and_last), first_and_last);
+
+        let obj = json!({
+            "fontWeight": "bold",
+            "textDecorationLine": ["underline", "line-through"],
+            "marginTop": 0.5,
+            "wordBreak": "keep-all",
+            "color": "red",
+        });
+        let (style, unknown) = Style::parse(obj.as_object().unwrap());
+        let style = style.unwrap();
+        assert!(unknown.is_empty());
+        assert_eq!(round_trip(&style), *style);
+    }
+
+    #[test]
+    fn parse_keeps_unknown_properties() {
+        let obj = json!({ "fontWeight": "heavy", "madeUp": 1, "color": "blue" });
+        let (style, unknown) = Style::parse(obj.as_object().unwrap());
+        assert_eq!(style.unwrap().color.as_deref(), Some("blue"));
+        assert_eq!(unknown, *json!({ "fontWeight": "heavy", "madeUp": 1 }).as_object().unwrap());
+    }
+}
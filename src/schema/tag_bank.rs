@@ -1,5 +1,6 @@
 use bincode::{Encode, Decode};
-use crate::schema::{JsonParseable, get_str, get_f32};
+use crate::schema::{JsonParseable, JsonSerializable, get_str, get_f32, f32_to_json};
+use crate::error::SchemaError;
 
 #[derive(Encode, Decode, Debug, Clone)]
 pub struct Tag {
@@ -11,9 +12,9 @@ pub struct Tag {
 }
 
 impl JsonParseable for Tag {
-    fn from_json_array(arr: &[serde_json::Value]) -> Result<Self, String> {
+    fn from_json_array(arr: &[serde_json::Value]) -> Result<Self, SchemaError> {
         if arr.len() != 5 {
-            return Err("Tag array must have exactly 5 elements".to_string());
+            return Err(SchemaError::WrongLength { kind: "Tag", expected: 5, found: arr.len() });
         }
         
         Ok(Tag {
@@ -24,4 +25,16 @@ impl JsonParseable for Tag {
             score: get_f32(&arr[4], "score")?,
         })
     }
-}
\ No newline at end of file
+}
+
+impl JsonSerializable for Tag {
+    fn to_json_array(&self) -> serde_json::Value {
+        serde_json::json!([
+            self.name,
+            self.category,
+            f32_to_json(self.order),
+            self.notes,
+            f32_to_json(self.score),
+        ])
+    }
+}
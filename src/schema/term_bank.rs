@@ -1,6 +1,7 @@
 use bincode::{Encode, Decode};
-use crate::schema::{JsonParseable, get_str, get_str_or_default, get_f32, get_i32};
+use crate::schema::{JsonParseable, JsonSerializable, get_str, get_str_or_default, get_f32, get_i32, f32_to_json, set_optional_str};
 use crate::schema::structured_content::StructuredContent;
+use crate::error::SchemaError;
 
 #[derive(Encode, Decode, Debug, Clone)]
 pub struct Term {
@@ -36,48 +37,47 @@ pub enum Definition {
 }
 
 impl Term {
-    fn parse_definitions(value: &serde_json::Value) -> Result<Vec<Definition>, String> {
+    fn parse_definitions(value: &serde_json::Value) -> Result<Vec<Definition>, SchemaError> {
         value.as_array()
-            .ok_or("Definitions must be an array")?
+            .ok_or(SchemaError::ExpectedArray("definitions"))?
             .iter()
             .map(Self::parse_definition)
             .collect()
     }
     
-    pub fn parse_definition(value: &serde_json::Value) -> Result<Definition, String> {
+    pub fn parse_definition(value: &serde_json::Value) -> Result<Definition, SchemaError> {
         if let Some(text) = value.as_str() {
             return Ok(Definition::Text(text.to_string()));
         }
         
-        if let Some(arr) = value.as_array() {
-            if arr.len() == 2 {
-                if let (Some(uninflected), Some(rules_arr)) = (arr[0].as_str(), arr[1].as_array()) {
-                    let rules = rules_arr.iter()
-                        .filter_map(|v| v.as_str().map(String::from))
-                        .collect();
-                    return Ok(Definition::Deinflection { 
-                        uninflected: uninflected.to_string(), 
-                        rules 
-                    });
-                }
-            }
+        if let Some(arr) = value.as_array()
+            && arr.len() == 2
+            && let (Some(uninflected), Some(rules_arr)) = (arr[0].as_str(), arr[1].as_array())
+        {
+            let rules = rules_arr.iter()
+                .filter_map(|v| v.as_str().map(String::from))
+                .collect();
+            return Ok(Definition::Deinflection { 
+                uninflected: uninflected.to_string(), 
+                rules 
+            });
         }
         
-        let obj = value.as_object().ok_or("Invalid definition format")?;
-        let def_type = obj.get("type").and_then(|v| v.as_str()).ok_or("Missing type")?;
+        let obj = value.as_object().ok_or(SchemaError::InvalidField("definition"))?;
+        let def_type = obj.get("type").and_then(|v| v.as_str()).ok_or(SchemaError::MissingField("type"))?;
         
         match def_type {
             "text" => {
-                let text = obj.get("text").and_then(|v| v.as_str()).ok_or("Missing text")?;
+                let text = obj.get("text").and_then(|v| v.as_str()).ok_or(SchemaError::MissingField("text"))?;
                 Ok(Definition::Text(text.to_string()))
             }
             "structured-content" => {
-                let content = obj.get("content").ok_or("Missing content")?;
+                let content = obj.get("content").ok_or(SchemaError::MissingField("content"))?;
                 Ok(Definition::StructuredContent(StructuredContent::parse(content)?))
             }
             "image" => {
                 Ok(Definition::Image {
-                    path: obj.get("path").and_then(|v| v.as_str()).ok_or("Missing path")?.to_string(),
+                    path: obj.get("path").and_then(|v| v.as_str()).ok_or(SchemaError::MissingField("path"))?.to_string(),
                     width: obj.get("width").and_then(|v| v.as_u64()).map(|v| v as u16),
                     height: obj.get("height").and_then(|v| v.as_u64()).map(|v| v as u16),
                     title: obj.get("title").and_then(|v| v.as_str()).map(String::from),
@@ -88,15 +88,15 @@ impl Term {
                     background: obj.get("background").and_then(|v| v.as_bool()).unwrap_or(true),
                 })
             }
-            _ => Err(format!("Unknown definition type: {}", def_type))
+            _ => Err(SchemaError::UnknownDefinitionType(def_type.to_string()))
         }
     }
 }
 
 impl JsonParseable for Term {
-    fn from_json_array(arr: &[serde_json::Value]) -> Result<Self, String> {
+    fn from_json_array(arr: &[serde_json::Value]) -> Result<Self, SchemaError> {
         if arr.len() != 8 {
-            return Err("Term array must have exactly 8 elements".to_string());
+            return Err(SchemaError::WrongLength { kind: "Term", expected: 8, found: arr.len() });
         }
         
         Ok(Term {
@@ -110,4 +110,52 @@ impl JsonParseable for Term {
             term_tags: get_str(&arr[7], "term tags")?,
         })
     }
-}
\ No newline at end of file
+}
+
+impl Definition {
+    /// Writes the definition back in the form yomitan dictionaries use.
+    pub fn to_json(&self) -> serde_json::Value {
+        match self {
+            Definition::Text(text) => serde_json::Value::from(text.as_str()),
+            Definition::StructuredContent(content) => {
+                serde_json::json!({ "type": "structured-content", "content": content.to_json() })
+            }
+            Definition::Image { path, width, height, title, alt, description, pixelated, monochrome, background } => {
+                let mut obj = serde_json::Map::new();
+                obj.insert("type".into(), "image".into());
+                obj.insert("path".into(), path.as_str().into());
+                if let Some(width) = width {
+                    obj.insert("width".into(), (*width).into());
+                }
+                if let Some(height) = height {
+                    obj.insert("height".into(), (*height).into());
+                }
+                set_optional_str(&mut obj, "title", title);
+                set_optional_str(&mut obj, "alt", alt);
+                set_optional_str(&mut obj, "description", description);
+                obj.insert("pixelated".into(), (*pixelated).into());
+                obj.insert("appearance".into(), if *monochrome { "monochrome" } else { "auto" }.into());
+                obj.insert("background".into(), (*background).into());
+                serde_json::Value::Object(obj)
+            }
+            Definition::Deinflection { uninflected, rules } => serde_json::json!([uninflected, rules]),
+        }
+    }
+}
+
+impl JsonSerializable for Term {
+    fn to_json_array(&self) -> serde_json::Value {
+        let definitions: Vec<serde_json::Value> = self.definitions.iter().map(Definition::to_json).collect();
+
+        serde_json::json!([
+            self.term,
+            self.reading,
+            self.definition_tags,
+            self.rules,
+            f32_to_json(self.score),
+            definitions,
+            self.sequence,
+            self.term_tags,
+        ])
+    }
+}
@@ -1,5 +1,7 @@
 use bincode::{Encode, Decode};
-use crate::schema::{JsonParseable, get_str, parse_string_array, parse_single_or_multiple};
+use crate::schema::{JsonParseable, JsonSerializable, get_str, parse_string_array, parse_single_or_multiple, f32_to_json};
+use serde_json::{Map, Value};
+use crate::error::SchemaError;
 
 #[derive(Encode, Decode, Debug, Clone)]
 pub struct TermMeta {
@@ -92,22 +94,22 @@ pub struct IpaTranscription {
 }
 
 impl TermMeta {
-    fn parse_frequency_data(value: &serde_json::Value) -> Result<FrequencyData, String> {
-        if let Some(obj) = value.as_object() {
-            if let (Some(reading), Some(freq)) = (obj.get("reading"), obj.get("frequency")) {
-                return Ok(FrequencyData::WithReading {
-                    reading: reading.as_str().ok_or("Invalid reading")?.to_string(),
-                    frequency: Self::parse_frequency(freq)?,
-                });
-            }
+    fn parse_frequency_data(value: &serde_json::Value) -> Result<FrequencyData, SchemaError> {
+        if let Some(obj) = value.as_object()
+            && let (Some(reading), Some(freq)) = (obj.get("reading"), obj.get("frequency"))
+        {
+            return Ok(FrequencyData::WithReading {
+                reading: reading.as_str().ok_or(SchemaError::InvalidField("reading"))?.to_string(),
+                frequency: Self::parse_frequency(freq)?,
+            });
         }
         Ok(FrequencyData::Simple(Self::parse_frequency(value)?))
     }
     
-    fn parse_frequency(value: &serde_json::Value) -> Result<Frequency, String> {
+    fn parse_frequency(value: &serde_json::Value) -> Result<Frequency, SchemaError> {
         if let Some(obj) = value.as_object() {
             return Ok(Frequency::Detailed {
-                value: obj.get("value").and_then(|v| v.as_f64()).ok_or("Missing frequency value")? as f32,
+                value: obj.get("value").and_then(|v| v.as_f64()).ok_or(SchemaError::MissingField("frequency value"))? as f32,
                 display_value: obj.get("displayValue").and_then(|v| v.as_str()).map(String::from),
             });
         }
@@ -120,35 +122,35 @@ impl TermMeta {
             return Ok(Frequency::Simple(FrequencyValue::String(s.to_string())));
         }
         
-        Err("Invalid frequency format".to_string())
+        Err(SchemaError::InvalidField("frequency"))
     }
     
-    fn parse_pitch_data(value: &serde_json::Value) -> Result<PitchData, String> {
-        let obj = value.as_object().ok_or("Pitch data must be an object")?;
+    fn parse_pitch_data(value: &serde_json::Value) -> Result<PitchData, SchemaError> {
+        let obj = value.as_object().ok_or(SchemaError::ExpectedObject("pitch data"))?;
         
         Ok(PitchData {
-            reading: obj.get("reading").and_then(|v| v.as_str()).ok_or("Missing reading")?.to_string(),
+            reading: obj.get("reading").and_then(|v| v.as_str()).ok_or(SchemaError::MissingField("reading"))?.to_string(),
             pitches: obj.get("pitches")
                 .and_then(|v| v.as_array())
-                .ok_or("Missing pitches array")?
+                .ok_or(SchemaError::MissingField("pitches"))?
                 .iter()
                 .map(Self::parse_pitch_accent)
                 .collect::<Result<Vec<_>, _>>()?,
         })
     }
     
-    fn parse_pitch_accent(value: &serde_json::Value) -> Result<PitchAccent, String> {
-        let obj = value.as_object().ok_or("Pitch accent must be an object")?;
+    fn parse_pitch_accent(value: &serde_json::Value) -> Result<PitchAccent, SchemaError> {
+        let obj = value.as_object().ok_or(SchemaError::ExpectedObject("pitch accent"))?;
         
         Ok(PitchAccent {
-            position: Self::parse_pitch_position(obj.get("position").ok_or("Missing position")?)?,
+            position: Self::parse_pitch_position(obj.get("position").ok_or(SchemaError::MissingField("position"))?)?,
             nasal: obj.get("nasal").map(Self::parse_nasal_positions).transpose()?,
             devoice: obj.get("devoice").map(Self::parse_devoice_positions).transpose()?,
             tags: obj.get("tags").map(parse_string_array).unwrap_or_default(),
         })
     }
     
-    fn parse_pitch_position(value: &serde_json::Value) -> Result<PitchPosition, String> {
+    fn parse_pitch_position(value: &serde_json::Value) -> Result<PitchPosition, SchemaError> {
         if let Some(num) = value.as_u64() {
             return Ok(PitchPosition::Numeric(num as u32));
         }
@@ -157,45 +159,45 @@ impl TermMeta {
             return Ok(PitchPosition::Pattern(s.to_string()));
         }
         
-        Err("Invalid pitch position".to_string())
+        Err(SchemaError::InvalidField("pitch position"))
     }
     
-    fn parse_nasal_positions(value: &serde_json::Value) -> Result<NasalPositions, String> {
+    fn parse_nasal_positions(value: &serde_json::Value) -> Result<NasalPositions, SchemaError> {
         parse_single_or_multiple(value, NasalPositions::Single, NasalPositions::Multiple)
     }
     
-    fn parse_devoice_positions(value: &serde_json::Value) -> Result<DevoicePositions, String> {
+    fn parse_devoice_positions(value: &serde_json::Value) -> Result<DevoicePositions, SchemaError> {
         parse_single_or_multiple(value, DevoicePositions::Single, DevoicePositions::Multiple)
     }
     
-    fn parse_ipa_data(value: &serde_json::Value) -> Result<IpaData, String> {
-        let obj = value.as_object().ok_or("IPA data must be an object")?;
+    fn parse_ipa_data(value: &serde_json::Value) -> Result<IpaData, SchemaError> {
+        let obj = value.as_object().ok_or(SchemaError::ExpectedObject("IPA data"))?;
         
         Ok(IpaData {
-            reading: obj.get("reading").and_then(|v| v.as_str()).ok_or("Missing reading")?.to_string(),
+            reading: obj.get("reading").and_then(|v| v.as_str()).ok_or(SchemaError::MissingField("reading"))?.to_string(),
             transcriptions: obj.get("transcriptions")
                 .and_then(|v| v.as_array())
-                .ok_or("Missing transcriptions array")?
+                .ok_or(SchemaError::MissingField("transcriptions"))?
                 .iter()
                 .map(Self::parse_ipa_transcription)
                 .collect::<Result<Vec<_>, _>>()?,
         })
     }
     
-    fn parse_ipa_transcription(value: &serde_json::Value) -> Result<IpaTranscription, String> {
-        let obj = value.as_object().ok_or("IPA transcription must be an object")?;
+    fn parse_ipa_transcription(value: &serde_json::Value) -> Result<IpaTranscription, SchemaError> {
+        let obj = value.as_object().ok_or(SchemaError::ExpectedObject("IPA transcription"))?;
         
         Ok(IpaTranscription {
-            ipa: obj.get("ipa").and_then(|v| v.as_str()).ok_or("Missing ipa")?.to_string(),
+            ipa: obj.get("ipa").and_then(|v| v.as_str()).ok_or(SchemaError::MissingField("ipa"))?.to_string(),
             tags: obj.get("tags").map(parse_string_array).unwrap_or_default(),
         })
     }
 }
 
 impl JsonParseable for TermMeta {
-    fn from_json_array(arr: &[serde_json::Value]) -> Result<Self, String> {
+    fn from_json_array(arr: &[serde_json::Value]) -> Result<Self, SchemaError> {
         if arr.len() != 3 {
-            return Err("Term meta array must have exactly 3 elements".to_string());
+            return Err(SchemaError::WrongLength { kind: "Term meta", expected: 3, found: arr.len() });
         }
         
         let term = get_str(&arr[0], "term")?;
@@ -214,9 +216,92 @@ impl JsonParseable for TermMeta {
                 let ipa_data = Self::parse_ipa_data(&arr[2])?;
                 (TermMetaMode::Ipa, TermMetaData::Ipa(ipa_data))
             }
-            _ => return Err(format!("Unknown mode: {}", mode_str)),
+            _ => return Err(SchemaError::UnknownMode(mode_str)),
         };
         
         Ok(TermMeta { term, mode, data })
     }
-}
\ No newline at end of file
+}
+
+impl TermMetaMode {
+    pub fn name(self) -> &'static str {
+        match self {
+            TermMetaMode::Freq => "freq",
+            TermMetaMode::Pitch => "pitch",
+            TermMetaMode::Ipa => "ipa",
+        }
+    }
+}
+
+impl Frequency {
+    pub(crate) fn to_json(&self) -> Value {
+        match self {
+            Frequency::Simple(FrequencyValue::Number(n)) => f32_to_json(*n),
+            Frequency::Simple(FrequencyValue::String(s)) => Value::from(s.as_str()),
+            Frequency::Detailed { value, display_value } => {
+                let mut obj = Map::new();
+                obj.insert("value".into(), f32_to_json(*value));
+                if let Some(display_value) = display_value {
+                    obj.insert("displayValue".into(), Value::from(display_value.as_str()));
+                }
+                Value::Object(obj)
+            }
+        }
+    }
+}
+
+impl TermMetaData {
+    fn to_json(&self) -> Value {
+        match self {
+            TermMetaData::Frequency(FrequencyData::Simple(frequency)) => frequency.to_json(),
+            TermMetaData::Frequency(FrequencyData::WithReading { reading, frequency }) => {
+                serde_json::json!({ "reading": reading, "frequency": frequency.to_json() })
+            }
+            TermMetaData::Pitch(pitch) => {
+                let pitches: Vec<Value> = pitch.pitches.iter().map(|accent| {
+                    let mut obj = Map::new();
+                    obj.insert("position".into(), match &accent.position {
+                        PitchPosition::Numeric(n) => Value::from(*n),
+                        PitchPosition::Pattern(s) => Value::from(s.as_str()),
+                    });
+                    if let Some(nasal) = &accent.nasal {
+                        obj.insert("nasal".into(), match nasal {
+                            NasalPositions::Single(n) => Value::from(*n),
+                            NasalPositions::Multiple(positions) => Value::from(positions.clone()),
+                        });
+                    }
+                    if let Some(devoice) = &accent.devoice {
+                        obj.insert("devoice".into(), match devoice {
+                            DevoicePositions::Single(n) => Value::from(*n),
+                            DevoicePositions::Multiple(positions) => Value::from(positions.clone()),
+                        });
+                    }
+                    if !accent.tags.is_empty() {
+                        obj.insert("tags".into(), Value::from(accent.tags.clone()));
+                    }
+                    Value::Object(obj)
+                }).collect();
+
+                serde_json::json!({ "reading": pitch.reading, "pitches": pitches })
+            }
+            TermMetaData::Ipa(ipa) => {
+                let transcriptions: Vec<Value> = ipa.transcriptions.iter().map(|transcription| {
+                    let mut obj = Map::new();
+                    obj.insert("ipa".into(), Value::from(transcription.ipa.as_str()));
+                    if !transcription.tags.is_empty() {
+                        obj.insert("tags".into(), Value::from(transcription.tags.clone()));
+                    }
+                    Value::Object(obj)
+                }).collect();
+
+                serde_json::json!({ "reading": ipa.reading, "transcriptions": transcriptions })
+            }
+        }
+    }
+}
+
+impl JsonSerializable for TermMeta {
+    fn to_json_array(&self) -> Value {
+        serde_json::json!([self.term, self.mode.name(), self.data.to_json()])
+    }
+}